@@ -0,0 +1,12 @@
+-- Audit trail for admin and moderation actions
+CREATE TABLE IF NOT EXISTS LEX_AUDITLOG (
+    AUDITID INT NOT NULL AUTO_INCREMENT,
+    USRID INT NOT NULL,
+    ACTION VARCHAR(64) NOT NULL,
+    TARGETTYPE VARCHAR(16) NOT NULL,
+    TARGETID INT NOT NULL,
+    DETAILS TEXT,
+    DATEON CHAR(14) NOT NULL,
+    PRIMARY KEY (AUDITID),
+    KEY IDX_AUDIT_TARGET (TARGETTYPE, TARGETID)
+);
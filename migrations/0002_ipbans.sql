@@ -0,0 +1,11 @@
+-- IP bans: exact addresses, CIDR ranges (IPv4 and IPv6) or legacy LIKE patterns
+CREATE TABLE IF NOT EXISTS LEX_IPBANS (
+    BANID INT NOT NULL AUTO_INCREMENT,
+    IPADDR VARCHAR(64) NOT NULL,
+    PRIMARY KEY (BANID)
+);
+
+ALTER TABLE LEX_IPBANS
+    ADD COLUMN REASON VARCHAR(255) NULL,
+    ADD COLUMN DATEON CHAR(14) NULL,
+    ADD COLUMN EXPIRES CHAR(14) NULL;
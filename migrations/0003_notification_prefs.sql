@@ -0,0 +1,9 @@
+-- Email digest preferences, FREQUENCY is 'D'aily, 'W'eekly or 'N'ever
+CREATE TABLE IF NOT EXISTS LEX_NOTIFYPREFS (
+    USRID INT NOT NULL,
+    FREQUENCY CHAR(1) NOT NULL DEFAULT 'W',
+    LASTSENT CHAR(14) NULL,
+    UNSUBTOKEN CHAR(32) NULL,
+    PRIMARY KEY (USRID),
+    UNIQUE KEY IDX_NOTIFY_TOKEN (UNSUBTOKEN)
+);
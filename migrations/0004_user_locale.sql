@@ -0,0 +1,2 @@
+-- Locale used to pick the email template set for a user, e.g. 'en' or 'de'
+ALTER TABLE LEX_USERS ADD COLUMN LOCALE VARCHAR(16) NULL;
@@ -0,0 +1,15 @@
+-- Outbound email queue, STATUS is 'P'ending, 'S'ent or 'F'ailed
+CREATE TABLE IF NOT EXISTS LEX_MAILQUEUE (
+    MAILID INT NOT NULL AUTO_INCREMENT,
+    TOEMAIL VARCHAR(255) NOT NULL,
+    SUBJECT VARCHAR(255) NOT NULL,
+    HTML MEDIUMTEXT NOT NULL,
+    TEXT MEDIUMTEXT NOT NULL,
+    STATUS CHAR(1) NOT NULL DEFAULT 'P',
+    ATTEMPTS INT NOT NULL DEFAULT 0,
+    LASTERROR TEXT NULL,
+    NEXTATTEMPT CHAR(14) NOT NULL,
+    DATEON CHAR(14) NOT NULL,
+    PRIMARY KEY (MAILID),
+    KEY IDX_MAILQUEUE_DUE (STATUS, NEXTATTEMPT)
+);
@@ -0,0 +1,7 @@
+-- When each user or address last had a view of a lot counted, VIEWER is 'U:<usrid>' or 'IP:<address>'
+CREATE TABLE IF NOT EXISTS LEX_LOTVIEWS (
+    LOTID INT NOT NULL,
+    VIEWER VARCHAR(64) NOT NULL,
+    LASTVIEW CHAR(14) NOT NULL,
+    PRIMARY KEY (LOTID, VIEWER)
+);
@@ -0,0 +1,24 @@
+-- Every published version of a lot with its changelog. Files of earlier versions are kept
+-- in `int_file_dir`, and OLDDL on the lot says whether users can still download them
+CREATE TABLE IF NOT EXISTS LEX_LOTREVISIONS (
+    REVID INT NOT NULL AUTO_INCREMENT,
+    LOTID INT NOT NULL,
+    USRID INT NOT NULL,
+    VERSION VARCHAR(20) NOT NULL,
+    LOTFILE VARCHAR(255) NOT NULL,
+    CHANGELOG TEXT NOT NULL,
+    DATEON CHAR(14) NOT NULL,
+    PRIMARY KEY (REVID),
+    KEY IDX_LOTREVISIONS_LOT (LOTID)
+);
+
+-- MySQL has no ADD COLUMN IF NOT EXISTS, so the column is only added when it's missing
+SET @ADD_OLDDL = IF(
+    (SELECT COUNT(*) FROM INFORMATION_SCHEMA.COLUMNS
+        WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = 'LEX_LOTS' AND COLUMN_NAME = 'OLDDL') = 0,
+    'ALTER TABLE LEX_LOTS ADD COLUMN OLDDL CHAR(1) NOT NULL DEFAULT ''F''',
+    'DO 0'
+);
+PREPARE ADD_OLDDL FROM @ADD_OLDDL;
+EXECUTE ADD_OLDDL;
+DEALLOCATE PREPARE ADD_OLDDL;
@@ -0,0 +1,10 @@
+-- Why a lot is locked, KIND is 'U'ser for the author's own lock or 'A'dmin. The lot's USRLOCK
+-- and ADMLOCK flags say whether it's locked; an author's lock is kept here under an admin lock
+CREATE TABLE IF NOT EXISTS LEX_LOTLOCKS (
+    LOTID INT NOT NULL,
+    KIND CHAR(1) NOT NULL,
+    USRID INT NOT NULL,
+    REASON VARCHAR(255) NOT NULL,
+    DATEON CHAR(14) NOT NULL,
+    PRIMARY KEY (LOTID, KIND)
+);
@@ -0,0 +1,91 @@
+use crate::*;
+use serde::{Deserialize, Serialize};
+
+/// A version of the API, mounted under `/api/{version}`.
+/// Older versions keep their legacy response shapes, newer ones are free to fix them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    V1,
+    V2,
+}
+/// The response of `/version`.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct VersionInfo {
+    pub version: ApiVersion,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub latest: ApiVersion,
+    pub versions: Vec<VersionStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct VersionStatus {
+    pub version: ApiVersion,
+    pub deprecated: bool,
+    pub sunset: Option<chrono::NaiveDate>,
+}
+
+impl std::str::FromStr for ApiVersion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_lowercase().as_str() {
+            "v1" => Ok(ApiVersion::V1),
+            "v2" => Ok(ApiVersion::V2),
+            _ => Err(Error::NotFound),
+        }
+    }
+}
+impl std::fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+impl ApiVersion {
+    pub const ALL: [ApiVersion; 2] = [ApiVersion::V1, ApiVersion::V2];
+    /// Served under the bare `/api` prefix, for clients that predate versioning.
+    pub const LEGACY: ApiVersion = ApiVersion::V1;
+    pub const LATEST: ApiVersion = ApiVersion::V2;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1",
+            ApiVersion::V2 => "v2",
+        }
+    }
+
+    pub fn is_deprecated(self) -> bool {
+        self < ApiVersion::LATEST
+    }
+
+    /// Adds the `Deprecation`, `Sunset` and successor `Link` headers to responses from deprecated versions.
+    pub fn with_headers(
+        self,
+        config: &Config,
+        reply: impl warp::Reply + 'static,
+    ) -> Box<dyn warp::Reply> {
+        if !self.is_deprecated() {
+            return Box::new(reply);
+        }
+
+        let mut response = reply.into_response();
+        let headers = response.headers_mut();
+
+        headers.insert("deprecation", warp::http::HeaderValue::from_static("true"));
+        if let Some(sunset) = config.api_sunset {
+            let sunset = http_date(sunset.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc());
+            if let Ok(sunset) = warp::http::HeaderValue::from_str(&sunset) {
+                headers.insert("sunset", sunset);
+            }
+        }
+        if let Ok(link) = warp::http::HeaderValue::from_str(&format!(
+            "</api/{}/>; rel=\"successor-version\"",
+            ApiVersion::LATEST
+        )) {
+            headers.insert("link", link);
+        }
+
+        Box::new(response)
+    }
+}
@@ -1,13 +1,12 @@
 use crate::*;
-use mysql_async::{
-    params,
-    prelude::{Query, WithParams},
-};
+use base64::Engine;
+use chrono::TimeZone;
+use mysql_async::{prelude::FromValue, Row};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
-    #[error("Environment Error")]
-    Env(#[from] std::env::VarError),
+    #[error("Config Error:\n{}", .0.join("\n"))]
+    Config(Vec<String>),
     #[error("ParseBool")]
     ParseBool(#[from] std::str::ParseBoolError),
     #[error("Unauthorized")]
@@ -16,14 +15,34 @@ pub enum Error {
     Forbidden,
     #[error("MalformedRequest")]
     MalformedRequest,
+    #[error("Invalid Params: {0:?}")]
+    InvalidParams(Vec<crate::validate::FieldError>),
     #[error("NotFound")]
     NotFound,
+    #[error("Gone")]
+    Gone,
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    #[error("NotImplemented")]
+    NotImplemented,
+    #[error("RateLimited")]
+    RateLimited { retry_after: u64 },
+    #[error("Malformed Row: {0}")]
+    MalformedRow(String),
     #[error("Database Error")]
     Database(#[from] mysql_async::Error),
     #[error("Email Error")]
     Lettre(#[from] lettre::error::Error),
     #[error("Email Transport Error")]
     LettreTransport(#[from] lettre::transport::smtp::Error),
+    #[error("Email File Transport Error")]
+    LettreFile(#[from] lettre::transport::file::Error),
+    #[error("Email Stub Transport Error")]
+    MailStub,
+    #[error("Template Error")]
+    Template(#[from] tera::Error),
+    #[error("Invalid Email Address")]
+    Address(#[from] lettre::address::AddressError),
     #[error("Base64 Error")]
     Base64(#[from] base64::DecodeError),
     #[error("SerdeJson Error")]
@@ -33,80 +52,145 @@ pub enum Error {
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug, Clone)]
-pub(crate) struct Config {
-    // DB settings
-    pub db_arch: String, // = 'mysql';
-    pub db_name: String, // = 'database_name';
-    pub db_host: String, // = 'database_host';
-    pub db_user: String, // = 'database_user';
-    pub db_pass: String, // = 'database_password';
+impl Error {
+    pub fn status(&self) -> warp::http::StatusCode {
+        use warp::http::StatusCode;
 
-    // Filesystem settings
-    pub int_file_dir: String, // = "/home/my_username/public_html/file_exchange/files/";	// Internal directory where files reside
-    pub ext_file_dir: String, // = "http://mydomain.com/file_exchange/files/";			// Weburl where files will be downloaded from
+        match self {
+            Error::Unauthorized => StatusCode::UNAUTHORIZED,
+            Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::MalformedRequest
+            | Error::InvalidParams(_)
+            | Error::ParseBool(_)
+            | Error::Base64(_)
+            | Error::Address(_) => StatusCode::BAD_REQUEST,
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Gone => StatusCode::GONE,
+            Error::Conflict(_) => StatusCode::CONFLICT,
+            Error::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            Error::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 
-    // Link settings
-    pub index_link: String, // = "http://mydomain.com/file_exchange/";					// Index url of your file exchange
-    pub img_link: String, // = "http://mydomain.com/file_exchange/images/";              // Url to the images for your file exchange
-    pub cat_link: String, // = "http://mydomain.com/file_exchange/category_images/";     // Url to the category images for your file exchange
+    /// Machine-readable error code. These are part of the API and must not change.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::Unauthorized => "unauthorized",
+            Error::Forbidden => "forbidden",
+            Error::MalformedRequest | Error::ParseBool(_) | Error::Base64(_) => "malformed_request",
+            Error::InvalidParams(_) => "invalid_params",
+            Error::Address(_) => "invalid_email_address",
+            Error::NotFound => "not_found",
+            Error::Gone => "gone",
+            Error::Conflict(_) => "conflict",
+            Error::NotImplemented => "not_implemented",
+            Error::RateLimited { .. } => "rate_limited",
+            _ => "internal_error",
+        }
+    }
 
-    // Log settings
-    pub do_log: bool,     // = false;
-    pub log_file: String, // = "/home/my_username/logs/file_exchange/exchange.log";
+    /// Message shown to clients. Internal errors get a generic one so their cause isn't leaked.
+    pub fn message(&self) -> String {
+        match self {
+            Error::Unauthorized => "Invalid username or password.".into(),
+            Error::Forbidden => "You are not allowed to do that.".into(),
+            Error::MalformedRequest | Error::ParseBool(_) | Error::Base64(_) => {
+                "The request is malformed.".into()
+            }
+            Error::InvalidParams(fields) => fields
+                .iter()
+                .map(|f| f.message.as_str())
+                .collect::<Vec<_>>()
+                .join(", "),
+            Error::Address(_) => "The email address is invalid.".into(),
+            Error::NotFound => "The requested resource does not exist.".into(),
+            Error::Gone => "The requested resource has been deleted.".into(),
+            Error::Conflict(reason) => reason.clone(),
+            Error::NotImplemented => "This endpoint is not implemented yet.".into(),
+            Error::RateLimited { .. } => "Too many requests, try again later.".into(),
+            _ => "An internal error occurred.".into(),
+        }
+    }
 
-    // Mail settings
-    pub email_orig: String, // = "file_exchange@mydomain.com";							// E-mail address to send administrative e-mails from
-    pub api_version: String, // api version
+    pub fn details(&self) -> Option<serde_json::Value> {
+        match self {
+            Error::InvalidParams(fields) => Some(serde_json::json!({ "fields": fields })),
+            Error::RateLimited { retry_after } => {
+                Some(serde_json::json!({ "retry_after": retry_after }))
+            }
+            _ => None,
+        }
+    }
 }
-impl Config {
-    pub(crate) async fn connect_db(&self) -> Result<mysql_async::Conn> {
-        let opts = mysql_async::OptsBuilder::default()
-            .ip_or_hostname(&self.db_host)
-            .user(Some(&self.db_user))
-            .pass(Some(&self.db_pass))
-            .db_name(Some(&self.db_name));
 
-        let conn_pool = mysql_async::Pool::new(opts);
+/// The JSON body of every error response.
+#[derive(Debug, Clone, serde::Serialize, utoipa::ToSchema)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    pub details: Option<serde_json::Value>,
+    pub request_id: String,
+}
+impl ErrorBody {
+    pub fn reply(self, status: warp::http::StatusCode) -> Box<dyn warp::Reply> {
+        let request_id = self.request_id.clone();
 
-        Ok(conn_pool.get_conn().await?)
+        Box::new(warp::reply::with_header(
+            warp::reply::with_status(warp::reply::json(&self), status),
+            "x-request-id",
+            request_id,
+        ))
     }
 }
 
-pub(crate) struct Base;
+/// Id attached to an error response and its log entry, so the two can be matched up.
+pub fn new_request_id() -> String {
+    uuid::Uuid::new_v4().simple().to_string()
+}
+
+pub struct Base;
 impl Base {
-    pub(crate) async fn get_auth(
+    pub async fn get_auth(
         config: std::sync::Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
     ) -> Result<usize> {
-        let password = String::from_utf8_lossy(&password.to_vec()).to_string();
-
-        let mut conn = config.connect_db().await?;
-
-        let user = "SELECT * FROM LEX_USERS WHERE UPPER(USRNAME) = UPPER(:username) AND USRPASS = :password AND ISACTIVE = 'T'"
-            .with(params! {
-                "username" => username.to_uppercase(),
-                password
-            })
-            .map(&mut conn, LEXUser::new).await?;
-
-        if user.len() == 1 {
-            "UPDATE LEX_USERS SET LASTIP = :ip, LASTLOGIN = :date, LOGINCNT = :count WHERE USRID = :usrid"
-            .with(params! {
-                ip,
-                "date" => chrono::Utc::now().format("%Y%m%d%H%M%S").to_string(),
-                "usrid" => user.first().unwrap().usrid
-            }).ignore(&mut conn).await?;
-
-            Ok(user.first().unwrap().usrid)
-        } else {
-            Err(Error::Unauthorized)
+        let usrid = Base::identify(config.clone(), username, password, &ip).await?;
+
+        config
+            .storage
+            .record_login(usrid, &ip, chrono::Utc::now())
+            .await?;
+
+        Ok(usrid)
+    }
+
+    /// Checks the credentials like `get_auth`, but without counting it as a login.
+    pub async fn identify(
+        config: std::sync::Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: &str,
+    ) -> Result<usize> {
+        let password = format!("{password:x}");
+
+        if IpBan::is_banned(config.clone(), ip).await? {
+            return Err(Error::Forbidden);
         }
+
+        Ok(config
+            .storage
+            .user_by_login(&username, &password)
+            .await?
+            .ok_or(Error::Unauthorized)?
+            .usrid)
     }
 
-    pub(crate) async fn is_auth(
+    pub async fn is_auth(
         config: std::sync::Arc<Config>,
         username: String,
         password: md5::Digest,
@@ -115,24 +199,186 @@ impl Base {
         Ok(Base::get_auth(config, username, password, ip).await.is_ok())
     }
 
-    pub(crate) async fn is_admin(config: std::sync::Arc<Config>, usrid: usize) -> Result<bool> {
-        let mut conn = config.connect_db().await?;
+    pub async fn is_admin(config: std::sync::Arc<Config>, usrid: usize) -> Result<bool> {
+        Ok(config.storage.user(usrid).await?.is_some_and(|u| u.is_admin))
+    }
 
-        let admin: Vec<String> = "SELECT ISADMIN FROM LEX_USERS WHERE USRID = :usrid"
-            .with(params! {
-                usrid,
-            })
-            .map(&mut conn, |usrid| usrid)
+    /// Authenticates the user and returns their id, if they are an admin.
+    pub async fn get_admin(
+        config: std::sync::Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+    ) -> Result<usize> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+
+        if Base::is_admin(config, id).await? {
+            Ok(id)
+        } else {
+            Err(Error::Forbidden)
+        }
+    }
+
+    /// Records an action taken by `usrid` against `target` in `LEX_AUDITLOG`.
+    pub async fn audit(
+        config: std::sync::Arc<Config>,
+        usrid: usize,
+        action: &str,
+        target: AuditTarget,
+        details: String,
+    ) -> Result<()> {
+        config
+            .storage
+            .insert_audit(usrid, action, target, &details, chrono::Utc::now())
             .await?;
 
-        Ok(admin.first() == Some(&"T".to_string()))
+        log::info!("Audit: user {usrid} {action} {} {}", target.kind(), target.id());
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditTarget {
+    User(usize),
+    Lot(isize),
+    IpBan(usize),
+}
+impl AuditTarget {
+    pub fn kind(self) -> &'static str {
+        match self {
+            AuditTarget::User(_) => "USER",
+            AuditTarget::Lot(_) => "LOT",
+            AuditTarget::IpBan(_) => "IPBAN",
+        }
+    }
+
+    pub fn id(self) -> isize {
+        match self {
+            AuditTarget::User(id) | AuditTarget::IpBan(id) => id as isize,
+            AuditTarget::Lot(id) => id,
+        }
+    }
+}
+
+/// An action recorded against one target in `LEX_AUDITLOG`.
+#[derive(Debug, Clone)]
+pub struct AuditEntry {
+    pub usrid: usize,
+    pub action: String,
+    pub details: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for AuditEntry {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let details: Option<String> = take_column(&row, "details")?;
+        let dateon: String = take_column(&row, "dateon")?;
+
+        Ok(AuditEntry {
+            usrid: take_column(&row, "usrid")?,
+            action: take_column(&row, "action")?,
+            details: details.unwrap_or_default(),
+            date: parse_lex_date(&dateon)?,
+        })
+    }
+}
+
+/// Reads HTTP Basic credentials from `headers`, hashing the password.
+/// Missing or malformed credentials come back as an empty username, which no user has.
+pub fn get_auth_from_headers(headers: warp::hyper::HeaderMap) -> (String, md5::Digest) {
+    let credentials = headers
+        .get(warp::http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "))
+        .and_then(|encoded| {
+            base64::engine::general_purpose::STANDARD
+                .decode(encoded.trim())
+                .ok()
+        })
+        .and_then(|decoded| String::from_utf8(decoded).ok());
+
+    match credentials.as_deref().and_then(|c| c.split_once(':')) {
+        Some((username, password)) => (username.to_string(), md5::compute(password)),
+        None => (String::new(), md5::compute("")),
     }
 }
 
-pub(crate) fn get_auth_from_headers(headers: warp::hyper::HeaderMap) -> (String, md5::Digest) {
-    todo!()
+/// Reads the column `column` from `row`, matching the name case-insensitively.
+pub fn take_column<T: FromValue>(row: &Row, column: &str) -> Result<T> {
+    let index = row
+        .columns_ref()
+        .iter()
+        .position(|c| c.name_str().eq_ignore_ascii_case(column))
+        .ok_or_else(|| Error::MalformedRow(format!("missing column `{column}`")))?;
+
+    row.get_opt(index)
+        .ok_or_else(|| Error::MalformedRow(format!("missing column `{column}`")))?
+        .map_err(|_| Error::MalformedRow(format!("unexpected value in column `{column}`")))
 }
 
-pub(crate) fn latin1_to_string(s: &[u8]) -> String {
+/// Parses a legacy `'T'`/`'F'` flag column.
+pub fn parse_flag(flag: &str) -> Result<bool> {
+    match flag.trim() {
+        "T" => Ok(true),
+        "F" => Ok(false),
+        other => Err(Error::MalformedRow(format!("invalid flag `{other}`"))),
+    }
+}
+
+/// Parses a legacy LEX date, stored either as `%Y%m%d` or `%Y%m%d%H%M%S`.
+/// Empty and zeroed dates are treated as absent.
+pub fn parse_lex_date(date: &str) -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+    let date = date.trim();
+    if date.is_empty() || date.chars().all(|c| c == '0') {
+        return Ok(None);
+    }
+
+    let naive = match date.len() {
+        8 => chrono::NaiveDate::parse_from_str(date, "%Y%m%d")
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0)),
+        14 => chrono::NaiveDateTime::parse_from_str(date, "%Y%m%d%H%M%S").ok(),
+        _ => None,
+    }
+    .ok_or_else(|| Error::MalformedRow(format!("invalid date `{date}`")))?;
+
+    Ok(Some(chrono::Utc.from_utc_datetime(&naive)))
+}
+
+/// Formats a date the way LEX stores it, as `%Y%m%d%H%M%S`.
+pub fn lex_timestamp(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%Y%m%d%H%M%S").to_string()
+}
+
+/// Formats a date for the legacy columns that only hold the day, as `%Y%m%d`.
+pub fn lex_date(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%Y%m%d").to_string()
+}
+
+/// Formats a date for HTTP headers such as `Last-Modified`.
+pub fn http_date(date: chrono::DateTime<chrono::Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Builds a case-insensitive substring pattern for `LIKE`.
+pub fn like_pattern(s: &str) -> String {
+    format!("%{}%", escape_like(&s.to_uppercase()))
+}
+
+/// Escapes the `LIKE` wildcards in `s`.
+pub fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+pub fn latin1_to_string(s: &[u8]) -> String {
     s.iter().map(|&c| c as char).collect()
 }
+
+/// Encodes `s` as latin1, replacing characters it can't represent with `?`.
+pub fn string_to_latin1(s: &str) -> Vec<u8> {
+    s.chars().map(|c| u8::try_from(c).unwrap_or(b'?')).collect()
+}
@@ -0,0 +1,288 @@
+use crate::*;
+use crate::apiversion::ApiVersion;
+use crate::email::{Mailer, MailTransport, SmtpSecurity, Templates};
+use crate::storage::{Backend, MemoryStorage, MySqlStorage, Storage};
+use serde::Deserialize;
+
+/// Config file read when `lex_config` isn't set. It's fine for it not to exist.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+/// Largest lot file accepted by uploads when `max_upload_size` isn't set, in bytes.
+const DEFAULT_MAX_UPLOAD_SIZE: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    // DB settings
+    pub db_arch: Backend, // = 'mysql';                                                    // One of "mysql" or "memory"
+    pub db_name: String, // = 'database_name';
+    pub db_host: String, // = 'database_host';
+    pub db_user: String, // = 'database_user';
+    pub db_pass: String, // = 'database_password';
+    pub db_fixtures: Option<String>, // = "fixtures/lex.json";                               // Data the "memory" backend starts with
+    pub storage: Arc<dyn Storage>,
+
+    // Filesystem settings
+    pub int_file_dir: String, // = "/home/my_username/public_html/file_exchange/files/";	// Internal directory where files reside
+    pub ext_file_dir: String, // = "http://mydomain.com/file_exchange/files/";			// Weburl where files will be downloaded from
+    pub int_img_dir: String, // = "/home/my_username/public_html/file_exchange/images/";	// Internal directory where lot images reside
+    pub max_upload_size: u64, // = 52428800;                                              // Largest lot file accepted by uploads, in bytes
+
+    // Link settings
+    pub index_link: String, // = "http://mydomain.com/file_exchange/";					// Index url of your file exchange
+    pub img_link: String, // = "http://mydomain.com/file_exchange/images/";              // Url to the images for your file exchange
+    pub cat_link: String, // = "http://mydomain.com/file_exchange/category_images/";     // Url to the category images for your file exchange
+
+    // Log settings
+    pub do_log: bool,     // = false;
+    pub log_file: String, // = "/home/my_username/logs/file_exchange/exchange.log";
+
+    // Mail settings
+    pub email_orig: String, // = "file_exchange@mydomain.com";							// E-mail address to send administrative e-mails from
+    pub api_version: ApiVersion, // = "v2";                                               // Version used for links in emails
+    pub api_sunset: Option<chrono::NaiveDate>, // = "2027-01-01";                         // Date after which deprecated versions may be removed
+    pub listen_address: std::net::SocketAddr, // = "0.0.0.0:8080";
+    pub smtp_host: String,   // = "localhost";
+    pub smtp_port: Option<u16>, // = 25;                                                    // Defaults to the port for `smtp_security`
+    pub smtp_security: SmtpSecurity, // = "none";                                         // One of "none", "starttls" or "tls"
+    pub smtp_user: Option<String>,
+    pub smtp_pass: Option<String>,
+    pub mail_transport: MailTransport, // = "smtp";                                        // One of "smtp", "file" or "memory"
+    pub mail_dir: String,       // = "/home/my_username/mail/";                            // Where the "file" transport writes .eml files
+    pub mailer: Mailer,
+    pub template_dir: Option<String>, // = "/home/my_username/file_exchange/templates/"; // Overrides for the built-in email templates, one directory per locale
+    pub templates: Templates,
+}
+
+/// Settings as read from the config file, before environment overrides and validation.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    db_arch: Option<Backend>,
+    db_name: Option<String>,
+    db_host: Option<String>,
+    db_user: Option<String>,
+    db_pass: Option<String>,
+    db_fixtures: Option<String>,
+    int_file_dir: Option<String>,
+    ext_file_dir: Option<String>,
+    int_img_dir: Option<String>,
+    max_upload_size: Option<u64>,
+    index_link: Option<String>,
+    img_link: Option<String>,
+    cat_link: Option<String>,
+    do_log: Option<bool>,
+    log_file: Option<String>,
+    email_orig: Option<String>,
+    api_version: Option<ApiVersion>,
+    api_sunset: Option<chrono::NaiveDate>,
+    listen_address: Option<std::net::SocketAddr>,
+    smtp_host: Option<String>,
+    smtp_port: Option<u16>,
+    smtp_security: Option<SmtpSecurity>,
+    smtp_user: Option<String>,
+    smtp_pass: Option<String>,
+    mail_transport: Option<MailTransport>,
+    mail_dir: Option<String>,
+    template_dir: Option<String>,
+}
+
+/// Collects every problem with the settings so they can be reported together.
+#[derive(Default)]
+struct Settings {
+    errors: Vec<String>,
+}
+impl Settings {
+    /// The value of `key`, taken from the environment if it's set there, otherwise from the file.
+    fn optional<T: std::str::FromStr>(&mut self, key: &str, file: Option<T>) -> Option<T> {
+        match std::env::var(key) {
+            Ok(value) => match value.parse() {
+                Ok(value) => Some(value),
+                Err(_) => {
+                    self.errors
+                        .push(format!("`{key}` has an invalid value `{value}` in the environment"));
+                    None
+                }
+            },
+            Err(_) => file,
+        }
+    }
+
+    fn required<T: std::str::FromStr>(&mut self, key: &str, file: Option<T>) -> Option<T> {
+        let value = self.optional(key, file);
+        if value.is_none() && !self.errors.iter().any(|e| e.starts_with(&format!("`{key}`"))) {
+            self.errors.push(format!("`{key}` is missing"));
+        }
+        value
+    }
+
+    /// Like `required`, for settings that other paths or urls get appended to.
+    fn directory(&mut self, key: &str, file: Option<String>) -> Option<String> {
+        let value = self.required(key, file);
+        if let Some(value) = &value {
+            if !value.ends_with('/') {
+                self.errors
+                    .push(format!("`{key}` must end with a trailing slash, found `{value}`"));
+            }
+        }
+        value
+    }
+}
+
+impl Config {
+    /// Loads the config file named by `lex_config` (or `config.toml`), applies overrides
+    /// from environment variables of the same name as each key, then validates the result.
+    pub fn load() -> Result<Self> {
+        let (path, explicit) = match std::env::var("lex_config") {
+            Ok(path) => (path, true),
+            Err(_) => (DEFAULT_CONFIG_FILE.to_string(), false),
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Config::from_toml(&path, &contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && !explicit => {
+                Config::from_raw(RawConfig::default())
+            }
+            Err(e) => Err(Error::Config(vec![format!("`{path}` can't be read: {e}")])),
+        }
+    }
+
+    /// Builds the config from the contents of the config file `path`, with the same
+    /// environment overrides and validation as `load`.
+    pub fn from_toml(path: &str, contents: &str) -> Result<Self> {
+        let raw = toml::from_str(contents)
+            .map_err(|e| Error::Config(vec![format!("`{path}` is not valid: {e}")]))?;
+
+        Config::from_raw(raw)
+    }
+
+    fn from_raw(raw: RawConfig) -> Result<Self> {
+        let mut s = Settings::default();
+
+        let db_arch = s.optional("db_arch", raw.db_arch).unwrap_or(Backend::Mysql);
+        // the in-memory backend has no database to connect to
+        let (db_name, db_host, db_user, db_pass) = if db_arch == Backend::Mysql {
+            (
+                s.required("db_name", raw.db_name),
+                s.required("db_host", raw.db_host),
+                s.required("db_user", raw.db_user),
+                s.required("db_pass", raw.db_pass),
+            )
+        } else {
+            (
+                s.optional("db_name", raw.db_name),
+                s.optional("db_host", raw.db_host),
+                s.optional("db_user", raw.db_user),
+                s.optional("db_pass", raw.db_pass),
+            )
+        };
+        let db_fixtures = s.optional("db_fixtures", raw.db_fixtures);
+        let int_file_dir = s.directory("int_file_dir", raw.int_file_dir);
+        let ext_file_dir = s.directory("ext_file_dir", raw.ext_file_dir);
+        let int_img_dir = s.directory("int_img_dir", raw.int_img_dir);
+        let max_upload_size = s.optional("max_upload_size", raw.max_upload_size);
+        let index_link = s.directory("index_link", raw.index_link);
+        let img_link = s.directory("img_link", raw.img_link);
+        let cat_link = s.directory("cat_link", raw.cat_link);
+        let do_log = s.optional("do_log", raw.do_log);
+        let log_file = s.optional("log_file", raw.log_file);
+        let email_orig = s.required("email_orig", raw.email_orig);
+        let api_version = s.optional("api_version", raw.api_version);
+        let api_sunset = s.optional("api_sunset", raw.api_sunset);
+        let listen_address = s.optional("listen_address", raw.listen_address);
+        let smtp_host = s.optional("smtp_host", raw.smtp_host);
+        let smtp_port = s.optional("smtp_port", raw.smtp_port);
+        let smtp_security = s.optional("smtp_security", raw.smtp_security);
+        let smtp_user = s.optional("smtp_user", raw.smtp_user);
+        let smtp_pass = s.optional("smtp_pass", raw.smtp_pass);
+        let mail_transport = s.optional("mail_transport", raw.mail_transport);
+        let mail_dir = s.optional("mail_dir", raw.mail_dir);
+        let template_dir = s.optional("template_dir", raw.template_dir);
+
+        let do_log = do_log.unwrap_or(false);
+        if do_log && log_file.is_none() {
+            s.errors.push("`log_file` is missing, it's needed when `do_log` is true".into());
+        }
+        let mail_transport = mail_transport.unwrap_or(MailTransport::Smtp);
+        if mail_transport == MailTransport::File && mail_dir.is_none() {
+            s.errors.push("`mail_dir` is missing, it's needed when `mail_transport` is \"file\"".into());
+        }
+        if let Some(email_orig) = &email_orig {
+            if email_orig.parse::<lettre::Address>().is_err() {
+                s.errors.push(format!("`email_orig` is not a valid email address, found `{email_orig}`"));
+            }
+        }
+        if smtp_user.is_some() != smtp_pass.is_some() {
+            s.errors.push("`smtp_user` and `smtp_pass` must be set together".into());
+        }
+
+        if !s.errors.is_empty() {
+            return Err(Error::Config(s.errors));
+        }
+
+        let mut config = Config {
+            db_arch,
+            db_name: db_name.unwrap_or_default(),
+            db_host: db_host.unwrap_or_default(),
+            db_user: db_user.unwrap_or_default(),
+            db_pass: db_pass.unwrap_or_default(),
+            db_fixtures,
+            storage: Arc::new(MemoryStorage::default()),
+            int_file_dir: int_file_dir.unwrap_or_default(),
+            ext_file_dir: ext_file_dir.unwrap_or_default(),
+            int_img_dir: int_img_dir.unwrap_or_default(),
+            max_upload_size: max_upload_size.unwrap_or(DEFAULT_MAX_UPLOAD_SIZE),
+            index_link: index_link.unwrap_or_default(),
+            img_link: img_link.unwrap_or_default(),
+            cat_link: cat_link.unwrap_or_default(),
+            do_log,
+            log_file: log_file.unwrap_or_default(),
+            email_orig: email_orig.unwrap_or_default(),
+            api_version: api_version.unwrap_or(ApiVersion::LATEST),
+            api_sunset,
+            listen_address: listen_address.unwrap_or_else(|| crate::LISTEN_ADDRESS.parse().unwrap()),
+            smtp_host: smtp_host.unwrap_or_else(|| "localhost".into()),
+            smtp_port,
+            smtp_security: smtp_security.unwrap_or(SmtpSecurity::None),
+            smtp_user,
+            smtp_pass,
+            mail_transport,
+            mail_dir: mail_dir.unwrap_or_default(),
+            mailer: Mailer::memory(),
+            templates: Templates::load(template_dir.as_deref())?,
+            template_dir,
+        };
+        config.mailer = config.build_mailer()?;
+        config.storage = config.build_storage()?;
+
+        Ok(config)
+    }
+
+    /// Builds the mail transport described by the mail settings.
+    pub fn build_mailer(&self) -> Result<Mailer> {
+        match self.mail_transport {
+            MailTransport::Smtp => Mailer::smtp(
+                &self.smtp_host,
+                self.smtp_port,
+                self.smtp_security,
+                self.smtp_user.clone().zip(self.smtp_pass.clone()),
+            ),
+            MailTransport::File => Ok(Mailer::file(&self.mail_dir)),
+            MailTransport::Memory => Ok(Mailer::memory()),
+        }
+    }
+
+    /// Opens the storage backend described by the DB settings.
+    pub fn build_storage(&self) -> Result<Arc<dyn Storage>> {
+        Ok(match self.db_arch {
+            Backend::Mysql => Arc::new(MySqlStorage::new(
+                &self.db_host,
+                &self.db_user,
+                &self.db_pass,
+                &self.db_name,
+            )),
+            Backend::Memory => match &self.db_fixtures {
+                Some(path) => Arc::new(MemoryStorage::load(path)?),
+                None => Arc::new(MemoryStorage::default()),
+            },
+        })
+    }
+}
@@ -2,62 +2,325 @@ use std::sync::Arc;
 
 use base64::Engine;
 use lettre::{
-    message::header::{ContentType, MIME_VERSION_1_0},
-    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+    message::{
+        header::MIME_VERSION_1_0,
+        Mailbox, MultiPart,
+    },
+    transport::{smtp::authentication::Credentials, stub::AsyncStubTransport},
+    AsyncFileTransport, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
 };
+use serde::{Deserialize, Serialize};
 
-pub(crate) struct Email {}
+/// Locale used when a user has none, or their locale has no template for an email.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Emails that have a template, each made of a `.subject`, `.html` and `.txt` file.
+const TEMPLATE_NAMES: &[&str] = &["registration", "password_reset", "digest", "moderation"];
+
+/// The built-in template set, which operators can override file by file.
+const BUILTIN_TEMPLATES: &[(&str, &str)] = &[
+    ("en/registration.subject", include_str!("../templates/email/en/registration.subject")),
+    ("en/registration.html", include_str!("../templates/email/en/registration.html")),
+    ("en/registration.txt", include_str!("../templates/email/en/registration.txt")),
+    ("en/password_reset.subject", include_str!("../templates/email/en/password_reset.subject")),
+    ("en/password_reset.html", include_str!("../templates/email/en/password_reset.html")),
+    ("en/password_reset.txt", include_str!("../templates/email/en/password_reset.txt")),
+    ("en/digest.subject", include_str!("../templates/email/en/digest.subject")),
+    ("en/digest.html", include_str!("../templates/email/en/digest.html")),
+    ("en/digest.txt", include_str!("../templates/email/en/digest.txt")),
+    ("en/moderation.subject", include_str!("../templates/email/en/moderation.subject")),
+    ("en/moderation.html", include_str!("../templates/email/en/moderation.html")),
+    ("en/moderation.txt", include_str!("../templates/email/en/moderation.txt")),
+];
+
+/// Email templates, grouped into one set per locale as `<locale>/<name>.<part>`.
+/// Values interpolated into `.html` templates are HTML-escaped.
+#[derive(Debug, Clone)]
+pub struct Templates {
+    tera: tera::Tera,
+}
+
+/// A rendered email, ready to be sent as multipart/alternative.
+#[derive(Debug, Clone)]
+pub struct RenderedEmail {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+impl Templates {
+    /// Loads the built-in templates, then any overrides found in `dir/<locale>/`.
+    pub fn load(dir: Option<&str>) -> crate::base::Result<Self> {
+        let mut tera = tera::Tera::default();
+        tera.add_raw_templates(BUILTIN_TEMPLATES.to_vec())?;
+
+        if let Some(dir) = dir.filter(|d| !d.is_empty()) {
+            let mut files = Vec::new();
+            for locale in std::fs::read_dir(dir)? {
+                let locale = locale?;
+                if !locale.file_type()?.is_dir() {
+                    continue;
+                }
+                let locale_name = locale.file_name().to_string_lossy().to_string();
+
+                for file in std::fs::read_dir(locale.path())? {
+                    let file = file?;
+                    let file_name = file.file_name().to_string_lossy().to_string();
+                    let is_template = TEMPLATE_NAMES.iter().any(|name| {
+                        ["subject", "html", "txt"]
+                            .iter()
+                            .any(|part| file_name == format!("{name}.{part}"))
+                    });
+
+                    if is_template {
+                        files.push((file.path(), Some(format!("{locale_name}/{file_name}"))));
+                    }
+                }
+            }
+            tera.add_template_files(files)?;
+        }
+
+        Ok(Templates { tera })
+    }
+
+    fn resolve(&self, locale: &str, name: &str, part: &str) -> String {
+        let localised = format!("{locale}/{name}.{part}");
+        if self.tera.get_template_names().any(|t| t == localised) {
+            localised
+        } else {
+            format!("{DEFAULT_LOCALE}/{name}.{part}")
+        }
+    }
+
+    pub fn render(
+        &self,
+        locale: Option<&str>,
+        name: &str,
+        context: &tera::Context,
+    ) -> crate::base::Result<RenderedEmail> {
+        let locale = locale.unwrap_or(DEFAULT_LOCALE);
+        let render = |part: &str| self.tera.render(&self.resolve(locale, name, part), context);
+
+        Ok(RenderedEmail {
+            subject: render("subject")?.trim().to_string(),
+            html: render("html")?,
+            text: render("txt")?,
+        })
+    }
+}
+
+pub struct Email {}
 impl Email {
-    pub(crate) async fn send_registration(
-        config: Arc<crate::base::Config>,
+    pub async fn send_registration(
+        config: Arc<crate::config::Config>,
         to_email: String,
         to_username: String,
         to_hash: String,
+        locale: Option<String>,
     ) -> crate::base::Result<()> {
-        let subject = format!("LEX Registration for {to_username}");
-        let key = base64::engine::general_purpose::STANDARD_NO_PAD
+        let key = base64::engine::general_purpose::URL_SAFE_NO_PAD
             .encode(format!("{to_username}:{to_hash}"));
         let link = format!(
             "{}api/{}/user/activate?activation_key={}",
             config.index_link, config.api_version, key
         );
 
-        let message = format!("
-            <html>
-                <head>
-                    <title>File Exchange Registration for {to_username}</title>
-                </head>
-                <body>
-                    <h3>Welcome to the File Exchange!</h3>
-                    <p>To make sure that the data you entered is correct, please click the link below to activate your account</p>
-                    <p>Activation: <a href='{link}'>Click here</a></p>
-                </body>
-            </html>
-        ");
+        let mut context = tera::Context::new();
+        context.insert("username", &to_username);
+        context.insert("link", &link);
+
+        let email = config
+            .templates
+            .render(locale.as_deref(), "registration", &context)?;
+
+        mail(config, to_email, email).await
+    }
+
+    pub async fn send_digest(
+        config: Arc<crate::config::Config>,
+        to_email: String,
+        to_username: String,
+        locale: Option<String>,
+        updates: Vec<crate::user::AvailableUpdate>,
+        comments: Vec<crate::notify::NewComment>,
+        unsubscribe_token: String,
+    ) -> crate::base::Result<()> {
+        let unsubscribe = format!(
+            "{}api/{}/user/unsubscribe?token={}",
+            config.index_link, config.api_version, unsubscribe_token
+        );
+
+        let mut context = tera::Context::new();
+        context.insert("username", &to_username);
+        context.insert("index_link", &config.index_link);
+        context.insert("updates", &updates);
+        context.insert("comments", &comments);
+        context.insert("unsubscribe", &unsubscribe);
 
-        Ok(mail(config, to_email, subject, message).await?)
+        let email = config.templates.render(locale.as_deref(), "digest", &context)?;
+
+        mail(config, to_email, email).await
     }
+
+    /// Tells an author that one of their lots was `action`, e.g. "locked".
+    pub async fn send_moderation(
+        config: Arc<crate::config::Config>,
+        to_email: String,
+        to_username: String,
+        locale: Option<String>,
+        lot: &crate::lot::Lot,
+        action: &str,
+        reason: String,
+    ) -> crate::base::Result<()> {
+        let mut context = tera::Context::new();
+        context.insert("username", &to_username);
+        context.insert("index_link", &config.index_link);
+        context.insert("lot_id", &lot.lotid);
+        context.insert("lot_name", lot.lotname.trim());
+        context.insert("action", action);
+        context.insert("reason", &reason);
+
+        let email = config
+            .templates
+            .render(locale.as_deref(), "moderation", &context)?;
+
+        mail(config, to_email, email).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    None,
+    StartTls,
+    Tls,
+}
+impl std::str::FromStr for SmtpSecurity {
+    type Err = crate::base::Error;
+
+    fn from_str(s: &str) -> crate::base::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(SmtpSecurity::None),
+            "starttls" => Ok(SmtpSecurity::StartTls),
+            "tls" => Ok(SmtpSecurity::Tls),
+            _ => Err(crate::base::Error::MalformedRequest),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MailTransport {
+    Smtp,
+    File,
+    Memory,
+}
+impl std::str::FromStr for MailTransport {
+    type Err = crate::base::Error;
+
+    fn from_str(s: &str) -> crate::base::Result<Self> {
+        match s.to_lowercase().as_str() {
+            "smtp" => Ok(MailTransport::Smtp),
+            "file" => Ok(MailTransport::File),
+            "memory" => Ok(MailTransport::Memory),
+            _ => Err(crate::base::Error::MalformedRequest),
+        }
+    }
+}
+
+/// Where outgoing mail goes: an SMTP server, `.eml` files in a directory, or memory for tests.
+#[derive(Debug, Clone)]
+pub enum Mailer {
+    Smtp(AsyncSmtpTransport<Tokio1Executor>),
+    File(std::path::PathBuf),
+    Memory(AsyncStubTransport),
 }
+impl Mailer {
+    pub fn smtp(
+        host: &str,
+        port: Option<u16>,
+        security: SmtpSecurity,
+        credentials: Option<(String, String)>,
+    ) -> crate::base::Result<Self> {
+        let mut builder = match security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some((user, pass)) = credentials {
+            builder = builder.credentials(Credentials::new(user, pass));
+        }
 
+        Ok(Mailer::Smtp(builder.build()))
+    }
+
+    pub fn file(dir: impl AsRef<std::path::Path>) -> Self {
+        Mailer::File(dir.as_ref().to_path_buf())
+    }
+
+    pub fn memory() -> Self {
+        Mailer::Memory(AsyncStubTransport::new_ok())
+    }
+
+    /// Messages sent through the in-memory transport, as `(envelope, raw message)`.
+    pub async fn messages(&self) -> Vec<(lettre::address::Envelope, String)> {
+        match self {
+            Mailer::Memory(stub) => stub.messages().await,
+            _ => Vec::new(),
+        }
+    }
+
+    pub async fn send(&self, email: Message) -> crate::base::Result<()> {
+        match self {
+            Mailer::Smtp(transport) => {
+                transport.send(email).await?;
+            }
+            Mailer::File(dir) => {
+                AsyncFileTransport::<Tokio1Executor>::new(dir)
+                    .send(email)
+                    .await?;
+            }
+            Mailer::Memory(transport) => {
+                transport
+                    .send(email)
+                    .await
+                    .map_err(|_| crate::base::Error::MailStub)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Queues `email` for delivery by the mail queue worker.
 async fn mail(
-    config: Arc<crate::base::Config>,
+    config: Arc<crate::config::Config>,
     to_email: String,
-    subject: String,
-    message: String,
+    email: RenderedEmail,
 ) -> crate::base::Result<()> {
-    let from = format!("SC4D File Exchange Administration <{}>", config.email_orig);
-    let email = Message::builder()
-        .from(from.parse().unwrap())
-        .to(to_email.parse().unwrap())
-        .subject(subject)
-        .header(ContentType::TEXT_HTML)
-        .header(MIME_VERSION_1_0)
-        .body(message)?;
+    // build it once up front so bad addresses fail the request instead of the queue
+    build_message(&config, &to_email, email.clone())?;
 
-    let mailer: AsyncSmtpTransport<Tokio1Executor> = AsyncSmtpTransport::unencrypted_localhost();
+    crate::mailqueue::MailQueue::enqueue(config, to_email, email).await
+}
 
-    // Send the email
-    let send = mailer.send(email).await?;
+pub fn build_message(
+    config: &crate::config::Config,
+    to_email: &str,
+    email: RenderedEmail,
+) -> crate::base::Result<Message> {
+    let from = Mailbox::new(
+        Some("SC4D File Exchange Administration".into()),
+        config.email_orig.parse()?,
+    );
 
-    Ok(())
+    Ok(Message::builder()
+        .from(from)
+        .to(to_email.parse()?)
+        .subject(email.subject)
+        .header(MIME_VERSION_1_0)
+        .multipart(MultiPart::alternative_plain_html(email.text, email.html))?)
 }
@@ -0,0 +1,242 @@
+use crate::*;
+use mysql_async::Row;
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+
+/// A banned address, range or legacy `LIKE` pattern (`%` and `_` wildcards).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BanPattern {
+    Exact(IpAddr),
+    Cidr(ipnet::IpNet),
+    Wildcard(String),
+}
+impl std::str::FromStr for BanPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+
+        if let Ok(ip) = s.parse::<IpAddr>() {
+            Ok(BanPattern::Exact(ip))
+        } else if let Ok(net) = s.parse::<ipnet::IpNet>() {
+            Ok(BanPattern::Cidr(net.trunc()))
+        } else if !s.is_empty()
+            && s.chars().any(|c| c == '%' || c == '_')
+            && s.chars()
+                .all(|c| c.is_ascii_hexdigit() || matches!(c, '.' | ':' | '%' | '_'))
+        {
+            Ok(BanPattern::Wildcard(s.to_string()))
+        } else {
+            Err(Error::MalformedRequest)
+        }
+    }
+}
+impl std::fmt::Display for BanPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BanPattern::Exact(ip) => write!(f, "{ip}"),
+            BanPattern::Cidr(net) => write!(f, "{net}"),
+            BanPattern::Wildcard(pattern) => write!(f, "{pattern}"),
+        }
+    }
+}
+impl BanPattern {
+    pub fn matches(&self, ip: IpAddr) -> bool {
+        match self {
+            BanPattern::Exact(banned) => canonical(*banned) == canonical(ip),
+            BanPattern::Cidr(net) => net.contains(&canonical(ip)) || net.contains(&ip),
+            BanPattern::Wildcard(pattern) => like_match(
+                pattern.as_bytes(),
+                canonical(ip).to_string().to_lowercase().as_bytes(),
+            ),
+        }
+    }
+}
+
+/// Maps IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) back to IPv4.
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+/// Matches `text` against a SQL `LIKE` pattern.
+fn like_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'%', rest)) => (0..=text.len()).any(|i| like_match(rest, &text[i..])),
+        Some((b'_', rest)) => !text.is_empty() && like_match(rest, &text[1..]),
+        Some((c, rest)) => text
+            .split_first()
+            .is_some_and(|(t, text)| t.eq_ignore_ascii_case(c) && like_match(rest, text)),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct IpBan {
+    pub id: usize,
+    #[serde(serialize_with = "serialize_pattern", deserialize_with = "deserialize_pattern")]
+    #[schema(value_type = String)]
+    pub pattern: BanPattern,
+    pub reason: Option<String>,
+    pub created: Option<chrono::DateTime<chrono::Utc>>,
+    pub expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for IpBan {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let ipaddr: String = take_column(&row, "ipaddr")?;
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+        let expires: Option<String> = take_column(&row, "expires")?;
+
+        Ok(IpBan {
+            id: take_column(&row, "banid")?,
+            pattern: ipaddr
+                .parse()
+                .map_err(|_| Error::MalformedRow(format!("invalid ban pattern `{ipaddr}`")))?,
+            reason: take_column(&row, "reason")?,
+            created: parse_lex_date(&dateon.unwrap_or_default())?,
+            expires: parse_lex_date(&expires.unwrap_or_default())?,
+        })
+    }
+}
+
+fn serialize_pattern<S: serde::Serializer>(
+    pattern: &BanPattern,
+    serializer: S,
+) -> std::result::Result<S::Ok, S::Error> {
+    serializer.collect_str(pattern)
+}
+
+fn deserialize_pattern<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<BanPattern, D::Error> {
+    let pattern = String::deserialize(deserializer)?;
+
+    pattern
+        .parse()
+        .map_err(|_| serde::de::Error::custom(format!("invalid ban pattern `{pattern}`")))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct IpBanParams {
+    #[validate(length(min = 1, max = 64))]
+    pub ip: String,
+    #[validate(length(max = 255))]
+    pub reason: Option<String>,
+    pub expires: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct IpBanCreated {
+    pub id: usize,
+}
+
+impl IpBan {
+    /// Returns whether `ip` falls under any ban that hasn't expired.
+    pub async fn is_banned(config: Arc<Config>, ip: &str) -> Result<bool> {
+        // remote addresses are passed around as strings, possibly with a port
+        let ip = match ip.parse::<IpAddr>() {
+            Ok(ip) => ip,
+            Err(_) => ip
+                .parse::<std::net::SocketAddr>()
+                .map_err(|_| Error::Forbidden)?
+                .ip(),
+        };
+
+        Ok(config
+            .storage
+            .ip_bans_for(canonical(ip), chrono::Utc::now())
+            .await?
+            .iter()
+            .any(|ban| ban.pattern.matches(ip)))
+    }
+
+    pub async fn adm_get_all(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+    ) -> Result<impl warp::Reply> {
+        Base::get_admin(config.clone(), username, password, ip).await?;
+
+        Ok(warp::reply::json(&config.storage.ip_bans().await?))
+    }
+
+    pub async fn adm_add(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        params: IpBanParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_admin(config.clone(), username, password, ip).await?;
+        let pattern: BanPattern = params.ip.parse()?;
+        let banid = config
+            .storage
+            .insert_ip_ban(&pattern, params.reason, params.expires, chrono::Utc::now())
+            .await?;
+
+        Base::audit(
+            config,
+            id,
+            "ipban_add",
+            AuditTarget::IpBan(banid),
+            pattern.to_string(),
+        )
+        .await?;
+
+        Ok(warp::reply::json(&IpBanCreated { id: banid }))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub async fn adm_edit(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        banid: usize,
+        params: IpBanParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_admin(config.clone(), username, password, ip).await?;
+        let pattern: BanPattern = params.ip.parse()?;
+        let exists = config
+            .storage
+            .update_ip_ban(banid, &pattern, params.reason, params.expires)
+            .await?;
+        if !exists {
+            return Err(Error::NotFound);
+        }
+
+        Base::audit(
+            config,
+            id,
+            "ipban_edit",
+            AuditTarget::IpBan(banid),
+            pattern.to_string(),
+        )
+        .await?;
+
+        Ok(warp::reply())
+    }
+
+    pub async fn adm_remove(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        banid: usize,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_admin(config.clone(), username, password, ip).await?;
+        if !config.storage.delete_ip_ban(banid).await? {
+            return Err(Error::NotFound);
+        }
+
+        Base::audit(config, id, "ipban_remove", AuditTarget::IpBan(banid), String::new()).await?;
+
+        Ok(warp::reply())
+    }
+}
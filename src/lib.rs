@@ -0,0 +1,673 @@
+//! The SC4D LEX API: the LEX domain types, their storage, and the HTTP routes serving them.
+
+pub mod apiversion;
+pub mod base;
+pub mod category;
+pub mod config;
+pub mod email;
+pub mod ipban;
+pub mod lot;
+pub mod mailqueue;
+pub mod notify;
+mod openapi;
+mod routes;
+pub mod search;
+pub mod storage;
+pub mod user;
+mod validate;
+
+use {apiversion::*, base::*, user::*, lot::*, search::*, category::*, ipban::*, notify::*, mailqueue::*};
+
+pub use base::{Error, Result};
+pub use config::Config;
+pub use lot::Lot;
+pub use search::Search;
+pub use storage::{Backend, MemoryStorage, MySqlStorage, Storage};
+pub use user::LEXUser;
+
+use std::sync::Arc;
+
+use futures::FutureExt;
+use itertools::Itertools;
+use warp::Filter;
+
+pub const LISTEN_ADDRESS: &str = "0.0.0.0:8080";
+
+/// A route, or a tree of them, with every outcome already turned into a reply.
+type Route = warp::filters::BoxedFilter<(Box<dyn warp::Reply>,)>;
+
+/// Builds every version of the API under `/api`, with rejections turned into error bodies.
+/// This is the whole HTTP API, ready to be passed to `warp::serve`.
+pub fn build_routes(config: Arc<Config>) -> Route {
+    warp::path("api")
+        .and(
+            warp::path(ApiVersion::V1.as_str())
+                .and(version_routes(config.clone(), ApiVersion::V1))
+                .or(warp::path(ApiVersion::V2.as_str()).and(version_routes(config.clone(), ApiVersion::V2)))
+                .unify()
+                // unversioned paths predate versioning and keep the legacy shapes
+                .or(version_routes(config, ApiVersion::LEGACY))
+                .unify(),
+        )
+        .recover(handle_rejection)
+        .unify()
+        .boxed()
+}
+
+/// Builds the route tree for one version of the API, relative to its mount point.
+fn version_routes(config: Arc<Config>, version: ApiVersion) -> Route {
+    mounted_routes(config.clone(), version)
+        .into_iter()
+        .map(|(_, route)| route)
+        .reduce(|routes, route| routes.or(route).unify().boxed())
+        .expect("the route list is not empty")
+        .map(move |reply| version.with_headers(&config, reply))
+        .boxed()
+}
+
+/// Every route of one version of the API, named after the `routes` function serving it,
+/// which is also its OpenAPI operation ID.
+fn mounted_routes(config: Arc<Config>, version: ApiVersion) -> Vec<(&'static str, Route)> {
+    macro_rules! named {
+        ($($route:ident),* $(,)?) => {
+            vec![$((stringify!($route), $route)),*]
+        };
+    }
+
+    let with_config = |arc_config: Arc<Config>| warp::any().map(move || arc_config.clone());
+
+    let get_endpoints = warp::path::end()
+        .and(warp::get())
+        .and_then(|| routes::get_endpoints().map(handle_application_error))
+        .boxed();
+    let get_openapi = warp::path!("openapi.json")
+        .and(warp::get())
+        .and_then(move || routes::get_openapi(version).map(handle_application_error))
+        .boxed();
+    let get_version = warp::path!("version")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(move |config| routes::get_version(config, version).map(handle_application_error))
+        .boxed();
+    let get_user = warp::path!("user")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(move |config, headers, remote| {
+            routes::get_user(config, headers, remote, version).map(handle_application_error)
+        })
+        .boxed();
+    let adm_get_all = warp::path!("user" / "all")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |config, headers, remote, query| {
+            routes::adm_get_all(config, headers, remote, query, version).map(handle_application_error)
+        })
+        .boxed();
+    let adm_get_user = warp::path!("user" / usize)
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(move |usrid, config, headers, remote| {
+            routes::adm_get_user(config, headers, remote, usrid, version).map(handle_application_error)
+        })
+        .boxed();
+    let adm_set_active = warp::path!("user" / usize / "status")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |usrid, config, headers, remote, query| {
+            routes::adm_set_active(config, headers, remote, usrid, query, version)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let adm_set_role = warp::path!("user" / usize / "role")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |usrid, config, headers, remote, query| {
+            routes::adm_set_role(config, headers, remote, usrid, query, version)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let adm_set_level = warp::path!("user" / usize / "level")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |usrid, config, headers, remote, query| {
+            routes::adm_set_level(config, headers, remote, usrid, query, version)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let adm_ban_user = warp::path!("user" / usize / "ban")
+        .and(warp::post())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(move |usrid, config, headers, remote| {
+            routes::adm_ban_user(config, headers, remote, usrid, version).map(handle_application_error)
+        })
+        .boxed();
+    let get_download_history = warp::path!("user" / "download-history")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::get_download_history(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let get_download_list = warp::path!("user" / "download-list")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::get_download_list(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let get_updates = warp::path!("user" / "updates")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::get_updates(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let get_notifications = warp::path!("user" / "notifications")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|config, headers, remote| {
+            routes::get_notifications(config, headers, remote).map(handle_application_error)
+        })
+        .boxed();
+    let put_notifications = warp::path!("user" / "notifications")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::put_notifications(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let get_unsubscribe = warp::path!("user" / "unsubscribe")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(validate::query())
+        .and_then(|config, query| {
+            routes::get_unsubscribe(config, query).map(handle_application_error)
+        })
+        .boxed();
+    let post_register_user = warp::path!("user" / "register")
+        .and(warp::post())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::post_register_user(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let get_activate_user = warp::path!("user" / "activate")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(validate::query())
+        .and_then(|config, query| {
+            routes::get_activate_user(config, query).map(handle_application_error)
+        })
+        .boxed();
+    let adm_get_ipbans = warp::path!("ipban")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|config, headers, remote| {
+            routes::adm_get_ipbans(config, headers, remote).map(handle_application_error)
+        })
+        .boxed();
+    let adm_add_ipban = warp::path!("ipban")
+        .and(warp::post())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::adm_add_ipban(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let adm_edit_ipban = warp::path!("ipban" / usize)
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|banid, config, headers, remote, query| {
+            routes::adm_edit_ipban(config, headers, remote, banid, query)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let adm_remove_ipban = warp::path!("ipban" / usize)
+        .and(warp::delete())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|banid, config, headers, remote| {
+            routes::adm_remove_ipban(config, headers, remote, banid).map(handle_application_error)
+        })
+        .boxed();
+    let adm_get_mail_queue = warp::path!("mail-queue")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|config, headers, remote, query| {
+            routes::adm_get_mail_queue(config, headers, remote, query).map(handle_application_error)
+        })
+        .boxed();
+    let adm_retry_mail = warp::path!("mail-queue" / usize / "retry")
+        .and(warp::post())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|mailid, config, headers, remote| {
+            routes::adm_retry_mail(config, headers, remote, mailid).map(handle_application_error)
+        })
+        .boxed();
+    let adm_purge_mail = warp::path!("mail-queue")
+        .and(warp::delete())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|config, headers, remote| {
+            routes::adm_purge_mail(config, headers, remote).map(handle_application_error)
+        })
+        .boxed();
+    let get_all_lots = warp::path!("lot" / "all")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(validate::query())
+        .and_then(move |config, headers, query| {
+            routes::get_all_lots(config, headers, query, version).map(handle_application_error)
+        })
+        .boxed();
+    let get_lot_http = warp::path!("lot" / isize)
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |lotid, config, headers, remote, query| {
+            routes::get_lot_http(config, headers, remote, lotid, query, version).map(handle_application_error)
+        })
+        .boxed();
+    let upload_lot = warp::path!("lot")
+        .and(warp::post())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(warp::multipart::form().max_length(Lot::max_upload_body(&config)))
+        .and_then(|config, headers, remote, form| {
+            routes::upload_lot(config, headers, remote, form).map(handle_application_error)
+        })
+        .boxed();
+    let update_lot = warp::path!("lot" / isize)
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(warp::multipart::form().max_length(Lot::max_upload_body(&config)))
+        .and_then(|lotid, config, headers, remote, form| {
+            routes::update_lot(config, headers, remote, lotid, form).map(handle_application_error)
+        })
+        .boxed();
+    let get_lot_versions = warp::path!("lot" / isize / "versions")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|lotid, config| routes::get_lot_versions(config, lotid).map(handle_application_error))
+        .boxed();
+    let get_version_download = warp::path!("lot" / isize / "versions" / usize / "download")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|lotid, revid, config, headers, remote| {
+            routes::get_version_download(config, headers, remote, lotid, revid)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let set_lot_lock = warp::path!("lot" / isize / "lock")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|lotid, config, headers, remote, query| {
+            routes::set_lot_lock(config, headers, remote, lotid, query).map(handle_application_error)
+        })
+        .boxed();
+    let adm_set_lot_lock = warp::path!("lot" / isize / "admin-lock")
+        .and(warp::put())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(|lotid, config, headers, remote, query| {
+            routes::adm_set_lot_lock(config, headers, remote, lotid, query)
+                .map(handle_application_error)
+        })
+        .boxed();
+    let get_download = warp::path!("lot" / isize / "download")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and_then(|lotid, config, headers, remote| {
+            routes::get_download(config, headers, remote, lotid).map(handle_application_error)
+        })
+        .boxed();
+    let do_download_list = warp::path!("lot" / isize / "download-list")
+        .and(warp::get())
+        .and_then(|lotid| routes::do_download_list(lotid).map(handle_application_error))
+        .boxed();
+    let bulk_download = warp::path!("lot" / isize / "bulk-dependency")
+        .and(warp::get())
+        .and_then(|lotid| routes::bulk_download(lotid).map(handle_application_error))
+        .boxed();
+    let delete_download_list = warp::path!("lot" / isize / "download-list")
+        .and(warp::delete())
+        .and_then(|lotid| routes::delete_download_list(lotid).map(handle_application_error))
+        .boxed();
+    let get_comment_http = warp::path!("lot" / isize / "comment")
+        .and(warp::get())
+        .and_then(|lotid| routes::get_comment_http(lotid).map(handle_application_error))
+        .boxed();
+    let post_comment = warp::path!("lot" / isize / "comment")
+        .and(warp::post())
+        .and_then(|lotid| routes::post_comment(lotid).map(handle_application_error))
+        .boxed();
+    let get_vote_http = warp::path!("lot" / isize / "vote")
+        .and(warp::get())
+        .and_then(|lotid| routes::get_vote_http(lotid).map(handle_application_error))
+        .boxed();
+    let get_lot_dependency = warp::path!("lot" / isize / "dependency")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|lotid, config| routes::get_lot_dependency(config, lotid).map(handle_application_error))
+        .boxed();
+    let get_dependency_string = warp::path!("lot" / isize / "dependency-string")
+        .and(warp::get())
+        .and_then(|lotid| routes::get_dependency_string(lotid).map(handle_application_error))
+        .boxed();
+    let update_dependency_string = warp::path!("lot" / isize / "dependency-string")
+        .and(warp::put())
+        .and_then(|lotid| routes::update_dependency_string(lotid).map(handle_application_error))
+        .boxed();
+    let do_search = warp::path!("search")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and(warp::header::headers_cloned())
+        .and(warp::filters::addr::remote())
+        .and(validate::query())
+        .and_then(move |config, headers, remote, query| {
+            routes::do_search(config, headers, remote, query, version).map(handle_application_error)
+        })
+        .boxed();
+    let get_broad_category = warp::path!("category" / "broad-category")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_broad_category(config).map(handle_application_error))
+        .boxed();
+    let get_lex_category = warp::path!("category" / "lex-category")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_lex_category(config).map(handle_application_error))
+        .boxed();
+    let get_lex_type = warp::path!("category" / "lex-type")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_lex_type(config).map(handle_application_error))
+        .boxed();
+    let get_group = warp::path!("category" / "group")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_group(config).map(handle_application_error))
+        .boxed();
+    let get_author = warp::path!("category" / "author")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_author(config).map(handle_application_error))
+        .boxed();
+    let get_all_categories = warp::path!("category" / "all")
+        .and(warp::get())
+        .and(with_config(config.clone()))
+        .and_then(|config| routes::get_all_categories(config).map(handle_application_error))
+        .boxed();
+
+    named![
+        get_version,
+        get_openapi,
+        get_user,
+        adm_get_all,
+        adm_get_user,
+        adm_set_active,
+        adm_set_role,
+        adm_set_level,
+        adm_ban_user,
+        get_download_history,
+        get_download_list,
+        get_updates,
+        get_notifications,
+        put_notifications,
+        get_unsubscribe,
+        post_register_user,
+        get_activate_user,
+        adm_get_ipbans,
+        adm_add_ipban,
+        adm_edit_ipban,
+        adm_remove_ipban,
+        adm_get_mail_queue,
+        adm_retry_mail,
+        adm_purge_mail,
+        get_all_lots,
+        get_lot_http,
+        upload_lot,
+        update_lot,
+        get_lot_versions,
+        get_version_download,
+        set_lot_lock,
+        adm_set_lot_lock,
+        get_download,
+        do_download_list,
+        bulk_download,
+        delete_download_list,
+        get_comment_http,
+        post_comment,
+        get_vote_http,
+        get_lot_dependency,
+        get_dependency_string,
+        update_dependency_string,
+        do_search,
+        get_broad_category,
+        get_lex_category,
+        get_lex_type,
+        get_group,
+        get_author,
+        get_all_categories,
+        get_endpoints,
+    ]
+}
+
+pub fn handle_application_error<'a>(
+    result: Result<impl warp::Reply + 'a>,
+) -> std::result::Result<Box<dyn warp::Reply + 'a>, std::convert::Infallible> {
+    match result {
+        Err(e) => Ok(error_reply(e)),
+        Ok(o) => Ok(Box::new(o)),
+    }
+}
+
+fn error_reply(e: Error) -> Box<dyn warp::Reply> {
+    let request_id = new_request_id();
+    // the full cause is only ever logged, clients get the code and a generic message
+    log::warn!("Request {request_id} application error: {e:?}");
+
+    let status = e.status();
+    let reply = ErrorBody {
+        code: e.code(),
+        message: e.message(),
+        details: e.details(),
+        request_id,
+    }
+    .reply(status);
+
+    match e {
+        Error::RateLimited { retry_after } => Box::new(warp::reply::with_header(
+            reply,
+            "retry-after",
+            retry_after.to_string(),
+        )),
+        _ => reply,
+    }
+}
+
+/// Turns warp's own rejections (unknown route, bad query string...) into the same error body.
+pub async fn handle_rejection(
+    rejection: warp::Rejection,
+) -> std::result::Result<Box<dyn warp::Reply>, std::convert::Infallible> {
+    use warp::http::StatusCode;
+
+    if let Some(validate::InvalidParams(fields)) = rejection.find() {
+        return Ok(error_reply(Error::InvalidParams(fields.clone())));
+    }
+
+    let (status, code, message) = if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "not_found", "The requested resource does not exist.")
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (StatusCode::METHOD_NOT_ALLOWED, "method_not_allowed", "The method is not allowed for this resource.")
+    } else if rejection.find::<warp::reject::InvalidQuery>().is_some()
+        || rejection.find::<warp::reject::MissingHeader>().is_some()
+        || rejection.find::<warp::reject::InvalidHeader>().is_some()
+    {
+        (StatusCode::BAD_REQUEST, "malformed_request", "The request is malformed.")
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "The request body is too large.")
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "An internal error occurred.")
+    };
+
+    let request_id = new_request_id();
+    log::warn!("Request {request_id} rejected: {rejection:?}");
+
+    Ok(ErrorBody {
+        code,
+        message: message.into(),
+        details: None,
+        request_id,
+    }
+    .reply(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    const CONFIG: &str = r#"
+db_arch = "memory"
+int_file_dir = "/tmp/lex/files/"
+ext_file_dir = "http://lex.test/files/"
+int_img_dir = "/tmp/lex/images/"
+index_link = "http://lex.test/"
+img_link = "http://lex.test/images/"
+cat_link = "http://lex.test/category_images/"
+email_orig = "lex@lex.test"
+mail_transport = "memory"
+"#;
+
+    /// Every mounted route is documented, and every documented operation is served by the
+    /// route of the same name at its documented method and path.
+    #[tokio::test]
+    async fn routes_match_the_openapi_spec() {
+        let config = Arc::new(Config::from_toml("test config", CONFIG).unwrap());
+        let routes = mounted_routes(config, ApiVersion::LATEST)
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+        let mut documented = BTreeSet::new();
+
+        for (path, mut item) in openapi::spec().paths.paths {
+            for (method, operation) in openapi::operations(&mut item) {
+                let id = operation.operation_id.clone().unwrap_or_default();
+                let route = routes
+                    .get(id.as_str())
+                    .unwrap_or_else(|| panic!("`{id}` is documented but not mounted"));
+
+                // every path parameter is an ID
+                let uri = path
+                    .split('/')
+                    .map(|segment| if segment.starts_with('{') { "1" } else { segment })
+                    .join("/");
+                let served = warp::test::request()
+                    .method(method)
+                    .path(&uri)
+                    .remote_addr("127.0.0.1:50000".parse().unwrap())
+                    .filter(route)
+                    .await;
+                // the route may still reject the request itself, e.g. for a missing query parameter
+                if let Err(rejection) = served {
+                    assert!(
+                        !rejection.is_not_found()
+                            && rejection.find::<warp::reject::MethodNotAllowed>().is_none(),
+                        "`{id}` is not served at {method} {path}"
+                    );
+                }
+
+                documented.insert(id);
+            }
+        }
+
+        let mounted = routes.keys().map(|id| id.to_string()).collect::<BTreeSet<_>>();
+        assert_eq!(mounted, documented);
+    }
+
+    #[tokio::test]
+    async fn errors_carry_their_status_code_and_headers() {
+        let reply = |e: Error| {
+            warp::Reply::into_response(
+                handle_application_error(Err::<warp::reply::Json, _>(e)).unwrap(),
+            )
+        };
+
+        let conflict = reply(Error::Conflict("The username is already taken.".into()));
+        assert_eq!(conflict.status(), warp::http::StatusCode::CONFLICT);
+        assert!(conflict.headers().get("retry-after").is_none());
+
+        let limited = reply(Error::RateLimited { retry_after: 30 });
+        assert_eq!(limited.status(), warp::http::StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(limited.headers()["retry-after"], "30");
+
+        let body = warp::hyper::body::to_bytes(limited.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "rate_limited");
+        assert_eq!(body["details"]["retry_after"], 30);
+    }
+}
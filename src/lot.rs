@@ -1,135 +1,1013 @@
 use std::collections;
 
 use crate::*;
-use mysql_async::{
-    params,
-    prelude::{FromValue, Query, WithParams},
-    Row,
-};
+use mysql_async::{Row, Value};
 use serde::{Deserialize, Serialize};
+use warp::Reply;
 
+/// Whether a lot can be downloaded, and if not why. Stored as the `ISACTIVE`, `ADMLOCK`
+/// and `USRLOCK` flags; a lot with several of them set reads as the first of
+/// `Inactive`, `AdminLocked` and `UserLocked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LotStatus {
+    Active,
+    AdminLocked,
+    UserLocked,
+    Inactive,
+}
+impl LotStatus {
+    pub fn from_flags(isactive: bool, admlock: bool, usrlock: bool) -> Self {
+        if !isactive {
+            LotStatus::Inactive
+        } else if admlock {
+            LotStatus::AdminLocked
+        } else if usrlock {
+            LotStatus::UserLocked
+        } else {
+            LotStatus::Active
+        }
+    }
 
+    /// The `(ISACTIVE, ADMLOCK, USRLOCK)` flags for this status.
+    pub fn flags(self) -> (bool, bool, bool) {
+        match self {
+            LotStatus::Active => (true, false, false),
+            LotStatus::AdminLocked => (true, true, false),
+            LotStatus::UserLocked => (true, false, true),
+            LotStatus::Inactive => (false, false, false),
+        }
+    }
+
+    pub fn is_locked(self) -> bool {
+        matches!(self, LotStatus::AdminLocked | LotStatus::UserLocked)
+    }
+}
+
+/// A row of `LEX_LOTS`. Text columns are decoded from latin1, and flags and dates parsed.
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct Lot {
-    lotid: isize,
-    catid: isize,
-    lotname: String,
-    lotfile: String,
-    usrid: isize,
-    lotdesc: Vec<u8>,
-    lotimgday: Vec<u8>,
-    lotimgnigt: Vec<u8>,
-    lotviews: isize,
-    lotdownloads: isize,
-    lastdownload: String,
-    lastview: String,
-    isactive: String,
-    dateon: String,
-    typeid: isize,
-    dungeon: String,
-    acclvl: isize,
-    lastupdate: String,
-    admlock: String,
-    usrlock: String,
-    lexexcl: String,
-    searchlinks: Vec<u8>,
-    rewardchain: Vec<u8>,
-    maxiscat: String,
-    biglotimg: Vec<u8>,
-    version: String,
-    lotgroup: isize,
-    deps: String,
+pub struct Lot {
+    pub lotid: isize,
+    pub catid: isize,
+    pub lotname: String,
+    pub lotfile: String,
+    pub usrid: usize,
+    pub lotdesc: String,
+    pub lotimgday: String,
+    pub lotimgnigt: String,
+    pub lotviews: usize,
+    pub lotdownloads: usize,
+    pub lastdownload: Option<chrono::DateTime<chrono::Utc>>,
+    pub lastview: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: LotStatus,
+    pub dateon: Option<chrono::DateTime<chrono::Utc>>,
+    pub typeid: isize,
+    pub dungeon: bool,
+    pub acclvl: isize,
+    pub lastupdate: Option<chrono::DateTime<chrono::Utc>>,
+    pub lexexcl: bool,
+    pub searchlinks: String,
+    pub rewardchain: String,
+    pub maxiscat: String,
+    pub biglotimg: String,
+    pub version: String,
+    pub lotgroup: isize,
+    pub deps: String,
+    /// Whether users can download the lot's earlier revisions.
+    #[serde(default)]
+    pub olddl: bool,
 }
+impl TryFrom<Row> for Lot {
+    type Error = Error;
 
-impl Lot {
-    pub(crate) fn new(row: Row) -> Self {
-        Lot {
-            lotid: FromValue::from_value(row["lotid"].clone()),
-            catid: FromValue::from_value(row["catid"].clone()),
-            lotname: FromValue::from_value(row["lotname"].clone()),
-            lotfile: FromValue::from_value(row["lotfile"].clone()),
-            usrid: FromValue::from_value(row["usrid"].clone()),
-            lotdesc: FromValue::from_value(row["lotdesc"].clone()),
-            lotimgday: FromValue::from_value(row["lotimgday"].clone()),
-            lotimgnigt: FromValue::from_value(row["lotimgnigt"].clone()),
-            lotviews: FromValue::from_value(row["lotviews"].clone()),
-            lotdownloads: FromValue::from_value(row["lotdownloads"].clone()),
-            lastdownload: FromValue::from_value(row["lastdownload"].clone()),
-            lastview: FromValue::from_value(row["lastview"].clone()),
-            isactive: FromValue::from_value(row["isactive"].clone()),
-            dateon: FromValue::from_value(row["dateon"].clone()),
-            typeid: FromValue::from_value(row["typeid"].clone()),
-            dungeon: FromValue::from_value(row["dungeon"].clone()),
-            acclvl: FromValue::from_value(row["acclvl"].clone()),
-            lastupdate: FromValue::from_value(row["lastupdate"].clone()),
-            admlock: FromValue::from_value(row["admlock"].clone()),
-            usrlock: FromValue::from_value(row["usrlock"].clone()),
-            lexexcl: FromValue::from_value(row["lexexcl"].clone()),
-            searchlinks: FromValue::from_value(row["searchlinks"].clone()),
-            rewardchain: FromValue::from_value(row["rewardchain"].clone()),
-            maxiscat: FromValue::from_value(row["maxiscat"].clone()),
-            biglotimg: FromValue::from_value(row["biglotimg"].clone()),
-            version: FromValue::from_value(row["version"].clone()),
-            lotgroup: FromValue::from_value(row["lotgroup"].clone()),
-            deps: FromValue::from_value(row["deps"].clone()),
-        }
-    }
-    pub(crate) async fn get_all(config: Arc<Config>) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-
-        let lots: Vec<serde_json::Value> =
-            "SELECT * FROM LEX_LOTS WHERE USRLOCK='F' AND ADMLOCK='F' AND ISACTIVE='T'"
-                .with(())
-                .map(&mut conn, |(lotid, lotname): (isize, String)| {
-                    serde_json::json!({
-                        "id": lotid,
-                        "name": lotname,
-                    })
+    fn try_from(row: Row) -> Result<Self> {
+        let text = |column: &str| -> Result<String> {
+            Ok(latin1_to_string(
+                &take_column::<Option<Vec<u8>>>(&row, column)?.unwrap_or_default(),
+            ))
+        };
+        let date = |column: &str| -> Result<Option<chrono::DateTime<chrono::Utc>>> {
+            parse_lex_date(&take_column::<Option<String>>(&row, column)?.unwrap_or_default())
+        };
+        let flag = |column: &str| -> Result<bool> {
+            parse_flag(&take_column::<String>(&row, column)?)
+        };
+        let number = |column: &str| -> Result<isize> {
+            Ok(take_column::<Option<isize>>(&row, column)?.unwrap_or_default())
+        };
+
+        Ok(Lot {
+            lotid: take_column(&row, "lotid")?,
+            catid: number("catid")?,
+            lotname: text("lotname")?,
+            lotfile: text("lotfile")?,
+            usrid: take_column(&row, "usrid")?,
+            lotdesc: text("lotdesc")?,
+            lotimgday: text("lotimgday")?,
+            lotimgnigt: text("lotimgnigt")?,
+            lotviews: take_column::<Option<usize>>(&row, "lotviews")?.unwrap_or_default(),
+            lotdownloads: take_column::<Option<usize>>(&row, "lotdownloads")?.unwrap_or_default(),
+            lastdownload: date("lastdownload")?,
+            lastview: date("lastview")?,
+            status: LotStatus::from_flags(flag("isactive")?, flag("admlock")?, flag("usrlock")?),
+            dateon: date("dateon")?,
+            typeid: number("typeid")?,
+            dungeon: flag("dungeon")?,
+            acclvl: number("acclvl")?,
+            lastupdate: date("lastupdate")?,
+            lexexcl: flag("lexexcl")?,
+            searchlinks: text("searchlinks")?,
+            rewardchain: text("rewardchain")?,
+            maxiscat: text("maxiscat")?,
+            biglotimg: text("biglotimg")?,
+            version: text("version")?,
+            lotgroup: number("lotgroup")?,
+            deps: text("deps")?,
+            olddl: flag("olddl")?,
+        })
+    }
+}
+
+/// A user's rating of a lot, from 1 to 3.
+#[derive(Debug, Clone, Serialize)]
+pub struct Vote {
+    pub usrid: usize,
+    pub rating: usize,
+}
+
+/// Number of votes for each rating, from 1 to 3.
+pub type VoteCounts = collections::BTreeMap<usize, usize>;
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct Comment {
+    pub id: usize,
+    pub author: String,
+    pub text: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for Comment {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+
+        Ok(Comment {
+            id: take_column(&row, "commid")?,
+            author: take_column(&row, "usrname")?,
+            text: latin1_to_string(&take_column::<Vec<u8>>(&row, "lotcomment")?),
+            date: parse_lex_date(&dateon.unwrap_or_default())?,
+        })
+    }
+}
+
+/// Lots per page of `/lot/all` when `amount` isn't given.
+pub const LOT_PAGE_SIZE: u64 = 50;
+/// Hours before another view of a lot by the same user or address is counted.
+const REPEAT_VIEW_HOURS: i64 = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum LotOrder {
+    Id,
+    Name,
+    Updated,
+    Downloads,
+}
+impl LotOrder {
+    pub fn column(self) -> &'static str {
+        match self {
+            LotOrder::Id => "LOTID",
+            LotOrder::Name => "LOTNAME",
+            LotOrder::Updated => "LASTUPDATE",
+            LotOrder::Downloads => "LOTDOWNLOADS",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LotListParams {
+    pub start: Option<u64>,
+    #[validate(range(min = 1, max = 500))]
+    pub amount: Option<u64>,
+    /// Only lots past this id, as given in `next` by the previous page. Needs `order_by=id`.
+    pub after: Option<isize>,
+    pub order_by: Option<LotOrder>,
+    pub order: Option<SortOrder>,
+}
+
+/// Which extras to include with a lot, as for searches.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LotParams {
+    /// When the caller last downloaded the lot. Needs authentication.
+    pub user: Option<bool>,
+    pub dependencies: Option<bool>,
+    pub comments: Option<bool>,
+    pub votes: Option<bool>,
+    pub categories: Option<bool>,
+    pub dependents: Option<bool>,
+}
+impl From<&SearchParams> for LotParams {
+    fn from(params: &SearchParams) -> Self {
+        LotParams {
+            user: params.user,
+            dependencies: params.dependencies,
+            comments: params.comments,
+            votes: params.votes,
+            categories: params.categories,
+            dependents: params.dependents,
+        }
+    }
+}
+
+/// One page of `/lot/all`, with the number of lots across every page.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotList {
+    pub total: usize,
+    pub lots: Vec<LotSummary>,
+    /// Pass as `after` to get the next page, when ordering by id and there may be more.
+    pub next: Option<isize>,
+}
+
+/// A lot as listed by `/lot/all` and concise searches.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotSummary {
+    pub id: isize,
+    pub name: String,
+}
+impl From<&Lot> for LotSummary {
+    fn from(lot: &Lot) -> Self {
+        LotSummary {
+            id: lot.lotid,
+            name: lot.lotname.trim().to_string(),
+        }
+    }
+}
+
+/// The v1 shape of a concise search result.
+#[derive(Debug, Clone, Serialize)]
+pub struct LegacyLotSummary {
+    pub lotid: isize,
+    pub lotname: String,
+}
+impl From<&Lot> for LegacyLotSummary {
+    fn from(lot: &Lot) -> Self {
+        LegacyLotSummary {
+            lotid: lot.lotid,
+            lotname: lot.lotname.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotImages {
+    pub primary: String,
+    pub secondary: String,
+    pub extra: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotCategories {
+    pub broad_category: Option<BroadCategory>,
+    pub lex_category: Option<LexCategory>,
+    pub lex_type: Option<LexType>,
+    pub group: Option<Group>,
+}
+
+/// A lot as returned to clients. Optional sections are only filled in when requested.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotView {
+    pub id: isize,
+    pub name: String,
+    pub version: String,
+    pub num_downloads: usize,
+    pub num_views: usize,
+    pub author: String,
+    pub is_exclusive: bool,
+    pub description: String,
+    pub images: LotImages,
+    pub link: String,
+    pub is_certified: bool,
+    pub is_active: bool,
+    pub upload_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub update_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub filesize: Option<String>,
+    pub comments: Option<Vec<Comment>>,
+    #[schema(value_type = Option<Object>)]
+    pub votes: Option<VoteCounts>,
+    pub dependencies: Option<Vec<LotDependency>>,
+    pub categories: Option<LotCategories>,
+    /// Lots that list this one as a dependency.
+    pub dependents: Option<Vec<LotDependency>>,
+    pub last_downloaded: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DownloadLink {
+    pub link: String,
+}
+
+/// Whether a lot that another depends on can be downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DependencyStatus {
+    Available,
+    /// Locked by its author or an admin.
+    Locked,
+    Deleted,
+    /// No lot has the listed ID.
+    Missing,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotDependency {
+    pub id: isize,
+    /// The lot's name, unless it's missing.
+    pub name: Option<String>,
+    pub status: DependencyStatus,
+}
+
+/// Who locked a lot. Stored as `KIND` in `LEX_LOTLOCKS`, and mirrored by the lot's `USRLOCK`
+/// and `ADMLOCK` flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LockKind {
+    User,
+    Admin,
+}
+impl std::str::FromStr for LockKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "U" => Ok(LockKind::User),
+            "A" => Ok(LockKind::Admin),
+            other => Err(Error::MalformedRow(format!("invalid lock kind `{other}`"))),
+        }
+    }
+}
+impl LockKind {
+    pub fn flag(self) -> &'static str {
+        match self {
+            LockKind::User => "U",
+            LockKind::Admin => "A",
+        }
+    }
+
+    /// The lot's flag for this kind of lock.
+    pub fn column(self) -> &'static str {
+        match self {
+            LockKind::User => "USRLOCK",
+            LockKind::Admin => "ADMLOCK",
+        }
+    }
+}
+
+/// A lock on a lot, with who applied it and why. A lot has at most one of each kind; an
+/// author's lock is kept under an admin lock, so it still applies once that's cleared.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct LotLock {
+    pub lotid: isize,
+    pub kind: LockKind,
+    pub usrid: usize,
+    pub reason: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for LotLock {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+
+        Ok(LotLock {
+            lotid: take_column(&row, "lotid")?,
+            kind: take_column::<String>(&row, "kind")?.parse()?,
+            usrid: take_column(&row, "usrid")?,
+            reason: latin1_to_string(&take_column::<Vec<u8>>(&row, "reason")?),
+            date: parse_lex_date(&dateon.unwrap_or_default())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LockParams {
+    pub locked: bool,
+    /// Shown to the author, and required when locking.
+    #[validate(length(min = 1, max = 255))]
+    pub reason: Option<String>,
+}
+
+/// A lot's status after its lock changed, with the reason for the lock in effect.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotLockState {
+    pub status: LotStatus,
+    pub reason: Option<String>,
+}
+
+/// Archive types a lot file can be, by extension, along with the bytes such files start with.
+const ARCHIVE_TYPES: &[(&str, &[u8])] = &[
+    ("zip", b"PK\x03\x04"),
+    ("rar", b"Rar!\x1a\x07"),
+    ("7z", b"7z\xbc\xaf\x27\x1c"),
+];
+/// Image types a lot image can be, as for `ARCHIVE_TYPES`.
+const IMAGE_TYPES: &[(&str, &[u8])] = &[
+    ("jpg", b"\xff\xd8\xff"),
+    ("jpeg", b"\xff\xd8\xff"),
+    ("png", b"\x89PNG\r\n\x1a\n"),
+    ("gif", b"GIF8"),
+];
+/// Most images an upload can have: the day, night and large images, in that order.
+pub const MAX_LOT_IMAGES: usize = 3;
+/// Largest image accepted by uploads, in bytes.
+pub const MAX_IMAGE_SIZE: u64 = 2 * 1024 * 1024;
+/// Room left in upload bodies for the text fields and multipart framing, in bytes.
+const UPLOAD_FORM_OVERHEAD: u64 = 256 * 1024;
+
+/// The text fields of a `POST /lot` form. It also takes the lot as a `file` part and up to
+/// three `image` parts.
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::ToSchema)]
+pub struct LotUploadParams {
+    #[validate(length(min = 1, max = 100))]
+    pub name: String,
+    #[validate(length(min = 1, max = 20))]
+    pub version: String,
+    #[validate(length(max = 65535))]
+    pub description: String,
+    /// LEX category ID.
+    pub category: isize,
+    /// LEX type ID.
+    #[serde(rename = "type")]
+    pub lot_type: isize,
+    /// Broad category ID.
+    pub broad_category: Option<isize>,
+    /// Group ID.
+    pub group: Option<isize>,
+    /// IDs of the lots this one needs, separated by commas.
+    pub dependencies: Option<String>,
+}
+
+/// A file sent as part of a multipart form.
+#[derive(Debug, Clone)]
+pub struct FormFile {
+    pub filename: String,
+    pub data: Vec<u8>,
+}
+
+/// The text fields of a `PUT /lot/{lotid}` form. Fields left out keep their current value.
+/// It can also take a new `file` part, and up to three `image` parts that replace the
+/// current images in order.
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::ToSchema)]
+pub struct LotUpdateParams {
+    /// The new version, which must differ from the current one.
+    #[validate(length(min = 1, max = 20))]
+    pub version: String,
+    /// What changed in this version.
+    #[validate(length(min = 1, max = 65535))]
+    pub changelog: String,
+    #[validate(length(min = 1, max = 100))]
+    pub name: Option<String>,
+    #[validate(length(max = 65535))]
+    pub description: Option<String>,
+    /// LEX category ID.
+    pub category: Option<isize>,
+    /// LEX type ID.
+    #[serde(rename = "type")]
+    pub lot_type: Option<isize>,
+    /// Broad category ID.
+    pub broad_category: Option<isize>,
+    /// Group ID.
+    pub group: Option<isize>,
+    /// IDs of the lots this one needs, separated by commas.
+    pub dependencies: Option<String>,
+    /// Whether users can download the lot's earlier versions.
+    pub allow_old_downloads: Option<bool>,
+}
+
+/// A lot upload or update form once every part has been read.
+#[derive(Debug, Clone)]
+pub struct LotUpload<P = LotUploadParams> {
+    pub params: P,
+    pub file: Option<FormFile>,
+    pub images: Vec<FormFile>,
+}
+impl<P: serde::de::DeserializeOwned + validator::Validate> LotUpload<P> {
+    /// Reads every part of the form, validating the text fields as query strings are.
+    pub async fn from_form(mut form: warp::multipart::FormData) -> Result<Self> {
+        use futures::TryStreamExt;
+        use warp::hyper::body::Buf;
+
+        let mut fields = Vec::new();
+        let mut file = None;
+        let mut images = Vec::new();
+
+        while let Some(part) = form.try_next().await.map_err(|_| Error::MalformedRequest)? {
+            let name = part.name().to_string();
+            let filename = part.filename().map(str::to_string);
+            let data = part
+                .stream()
+                .try_fold(Vec::new(), |mut data, chunk| async move {
+                    data.extend_from_slice(chunk.chunk());
+                    Ok(data)
                 })
-                .await?;
+                .await
+                .map_err(|_| Error::MalformedRequest)?;
+
+            match (name.as_str(), filename) {
+                ("file", Some(filename)) => file = Some(FormFile { filename, data }),
+                ("image", Some(filename)) => images.push(FormFile { filename, data }),
+                (_, None) => {
+                    let value = String::from_utf8(data).map_err(|_| Error::MalformedRequest)?;
+                    fields.push((name, value));
+                }
+                _ => return Err(Error::MalformedRequest),
+            }
+        }
 
-        Ok(warp::reply::json(&lots))
+        let query = form_urlencoded::Serializer::new(String::new())
+            .extend_pairs(fields)
+            .finish();
+
+        Ok(LotUpload {
+            params: crate::validate::parse_query(&query).map_err(Error::InvalidParams)?,
+            file,
+            images,
+        })
     }
-    pub(crate) async fn get_lot(
-        config: Arc<Config>,
-        params: SearchParams,
-        lot: Self,
-        usrid: usize,
-    ) -> Result<serde_json::Value> {
-        let mut conn = config.connect_db().await?;
-        let author = "SELECT * FROM LEX_USERS WHERE USRID = :usrid"
-            .with(params! {"usrid" => usrid})
-            .map(&mut conn, |usrname: String| usrname)
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotCreated {
+    pub id: isize,
+}
+
+/// A published version of a lot, along with the file it was published with. Files are kept
+/// when a lot is updated, so earlier versions can still be downloaded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Revision {
+    pub revid: usize,
+    pub lotid: isize,
+    pub usrid: usize,
+    pub version: String,
+    pub lotfile: String,
+    pub changelog: String,
+    pub dateon: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for Revision {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let text = |column: &str| -> Result<String> {
+            Ok(latin1_to_string(
+                &take_column::<Option<Vec<u8>>>(&row, column)?.unwrap_or_default(),
+            ))
+        };
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+
+        Ok(Revision {
+            revid: take_column(&row, "revid")?,
+            lotid: take_column(&row, "lotid")?,
+            usrid: take_column(&row, "usrid")?,
+            version: text("version")?,
+            lotfile: text("lotfile")?,
+            changelog: text("changelog")?,
+            dateon: parse_lex_date(&dateon.unwrap_or_default())?,
+        })
+    }
+}
+
+/// A version of a lot, as listed by `/lot/{lotid}/versions`.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct LotVersion {
+    pub id: usize,
+    pub version: String,
+    pub changelog: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,
+    pub is_current: bool,
+    /// Whether users can download this version. The current one always can be.
+    pub is_downloadable: bool,
+}
+
+/// The extension of `filename`, if it's one of `types` and `data` starts with that type's bytes.
+fn file_type(filename: &str, data: &[u8], types: &[(&'static str, &[u8])]) -> Option<&'static str> {
+    let extension = filename.rsplit_once('.')?.1.to_lowercase();
+
+    types
+        .iter()
+        .find(|(ext, magic)| *ext == extension && data.starts_with(magic))
+        .map(|(ext, _)| *ext)
+}
+
+/// A name to store an uploaded file under that's safe on disk and can't clash with another.
+fn stored_name(filename: &str, extension: &str) -> String {
+    // clients may send a full path
+    let filename = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+    let stem = stem
+        .chars()
+        .take(64)
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect::<String>();
+
+    format!("{}_{stem}.{extension}", uuid::Uuid::new_v4().simple())
+}
+
+/// Writes `data` to a new file at `path`, failing rather than overwriting an existing one.
+async fn write_new_file(path: &str, data: &[u8]) -> Result<()> {
+    use tokio::io::AsyncWriteExt;
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await?;
+    file.write_all(data).await?;
+
+    Ok(file.flush().await?)
+}
+
+/// Splits a `DEPS` column into the IDs of the lots it lists.
+pub(crate) fn dependency_ids(deps: &str) -> Vec<isize> {
+    deps.split('$').filter_map(|id| id.trim().parse().ok()).collect()
+}
+
+/// Collects every problem with an upload's fields and files so they can be reported together.
+#[derive(Default)]
+struct UploadErrors(Vec<crate::validate::FieldError>);
+impl UploadErrors {
+    fn add(&mut self, field: &str, message: impl std::fmt::Display) {
+        self.0.push(crate::validate::FieldError {
+            field: field.into(),
+            message: format!("{field} {message}"),
+        });
+    }
+
+    fn check(self) -> Result<()> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InvalidParams(self.0))
+        }
+    }
+}
+
+/// A new version has to differ from the one the lot is at.
+fn check_new_version(lot: &Lot, version: &str, errors: &mut UploadErrors) {
+    if version.trim() == lot.version.trim() {
+        errors.add("version", "must differ from the current version");
+    }
+}
+
+/// The extension of an uploaded lot file, if it's an archive within the size limit.
+fn check_archive(config: &Config, file: &FormFile, errors: &mut UploadErrors) -> Option<&'static str> {
+    if file.data.len() as u64 > config.max_upload_size {
+        errors.add("file", format!("must be at most {} bytes", config.max_upload_size));
+        return None;
+    }
+
+    let extension = file_type(&file.filename, &file.data, ARCHIVE_TYPES);
+    if extension.is_none() {
+        errors.add("file", "must be a zip, rar or 7z archive");
+    }
+    extension
+}
+
+/// The extension of each uploaded image, leaving out any that aren't acceptable.
+fn check_images(images: &[FormFile], errors: &mut UploadErrors) -> Vec<&'static str> {
+    if images.len() > MAX_LOT_IMAGES {
+        errors.add("image", format!("can be given at most {MAX_LOT_IMAGES} times"));
+    }
+
+    let extensions = images
+        .iter()
+        .filter_map(|image| {
+            file_type(&image.filename, &image.data, IMAGE_TYPES)
+                .filter(|_| image.data.len() as u64 <= MAX_IMAGE_SIZE)
+        })
+        .collect::<Vec<_>>();
+    if extensions.len() < images.len() {
+        errors.add(
+            "image",
+            format!("must be a jpg, png or gif of at most {MAX_IMAGE_SIZE} bytes"),
+        );
+    }
+    extensions
+}
+
+/// Checks that the given categories, type and group exist, returning the broad category.
+async fn check_categories(
+    config: &Arc<Config>,
+    category: Option<isize>,
+    lot_type: Option<isize>,
+    broad_category: Option<isize>,
+    group: Option<isize>,
+    errors: &mut UploadErrors,
+) -> Result<Option<BroadCategory>> {
+    if let Some(category) = category {
+        if !Category::get_lex_category(config.clone())
             .await?
-            .first()
-            .unwrap()
-            .clone();
+            .iter()
+            .any(|c| c.id == category)
+        {
+            errors.add("category", "is not a known category");
+        }
+    }
+    if let Some(lot_type) = lot_type {
+        if !Category::get_lex_type(config.clone())
+            .await?
+            .iter()
+            .any(|t| t.id == lot_type)
+        {
+            errors.add("type", "is not a known type");
+        }
+    }
+    if let Some(group) = group {
+        if !Category::get_group(config.clone())
+            .await?
+            .iter()
+            .any(|g| g.id == group)
+        {
+            errors.add("group", "is not a known group");
+        }
+    }
 
-        let id = lot.lotid;
-        let name = lot.lotname.trim();
-        let version = lot.version.trim();
-        let numdl = lot.lotdownloads;
+    let Some(broad_category) = broad_category else {
+        return Ok(None);
+    };
+    let found = Category::get_broad_category(config.clone())
+        .await?
+        .into_iter()
+        .find(|c| c.id == broad_category);
+    if found.is_none() {
+        errors.add("broad_category", "is not a known broad category");
+    }
+    Ok(found)
+}
+
+/// The IDs in a comma-separated dependency list, which must all be lots other than `own`.
+async fn check_dependencies(
+    config: &Config,
+    dependencies: &str,
+    own: Option<isize>,
+    errors: &mut UploadErrors,
+) -> Result<Vec<isize>> {
+    let mut deps = Vec::new();
 
-        let exclusive = lot.lexexcl == "T";
+    for dep in dependencies.split(',').map(str::trim).filter(|dep| !dep.is_empty()) {
+        let lot = match dep.parse() {
+            Ok(lotid) if Some(lotid) != own => config.storage.lot(lotid).await?,
+            _ => None,
+        };
+        match lot {
+            Some(lot) if lot.status != LotStatus::Inactive => deps.push(lot.lotid),
+            _ => errors.add("dependencies", format!("lists `{dep}`, which is not a lot")),
+        }
+    }
+
+    Ok(deps)
+}
+
+/// Files written for an upload, so they can be removed again if it fails.
+#[derive(Default)]
+struct StoredFiles(Vec<String>);
+impl StoredFiles {
+    /// Writes `file` to `dir` under a new name, returning the name.
+    async fn write(&mut self, dir: &str, file: &FormFile, extension: &str) -> Result<String> {
+        tokio::fs::create_dir_all(dir).await?;
+
+        let name = stored_name(&file.filename, extension);
+        let path = format!("{dir}{name}");
+        write_new_file(&path, &file.data).await?;
+        self.0.push(path);
+
+        Ok(name)
+    }
 
-        let desc = latin1_to_string(&lot.lotdesc);
+    /// Writes the lot file, if there is one, and the images, removing everything written if any fails.
+    async fn write_all(
+        config: &Config,
+        file: Option<(&FormFile, &str)>,
+        images: &[FormFile],
+        image_types: &[&str],
+    ) -> Result<(Self, Option<String>, Vec<String>)> {
+        let mut stored = StoredFiles::default();
 
-        let img = serde_json::json!({
-            "primary": format!("{}{}", config.img_link, String::from_utf8_lossy(&lot.lotimgday)),
-            "secondary": format!("{}{}", config.img_link, String::from_utf8_lossy(&lot.lotimgnigt)),
-            "extra": format!("{}{}", config.img_link, String::from_utf8_lossy(&lot.biglotimg)),
+        let written = async {
+            let lotfile = match file {
+                Some((file, extension)) => {
+                    Some(stored.write(&config.int_file_dir, file, extension).await?)
+                }
+                None => None,
+            };
+            let mut image_names = Vec::new();
+            for (image, extension) in images.iter().zip(image_types) {
+                image_names.push(stored.write(&config.int_img_dir, image, extension).await?);
+            }
+            Ok((lotfile, image_names))
+        }
+        .await;
+
+        match written {
+            Ok((lotfile, image_names)) => Ok((stored, lotfile, image_names)),
+            Err(e) => {
+                stored.remove().await;
+                Err(e)
+            }
+        }
+    }
+
+    async fn remove(self) {
+        for path in self.0 {
+            if let Err(e) = tokio::fs::remove_file(&path).await {
+                log::warn!("Couldn't remove `{path}` after a failed upload: {e:?}");
+            }
+        }
+    }
+}
+
+impl Lot {
+    /// The columns of `LEX_LOTS` that describe the lot, as stored, so that reading the row
+    /// back gives the same lot. `LOTID` and the counters are left out: the counters are only
+    /// ever incremented in place, so that concurrent views and downloads aren't lost. So are
+    /// the status flags, which legacy rows can have several of set and only locks change.
+    pub fn columns(&self) -> Vec<(&'static str, Value)> {
+        let flag = |value: bool| Value::from(if value { "T" } else { "F" });
+        // legacy lot dates only hold the day
+        let date = |date: Option<chrono::DateTime<chrono::Utc>>| {
+            Value::from(date.map(lex_date).unwrap_or_default())
+        };
+        let text = |text: &str| Value::from(string_to_latin1(text));
+
+        vec![
+            ("CATID", self.catid.into()),
+            ("LOTNAME", text(&self.lotname)),
+            ("LOTFILE", text(&self.lotfile)),
+            ("USRID", self.usrid.into()),
+            ("LOTDESC", text(&self.lotdesc)),
+            ("LOTIMGDAY", text(&self.lotimgday)),
+            ("LOTIMGNIGT", text(&self.lotimgnigt)),
+            ("DATEON", date(self.dateon)),
+            ("TYPEID", self.typeid.into()),
+            ("DUNGEON", flag(self.dungeon)),
+            ("ACCLVL", self.acclvl.into()),
+            ("LASTUPDATE", date(self.lastupdate)),
+            ("LEXEXCL", flag(self.lexexcl)),
+            ("SEARCHLINKS", text(&self.searchlinks)),
+            ("REWARDCHAIN", text(&self.rewardchain)),
+            ("MAXISCAT", text(&self.maxiscat)),
+            ("BIGLOTIMG", text(&self.biglotimg)),
+            ("VERSION", text(&self.version)),
+            ("LOTGROUP", self.lotgroup.into()),
+            ("DEPS", text(&self.deps)),
+            ("OLDDL", flag(self.olddl)),
+        ]
+    }
+    /// The `ISACTIVE`, `ADMLOCK` and `USRLOCK` flags for the lot's status.
+    pub fn status_columns(&self) -> Vec<(&'static str, Value)> {
+        let flag = |value: bool| Value::from(if value { "T" } else { "F" });
+        let (isactive, admlock, usrlock) = self.status.flags();
+
+        vec![
+            ("ISACTIVE", flag(isactive)),
+            ("ADMLOCK", flag(admlock)),
+            ("USRLOCK", flag(usrlock)),
+        ]
+    }
+    /// The view and download counters, along with when each last changed, for new lots.
+    pub fn counter_columns(&self) -> Vec<(&'static str, Value)> {
+        let date = |date: Option<chrono::DateTime<chrono::Utc>>| {
+            Value::from(date.map(lex_date).unwrap_or_default())
+        };
+
+        vec![
+            ("LOTVIEWS", self.lotviews.into()),
+            ("LOTDOWNLOADS", self.lotdownloads.into()),
+            ("LASTVIEW", date(self.lastview)),
+            ("LASTDOWNLOAD", date(self.lastdownload)),
+        ]
+    }
+    /// Lists a page of available lots. The `ETag` follows the page itself and the
+    /// `Last-Modified` header the newest `LASTUPDATE`, so that pollers get a
+    /// `304 Not Modified` until a lot changes.
+    pub async fn get_all(
+        config: Arc<Config>,
+        params: LotListParams,
+        if_none_match: Option<String>,
+        if_modified_since: Option<String>,
+        version: ApiVersion,
+    ) -> Result<warp::reply::Response> {
+        let order_by = params.order_by.unwrap_or(LotOrder::Id);
+        if params.after.is_some() && order_by != LotOrder::Id {
+            return Err(Error::InvalidParams(vec![crate::validate::FieldError {
+                field: "after".into(),
+                message: "after must only be used when ordering by id".into(),
+            }]));
+        }
+
+        let (total, last_updated) = config.storage.lots_modified().await?;
+        let lots = config.storage.list_lots(&params).await?;
+        let etag = Lot::list_etag(&params, total, &lots);
+        // `LASTUPDATE` only holds the day, so lots may keep changing until that day is over
+        let last_modified = last_updated.map(|updated| {
+            let day_over = updated.date_naive().and_time(chrono::NaiveTime::MIN).and_utc()
+                + chrono::Duration::days(1);
+            day_over.min(chrono::Utc::now())
         });
 
-        let link = format!("{}lex_filedesc.php?lotGET={}", config.index_link, id);
-        let certified = lot.acclvl > 0;
-        let active = !(lot.admlock == "T" || lot.usrlock == "T" || lot.isactive == "F");
-        let upload_date = chrono::NaiveDateTime::parse_from_str(&lot.dateon, "%Y%m%d").unwrap();
-        let update_date = chrono::NaiveDateTime::parse_from_str(&lot.lastupdate, "%Y%m%d").unwrap();
+        // `If-None-Match` wins over `If-Modified-Since` when both are sent
+        let not_modified = match (if_none_match, if_modified_since) {
+            (Some(tags), _) => tags
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag),
+            (None, Some(since)) => {
+                chrono::DateTime::parse_from_rfc2822(&since).is_ok_and(|since| {
+                    last_modified.is_some_and(|modified| modified.timestamp() <= since.timestamp())
+                })
+            }
+            (None, None) => false,
+        };
+
+        let mut response = if not_modified {
+            warp::http::StatusCode::NOT_MODIFIED.into_response()
+        } else {
+            let amount = params.amount.unwrap_or(LOT_PAGE_SIZE) as usize;
+            let next = if order_by == LotOrder::Id && lots.len() == amount {
+                lots.last().map(|lot| lot.lotid)
+            } else {
+                None
+            };
+
+            if version == ApiVersion::V1 {
+                // v1 clients read a bare list, without paging
+                warp::reply::json(&lots.iter().map(LotSummary::from).collect::<Vec<_>>()).into_response()
+            } else {
+                warp::reply::json(&LotList {
+                    total,
+                    lots: lots.iter().map(LotSummary::from).collect(),
+                    next,
+                })
+                .into_response()
+            }
+        };
+
+        let headers = response.headers_mut();
+        if let Ok(etag) = warp::http::HeaderValue::from_str(&etag) {
+            headers.insert(warp::http::header::ETAG, etag);
+        }
+        if let Some(last_modified) = last_modified {
+            if let Ok(date) = warp::http::HeaderValue::from_str(&http_date(last_modified)) {
+                headers.insert(warp::http::header::LAST_MODIFIED, date);
+            }
+        }
+
+        Ok(response)
+    }
+    /// An `ETag` for a page of the lot list, which changes along with the query, the total,
+    /// or any listed lot's name, version, update date or counters.
+    fn list_etag(params: &LotListParams, total: usize, lots: &[Lot]) -> String {
+        let mut content = format!("{}|{total}", serde_urlencoded::to_string(params).unwrap_or_default());
+        for lot in lots {
+            content += &format!(
+                "|{}:{}:{}:{}:{}:{}",
+                lot.lotid,
+                lot.lotname,
+                lot.version,
+                lot.lastupdate.map(lex_timestamp).unwrap_or_default(),
+                lot.lotviews,
+                lot.lotdownloads
+            );
+        }
+
+        format!("\"{:x}\"", md5::compute(content))
+    }
+    pub async fn get_lot(
+        config: Arc<Config>,
+        params: LotParams,
+        lot: Self,
+        usrid: Option<usize>,
+    ) -> Result<LotView> {
+        let author = LEXUser::fetch(&config, lot.usrid).await?.username;
+
+        let id = lot.lotid;
+
+        let images = LotImages {
+            primary: format!("{}{}", config.img_link, lot.lotimgday),
+            secondary: format!("{}{}", config.img_link, lot.lotimgnigt),
+            extra: format!("{}{}", config.img_link, lot.biglotimg),
+        };
+
+        // a file missing from disk shouldn't hide the rest of the lot
         let file = format!("{}{}", config.int_file_dir, lot.lotfile);
-        let filesize = Lot::get_human_filesize(tokio::fs::read_to_string(file).await?).await;
+        let filesize = tokio::fs::metadata(file)
+            .await
+            .ok()
+            .map(|metadata| Lot::get_human_filesize(metadata.len()));
 
         let comments = if params.comments == Some(true) {
-            Some(Lot::get_comment(id).await?)
+            Some(Lot::get_comment(config.clone(), id).await?)
         } else {
             None
         };
@@ -141,154 +1019,739 @@ impl Lot {
         };
 
         let dependencies = if params.dependencies == Some(true) {
-            Some(Lot::get_dependencies(lot.deps.clone()).await?)
+            Some(Lot::get_dependencies(config.clone(), &lot.deps).await?)
         } else {
             None
         };
 
         let categories = if params.categories == Some(true) {
-            Some(Lot::get_categories(lot.clone()).await?)
+            Some(Lot::get_categories(config.clone(), &lot).await?)
         } else {
             None
         };
 
         let dependents = if params.dependents == Some(true) {
-            Some(Lot::get_dependents(id).await?)
+            Some(Lot::get_dependents(config.clone(), id).await?)
         } else {
             None
         };
 
-        let user = if params.user == Some(true) {
-            "'SELECT * FROM LEX_DOWNLOADTRACK WHERE LOTID = :lotid AND USRID = :usrid AND ISACTIVE=\'T\''"
-                .with(params!{
-                    "lotid" => id,
-                    "usrid" => usrid
-                }).map(&mut conn, |lastdl: String| lastdl).await?.first().cloned()
-        } else {
-            None
+        let last_downloaded = match usrid {
+            Some(usrid) if params.user == Some(true) => config
+                .storage
+                .download_track(usrid, id)
+                .await?
+                .and_then(|track| track.last_downloaded),
+            _ => None,
         };
 
-        Ok(serde_json::json!({
-            "id": id,
-            "name": name,
-            "version": version,
-            "num_downloads": numdl,
-            "author": author,
-            "is_exclusive": exclusive,
-            "description": desc,
-            "images": img,
-            "link": link,
-            "is_certified": certified,
-            "is_active": active,
-            "upload_date": upload_date,
-            "update_date": update_date,
-            "filesize": filesize,
-            "comments": comments,
-            "votes": votes,
-            "dependencies": dependencies,
-            "categories": categories,
-            "dependents": dependents,
-            "last_downloaded": user,
-        }))
+        Ok(LotView {
+            id,
+            name: lot.lotname.trim().to_string(),
+            version: lot.version.trim().to_string(),
+            num_downloads: lot.lotdownloads,
+            num_views: lot.lotviews,
+            author,
+            is_exclusive: lot.lexexcl,
+            description: lot.lotdesc,
+            images,
+            link: format!("{}lex_filedesc.php?lotGET={}", config.index_link, id),
+            is_certified: lot.acclvl > 0,
+            is_active: lot.status == LotStatus::Active,
+            upload_date: lot.dateon,
+            update_date: lot.lastupdate,
+            filesize,
+            comments,
+            votes,
+            dependencies,
+            categories,
+            dependents,
+            last_downloaded,
+        })
     }
-    pub(crate) async fn get_lot_http(
+    /// Fetches a single lot, counting the view once per user (or address, when anonymous) a day.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_lot_http(
         config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
         lotid: isize,
-    ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
+        params: LotParams,
+        version: ApiVersion,
+    ) -> Result<warp::reply::Json> {
+        // credentials are optional here, but wrong ones are still rejected. Viewing a lot isn't
+        // a login, so it doesn't count as one
+        let usrid = if username.is_empty() {
+            None
+        } else {
+            Some(Base::identify(config.clone(), username, password, &ip).await?)
+        };
+        if params.user == Some(true) && usrid.is_none() {
+            return Err(Error::Unauthorized);
+        }
 
-        let lot: Option<Lot> = "SELECT * FROM LEX_LOTS WHERE LOTID = :lotid"
-            .with(params! {
-                "lotid" => lotid,
-            })
-            .map(&mut conn, Lot::new)
+        let mut lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+        if lot.status == LotStatus::Inactive {
+            return Err(Error::Gone);
+        }
+
+        let viewer = match usrid {
+            Some(usrid) => format!("U:{usrid}"),
+            None => format!("IP:{ip}"),
+        };
+        let now = chrono::Utc::now();
+        if config
+            .storage
+            .record_view(lotid, &viewer, now, now - chrono::Duration::hours(REPEAT_VIEW_HOURS))
             .await?
-            .first()
-            .cloned();
+        {
+            lot.lotviews += 1;
+            lot.lastview = Some(now);
+        }
 
-        match lot {
-            Some(lot) => Ok(warp::reply::json(&lot)),
-            None => Err(Error::NotFound),
+        // v1 clients read the stored field names
+        if version == ApiVersion::V1 {
+            return Ok(warp::reply::json(&lot));
         }
+
+        let view = Lot::get_lot(config, params, lot, usrid).await?;
+
+        Ok(warp::reply::json(&view))
     }
-    pub(crate) async fn check_download_limits(usr: String, lot: String) {
-        todo!()
-    }
-    pub(crate) async fn update_download_tracker(usrid: String, lot: String) {
-        todo!()
+    /// Largest `POST /lot` or `PUT /lot/{lotid}` body accepted: a lot file, every image and the
+    /// text fields.
+    pub fn max_upload_body(config: &Config) -> u64 {
+        config.max_upload_size + MAX_LOT_IMAGES as u64 * MAX_IMAGE_SIZE + UPLOAD_FORM_OVERHEAD
     }
-    pub(crate) async fn get_download(lotid: isize) {
-        todo!()
+    /// Publishes a lot for a user with the uploader flag. It stays uncertified until an admin
+    /// certifies it.
+    pub async fn upload(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        form: warp::multipart::FormData,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        if !config.storage.user(id).await?.is_some_and(|u| u.uploader) {
+            return Err(Error::Forbidden);
+        }
+
+        let LotUpload {
+            params,
+            file,
+            images,
+        } = LotUpload::<LotUploadParams>::from_form(form).await?;
+
+        let mut errors = UploadErrors::default();
+        let archive = match &file {
+            Some(file) => check_archive(&config, file, &mut errors),
+            None => {
+                errors.add("file", "is required");
+                None
+            }
+        };
+        let image_types = check_images(&images, &mut errors);
+        let broad_category = check_categories(
+            &config,
+            Some(params.category),
+            Some(params.lot_type),
+            params.broad_category,
+            params.group,
+            &mut errors,
+        )
+        .await?;
+        let deps = check_dependencies(
+            &config,
+            params.dependencies.as_deref().unwrap_or_default(),
+            None,
+            &mut errors,
+        )
+        .await?;
+        errors.check()?;
+
+        let (stored, lotfile, image_names) =
+            StoredFiles::write_all(&config, file.as_ref().zip(archive), &images, &image_types)
+                .await?;
+        let image = |i: usize| image_names.get(i).cloned().unwrap_or_default();
+
+        let now = chrono::Utc::now();
+        let lot = Lot {
+            lotid: 0,
+            catid: params.category,
+            lotname: params.name,
+            lotfile: lotfile.unwrap_or_default(),
+            usrid: id,
+            lotdesc: params.description,
+            lotimgday: image(0),
+            lotimgnigt: image(1),
+            lotviews: 0,
+            lotdownloads: 0,
+            lastdownload: None,
+            lastview: None,
+            status: LotStatus::Active,
+            dateon: Some(now),
+            typeid: params.lot_type,
+            dungeon: false,
+            // uncertified
+            acclvl: 0,
+            lastupdate: Some(now),
+            lexexcl: false,
+            searchlinks: String::new(),
+            rewardchain: String::new(),
+            maxiscat: broad_category.map(|c| c.image).unwrap_or_default(),
+            biglotimg: image(2),
+            version: params.version,
+            lotgroup: params.group.unwrap_or_default(),
+            deps: deps.iter().join("$"),
+            olddl: false,
+        };
+
+        let lotid = match config.storage.insert_lot(&lot).await {
+            Ok(lotid) => lotid,
+            Err(e) => {
+                stored.remove().await;
+                return Err(e);
+            }
+        };
+
+        Ok(warp::reply::json(&LotCreated { id: lotid }))
     }
-    pub(crate) async fn do_download_list(lotid: isize) {
-        todo!()
+    /// Publishes a new version of a lot for its author or an admin, recording a revision with
+    /// the changelog. The previous file is kept, so that version can still be downloaded.
+    /// Locked lots can't be updated.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn update(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        lotid: isize,
+        form: warp::multipart::FormData,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+        Lot::check_updatable(&lot)?;
+        if lot.usrid != id && !Base::is_admin(config.clone(), id).await? {
+            return Err(Error::Forbidden);
+        }
+
+        let LotUpload {
+            params,
+            file,
+            images,
+        } = LotUpload::<LotUpdateParams>::from_form(form).await?;
+
+        let mut errors = UploadErrors::default();
+        check_new_version(&lot, &params.version, &mut errors);
+        let archive = file
+            .as_ref()
+            .and_then(|file| check_archive(&config, file, &mut errors));
+        let image_types = check_images(&images, &mut errors);
+        let broad_category = check_categories(
+            &config,
+            params.category,
+            params.lot_type,
+            params.broad_category,
+            params.group,
+            &mut errors,
+        )
+        .await?;
+        let deps = match &params.dependencies {
+            Some(deps) => Some(check_dependencies(&config, deps, Some(lotid), &mut errors).await?),
+            None => None,
+        };
+        errors.check()?;
+
+        let (stored, lotfile, image_names) =
+            StoredFiles::write_all(&config, file.as_ref().zip(archive), &images, &image_types)
+                .await?;
+
+        // applied to the row as it is when the update is written, so that a lock or edit made
+        // since it was read above isn't undone
+        let now = chrono::Utc::now();
+        let apply = |lot: &mut Lot| -> Result<Revision> {
+            Lot::check_updatable(lot)?;
+            let mut errors = UploadErrors::default();
+            check_new_version(lot, &params.version, &mut errors);
+            errors.check()?;
+
+            lot.version = params.version.trim().to_string();
+            lot.lastupdate = Some(now);
+            if let Some(lotfile) = &lotfile {
+                lot.lotfile = lotfile.clone();
+            }
+            for (image, name) in [&mut lot.lotimgday, &mut lot.lotimgnigt, &mut lot.biglotimg]
+                .into_iter()
+                .zip(&image_names)
+            {
+                *image = name.clone();
+            }
+            if let Some(name) = &params.name {
+                lot.lotname = name.clone();
+            }
+            if let Some(description) = &params.description {
+                lot.lotdesc = description.clone();
+            }
+            if let Some(category) = params.category {
+                lot.catid = category;
+            }
+            if let Some(lot_type) = params.lot_type {
+                lot.typeid = lot_type;
+            }
+            if let Some(broad_category) = &broad_category {
+                lot.maxiscat = broad_category.image.clone();
+            }
+            if let Some(group) = params.group {
+                lot.lotgroup = group;
+            }
+            if let Some(deps) = &deps {
+                lot.deps = deps.iter().join("$");
+            }
+            if let Some(allow_old_downloads) = params.allow_old_downloads {
+                lot.olddl = allow_old_downloads;
+            }
+
+            Ok(Revision {
+                revid: 0,
+                lotid,
+                usrid: id,
+                version: lot.version.clone(),
+                lotfile: lot.lotfile.clone(),
+                changelog: params.changelog.clone(),
+                dateon: Some(now),
+            })
+        };
+        let revision = match config.storage.update_lot_version(lotid, &apply).await {
+            Ok(Some(revision)) => revision,
+            Ok(None) => {
+                stored.remove().await;
+                return Err(Error::NotFound);
+            }
+            Err(e) => {
+                stored.remove().await;
+                return Err(e);
+            }
+        };
+
+        Ok(warp::reply::json(&LotVersion {
+            id: revision.revid,
+            version: revision.version,
+            changelog: revision.changelog,
+            date: revision.dateon,
+            is_current: true,
+            is_downloadable: true,
+        }))
     }
-    pub(crate) async fn delete_download_list(lotid: isize) {
-        todo!()
+    /// Deleted lots are gone, and locked ones can't be changed until they're unlocked.
+    fn check_updatable(lot: &Lot) -> Result<()> {
+        match lot.status {
+            LotStatus::Inactive => Err(Error::Gone),
+            status if status.is_locked() => Err(Error::Forbidden),
+            _ => Ok(()),
+        }
     }
-    pub(crate) async fn get_comment(lotid: isize) -> Result<serde_json::Value> {
-        todo!()
+    /// The lot's revisions, newest first. Lots published before revisions were kept and not
+    /// updated since have none stored, so their current version is given with an id of 0.
+    async fn revisions(config: &Config, lot: &Lot) -> Result<Vec<Revision>> {
+        let revisions = config.storage.revisions(lot.lotid).await?;
+        if revisions.is_empty() {
+            return Ok(vec![Lot::current_revision(lot)]);
+        }
+
+        Ok(revisions)
     }
-    pub(crate) async fn get_comment_http(lotid: isize) {
-        todo!()
+    /// An unsaved revision for the lot's current version.
+    pub(crate) fn current_revision(lot: &Lot) -> Revision {
+        Revision {
+            revid: 0,
+            lotid: lot.lotid,
+            usrid: lot.usrid,
+            version: lot.version.trim().to_string(),
+            lotfile: lot.lotfile.clone(),
+            changelog: String::new(),
+            dateon: lot.lastupdate.or(lot.dateon),
+        }
     }
-    pub(crate) async fn get_vote(config: Arc<Config>, lotid: isize) -> Result<serde_json::Value> {
-        let mut conn = config.connect_db().await?;
+    /// Lists the lot's versions with their changelogs, newest first.
+    pub async fn get_versions(config: Arc<Config>, lotid: isize) -> Result<impl warp::Reply> {
+        let lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+        if lot.status == LotStatus::Inactive {
+            return Err(Error::Gone);
+        }
 
-        let res =
-            "SELECT * FROM LEX_VOTES WHERE LOTID = :lotid AND ISACTIVE = 'T' AND RATETYPE = 'U'"
-                .with(params! {
-                    "lotid" => lotid,
-                })
-                .map(&mut conn, |rating: isize| rating)
-                .await?;
+        let versions = Lot::revisions(&config, &lot)
+            .await?
+            .into_iter()
+            .enumerate()
+            .map(|(i, revision)| LotVersion {
+                id: revision.revid,
+                version: revision.version,
+                changelog: revision.changelog,
+                date: revision.dateon,
+                is_current: i == 0,
+                is_downloadable: i == 0 || lot.olddl,
+            })
+            .collect::<Vec<_>>();
 
-        let mut map = collections::HashMap::from([(1, 0), (2, 0), (3, 0)]);
+        Ok(warp::reply::json(&versions))
+    }
+    /// Gives a link to one of the lot's versions. Earlier versions can only be downloaded when
+    /// the author allows it, or by the author or an admin. Unlike `get_download`, this isn't
+    /// added to the download history.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn get_version_download(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        lotid: isize,
+        revid: usize,
+    ) -> Result<impl warp::Reply> {
+        let usrid = Base::get_auth(config.clone(), username, password, ip).await?;
+        let lot = config
+            .storage
+            .lot(lotid)
+            .await?
+            .filter(|lot| lot.status == LotStatus::Active)
+            .ok_or(Error::NotFound)?;
 
-        for rating in res {
-            *map.get_mut(&rating).unwrap() += 1;
+        let revisions = Lot::revisions(&config, &lot).await?;
+        let is_current = revisions.first().is_some_and(|r| r.revid == revid);
+        let revision = revisions
+            .into_iter()
+            .find(|r| r.revid == revid)
+            .ok_or(Error::NotFound)?;
+
+        if !is_current
+            && !lot.olddl
+            && lot.usrid != usrid
+            && !Base::is_admin(config.clone(), usrid).await?
+        {
+            return Err(Error::Forbidden);
         }
 
-        Ok(serde_json::json!({
-            "1": map.get(&1).unwrap().clone(),
-            "2": map.get(&2).unwrap().clone(),
-            "3": map.get(&3).unwrap().clone(),
+        Ok(warp::reply::json(&DownloadLink {
+            link: format!("{}{}", config.ext_file_dir, revision.lotfile),
         }))
     }
-    pub(crate) async fn get_vote_http(lotid: isize) {
-        todo!()
+    pub async fn get_download(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        lotid: isize,
+    ) -> Result<impl warp::Reply> {
+        // banned addresses are rejected by `get_auth`, so they can't download either
+        let usrid = Base::get_auth(config.clone(), username, password, ip).await?;
+        let lot = config
+            .storage
+            .lot(lotid)
+            .await?
+            .filter(|lot| lot.status == LotStatus::Active)
+            .ok_or(Error::NotFound)?;
+
+        config
+            .storage
+            .record_download(usrid, &lot, chrono::Utc::now())
+            .await?;
+
+        Ok(warp::reply::json(&DownloadLink {
+            link: format!("{}{}", config.ext_file_dir, lot.lotfile),
+        }))
     }
-    pub(crate) async fn post_comment(lotid: isize) {
-        todo!()
+    pub async fn get_comment(config: Arc<Config>, lotid: isize) -> Result<Vec<Comment>> {
+        config.storage.comments(lotid).await
     }
-    pub(crate) async fn get_categories(lot: Self) -> Result<serde_json::Value> {
-        todo!()
+    /// Counts the votes for each rating.
+    pub async fn get_vote(config: Arc<Config>, lotid: isize) -> Result<VoteCounts> {
+        let votes = config.storage.votes(lotid).await?;
+
+        let mut counts = VoteCounts::from([(1, 0), (2, 0), (3, 0)]);
+
+        for vote in votes {
+            if let Some(count) = counts.get_mut(&vote.rating) {
+                *count += 1;
+            }
+        }
+
+        Ok(counts)
     }
-    pub(crate) async fn get_lot_dependency(lotid: isize) {
-        todo!()
+    pub async fn get_categories(config: Arc<Config>, lot: &Self) -> Result<LotCategories> {
+        let maxiscat = lot.maxiscat.trim();
+
+        Ok(LotCategories {
+            broad_category: Category::get_broad_category(config.clone())
+                .await?
+                .into_iter()
+                .find(|c| c.image == maxiscat),
+            lex_category: Category::get_lex_category(config.clone())
+                .await?
+                .into_iter()
+                .find(|c| c.id == lot.catid),
+            lex_type: Category::get_lex_type(config.clone())
+                .await?
+                .into_iter()
+                .find(|t| t.id == lot.typeid),
+            group: Category::get_group(config)
+                .await?
+                .into_iter()
+                .find(|g| g.id == lot.lotgroup),
+        })
     }
-    pub(crate) async fn get_dependency_string(lotid: isize) {
-        todo!()
+    pub async fn get_lot_dependency(config: Arc<Config>, lotid: isize) -> Result<impl warp::Reply> {
+        let lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+        if lot.status == LotStatus::Inactive {
+            return Err(Error::Gone);
+        }
+
+        Ok(warp::reply::json(&Lot::get_dependencies(config, &lot.deps).await?))
     }
-    pub(crate) async fn update_dependency_string(lotid: isize) {
-        todo!()
+    /// The lots listed in a `DEPS` column, each with whether it can be downloaded right now.
+    pub async fn get_dependencies(config: Arc<Config>, deps: &str) -> Result<Vec<LotDependency>> {
+        let mut dependencies = Vec::new();
+
+        for lotid in dependency_ids(deps) {
+            let lot = config.storage.lot(lotid).await?;
+            dependencies.push(LotDependency {
+                id: lotid,
+                name: lot.as_ref().map(|lot| lot.lotname.trim().to_string()),
+                status: Lot::get_dependency_status(lot.as_ref()),
+            });
+        }
+
+        Ok(dependencies)
+    }
+    /// The lots that list `lotid` as a dependency, each with whether it can be downloaded.
+    pub async fn get_dependents(config: Arc<Config>, lotid: isize) -> Result<Vec<LotDependency>> {
+        Ok(config
+            .storage
+            .dependents(lotid)
+            .await?
+            .iter()
+            .map(|lot| LotDependency {
+                id: lot.lotid,
+                name: Some(lot.lotname.trim().to_string()),
+                status: Lot::get_dependency_status(Some(lot)),
+            })
+            .collect())
+    }
+    pub fn get_dependency_status(lot: Option<&Lot>) -> DependencyStatus {
+        match lot.map(|lot| lot.status) {
+            None => DependencyStatus::Missing,
+            Some(LotStatus::Inactive) => DependencyStatus::Deleted,
+            Some(LotStatus::AdminLocked | LotStatus::UserLocked) => DependencyStatus::Locked,
+            Some(LotStatus::Active) => DependencyStatus::Available,
+        }
+    }
+    /// Locks or unlocks one of the author's own lots. Lots an admin has locked can't be
+    /// changed until the admin lock is cleared.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn set_user_lock(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        lotid: isize,
+        params: LockParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+        if lot.usrid != id {
+            return Err(Error::Forbidden);
+        }
+        if lot.status == LotStatus::AdminLocked {
+            return Err(Error::Forbidden);
+        }
+
+        Lot::set_lock(config, id, lot, LockKind::User, params).await
     }
-    pub(crate) async fn bulk_download(lotid: isize) {
-        todo!()
+    /// Applies or clears an admin lock, which takes precedence over the author's own lock.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn adm_set_lock(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        lotid: isize,
+        params: LockParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_admin(config.clone(), username, password, ip).await?;
+        let lot = config.storage.lot(lotid).await?.ok_or(Error::NotFound)?;
+
+        Lot::set_lock(config, id, lot, LockKind::Admin, params).await
     }
-    pub(crate) async fn get_dependencies_flat(deps: String) {
-        todo!()
+    /// Applies or clears a lock of `kind` on behalf of `usrid`, then records it in the audit
+    /// log and lets the author know. Requests that change nothing are answered with the
+    /// current state.
+    async fn set_lock(
+        config: Arc<Config>,
+        usrid: usize,
+        lot: Lot,
+        kind: LockKind,
+        params: LockParams,
+    ) -> Result<warp::reply::Json> {
+        if lot.status == LotStatus::Inactive {
+            return Err(Error::Gone);
+        }
+        let reason = params.reason.unwrap_or_default().trim().to_string();
+        if params.locked && reason.is_empty() {
+            return Err(Error::InvalidParams(vec![crate::validate::FieldError {
+                field: "reason".into(),
+                message: "reason is required when locking".into(),
+            }]));
+        }
+
+        let lock = params.locked.then(|| LotLock {
+            lotid: lot.lotid,
+            kind,
+            usrid,
+            reason: reason.clone(),
+            date: Some(chrono::Utc::now()),
+        });
+        let changed = config
+            .storage
+            .set_lot_lock(lot.lotid, kind, lock.as_ref())
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        let lot = config.storage.lot(lot.lotid).await?.ok_or(Error::NotFound)?;
+        let locks = config.storage.lot_locks(lot.lotid).await?;
+        let reason_in_effect = match lot.status {
+            LotStatus::AdminLocked => Some(LockKind::Admin),
+            LotStatus::UserLocked => Some(LockKind::User),
+            _ => None,
+        }
+        .and_then(|kind| locks.iter().find(|l| l.kind == kind))
+        .map(|l| l.reason.clone())
+        .filter(|reason| !reason.is_empty());
+        let state = LotLockState {
+            status: lot.status,
+            reason: reason_in_effect,
+        };
+        if !changed {
+            return Ok(warp::reply::json(&state));
+        }
+
+        let (action, described) = match (kind, params.locked) {
+            (LockKind::User, true) => ("lot_lock", "locked"),
+            (LockKind::User, false) => ("lot_unlock", "unlocked"),
+            (LockKind::Admin, true) => ("lot_admin_lock", "locked by the administration"),
+            (LockKind::Admin, false) => ("lot_admin_unlock", "unlocked by the administration"),
+        };
+        Base::audit(config.clone(), usrid, action, AuditTarget::Lot(lot.lotid), reason.clone())
+            .await?;
+
+        // the lock is in place by now, so a failure to tell the author doesn't undo it
+        let notified = async {
+            let author = LEXUser::fetch(&config, lot.usrid).await?;
+            let locale = config.storage.user_locale(author.usrid).await?;
+            crate::email::Email::send_moderation(
+                config.clone(),
+                author.email_address,
+                author.username,
+                locale,
+                &lot,
+                described,
+                reason,
+            )
+            .await
+        }
+        .await;
+        if let Err(e) = notified {
+            log::warn!("Couldn't tell the author of lot {} it was {described}: {e:?}", lot.lotid);
+        }
+
+        Ok(warp::reply::json(&state))
     }
-    pub(crate) async fn get_dependencies(deps: String) -> Result<serde_json::Value> {
-        todo!()
+    /// Formats a size in bytes the way the LEX pages do, e.g. `1.5 MB`.
+    pub fn get_human_filesize(bytes: u64) -> String {
+        const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            format!("{bytes} B")
+        } else {
+            format!("{size:.1} {}", UNITS[unit])
+        }
     }
-    pub(crate) async fn get_dependents(lotid: isize) -> Result<serde_json::Value> {
-        todo!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use mysql_common::{constants::ColumnType, packets::Column};
+
+    /// Reads `columns` back the way a `SELECT * FROM LEX_LOTS` row would be.
+    fn row(columns: Vec<(&'static str, Value)>) -> Row {
+        let (names, values): (Vec<_>, Vec<_>) = columns.into_iter().unzip();
+        let columns = names
+            .iter()
+            .map(|name| Column::new(ColumnType::MYSQL_TYPE_STRING).with_name(name.as_bytes()))
+            .collect::<Vec<_>>();
+
+        mysql_common::row::new_row(values, columns.into())
     }
-    pub(crate) async fn get_dependency_status(dep: String) {
-        todo!()
+
+    #[test]
+    fn columns_read_back_as_the_same_lot() {
+        let day = |y, m, d| Some(chrono::Utc.with_ymd_and_hms(y, m, d, 0, 0, 0).unwrap());
+        let fixtures: serde_json::Value =
+            serde_json::from_str(include_str!("../fixtures/lex.json")).unwrap();
+        let lot = Lot {
+            lotname: "Caf\u{e9} Plaza".into(),
+            lotviews: 12,
+            lotdownloads: 3,
+            lastview: day(2023, 6, 2),
+            lastdownload: day(2023, 6, 1),
+            status: LotStatus::UserLocked,
+            dateon: day(2020, 1, 5),
+            lastupdate: day(2021, 3, 1),
+            olddl: true,
+            ..serde_json::from_value(fixtures["lots"][0].clone()).unwrap()
+        };
+
+        let mut columns = lot.columns();
+        columns.extend(lot.counter_columns());
+        columns.extend(lot.status_columns());
+        // legacy lot dates only hold the day
+        for (name, value) in &columns {
+            if ["DATEON", "LASTUPDATE", "LASTVIEW", "LASTDOWNLOAD"].contains(name) {
+                assert!(matches!(value, Value::Bytes(date) if date.len() == 8), "{name} is {value:?}");
+            }
+        }
+        columns.push(("LOTID", lot.lotid.into()));
+
+        let read = Lot::try_from(row(columns)).unwrap();
+        assert_eq!(serde_json::to_value(read).unwrap(), serde_json::to_value(&lot).unwrap());
     }
-    pub(crate) async fn get_human_filesize(bytes: String) -> String {
-        todo!()
+
+    #[test]
+    fn updates_leave_legacy_status_flags_alone() {
+        let fixtures: serde_json::Value =
+            serde_json::from_str(include_str!("../fixtures/lex.json")).unwrap();
+        let lot: Lot = serde_json::from_value(fixtures["lots"][0].clone()).unwrap();
+
+        // a deleted lot that was also locked by both its author and an admin
+        let mut columns = lot.columns();
+        columns.extend(lot.counter_columns());
+        columns.extend([("ISACTIVE", "F".into()), ("ADMLOCK", "T".into()), ("USRLOCK", "T".into())]);
+        columns.push(("LOTID", lot.lotid.into()));
+        let read = Lot::try_from(row(columns)).unwrap();
+        assert_eq!(read.status, LotStatus::Inactive);
+
+        // writing back the status it reads as would lose the locks, so the flags are left out
+        let written = read.columns();
+        for name in ["ISACTIVE", "ADMLOCK", "USRLOCK"] {
+            assert!(written.iter().all(|(column, _)| *column != name), "{name} is written");
+        }
     }
 }
@@ -0,0 +1,221 @@
+use crate::*;
+use mysql_async::Row;
+use serde::{Deserialize, Serialize};
+
+/// How often the worker looks for messages that are due.
+const QUEUE_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// How many messages the worker delivers per poll.
+const QUEUE_BATCH_SIZE: usize = 50;
+/// Delay before the first retry, doubled after every failed attempt.
+const RETRY_BASE_SECS: i64 = 60;
+/// Longest delay between two attempts.
+const RETRY_MAX_SECS: i64 = 6 * 60 * 60;
+/// Attempts after which a message is marked as failed and no longer retried.
+const MAX_ATTEMPTS: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum MailStatus {
+    Pending,
+    Sent,
+    Failed,
+}
+impl std::str::FromStr for MailStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "P" => Ok(MailStatus::Pending),
+            "S" => Ok(MailStatus::Sent),
+            "F" => Ok(MailStatus::Failed),
+            other => Err(Error::MalformedRow(format!("invalid mail status `{other}`"))),
+        }
+    }
+}
+impl MailStatus {
+    pub fn flag(self) -> &'static str {
+        match self {
+            MailStatus::Pending => "P",
+            MailStatus::Sent => "S",
+            MailStatus::Failed => "F",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct MailQueueParams {
+    pub status: Option<MailStatus>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct QueuedMail {
+    pub id: usize,
+    pub to: String,
+    pub subject: String,
+    #[serde(skip)]
+    pub html: String,
+    #[serde(skip)]
+    pub text: String,
+    pub status: MailStatus,
+    pub attempts: usize,
+    pub last_error: Option<String>,
+    pub next_attempt: Option<chrono::DateTime<chrono::Utc>>,
+    pub created: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for QueuedMail {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let status: String = take_column(&row, "status")?;
+        let nextattempt: Option<String> = take_column(&row, "nextattempt")?;
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+
+        Ok(QueuedMail {
+            id: take_column(&row, "mailid")?,
+            to: take_column(&row, "toemail")?,
+            subject: take_column(&row, "subject")?,
+            html: take_column(&row, "html")?,
+            text: take_column(&row, "text")?,
+            status: status.parse()?,
+            attempts: take_column(&row, "attempts")?,
+            last_error: take_column(&row, "lasterror")?,
+            next_attempt: parse_lex_date(&nextattempt.unwrap_or_default())?,
+            created: parse_lex_date(&dateon.unwrap_or_default())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct PurgedMail {
+    pub purged: u64,
+}
+
+/// Delay before the attempt following `attempts` failed ones.
+fn retry_delay(attempts: usize) -> chrono::Duration {
+    let exponent = attempts.saturating_sub(1).min(31) as u32;
+    let secs = RETRY_BASE_SECS.saturating_mul(2_i64.saturating_pow(exponent));
+
+    chrono::Duration::seconds(secs.min(RETRY_MAX_SECS))
+}
+
+pub struct MailQueue;
+impl MailQueue {
+    pub async fn enqueue(
+        config: Arc<Config>,
+        to_email: String,
+        email: crate::email::RenderedEmail,
+    ) -> Result<()> {
+        config
+            .storage
+            .enqueue_mail(&to_email, &email, chrono::Utc::now())
+            .await
+    }
+
+    /// Runs forever, delivering queued messages as they become due.
+    pub async fn worker(config: Arc<Config>) {
+        let mut interval = tokio::time::interval(QUEUE_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = MailQueue::deliver_due(config.clone()).await {
+                log::warn!("Mail queue run failed: {e:?}");
+            }
+        }
+    }
+
+    pub async fn deliver_due(config: Arc<Config>) -> Result<()> {
+        let due = config
+            .storage
+            .due_mail(chrono::Utc::now(), QUEUE_BATCH_SIZE)
+            .await?;
+
+        for mail in due {
+            let result = async {
+                let message = crate::email::build_message(
+                    &config,
+                    &mail.to,
+                    crate::email::RenderedEmail {
+                        subject: mail.subject.clone(),
+                        html: mail.html.clone(),
+                        text: mail.text.clone(),
+                    },
+                )?;
+                config.mailer.send(message).await
+            }
+            .await;
+
+            match result {
+                Ok(()) => config.storage.set_mail_sent(mail.id).await?,
+                Err(e) => {
+                    let attempts = mail.attempts + 1;
+                    let status = if attempts >= MAX_ATTEMPTS {
+                        MailStatus::Failed
+                    } else {
+                        MailStatus::Pending
+                    };
+                    log::warn!("Mail {} delivery attempt {attempts} failed: {e:?}", mail.id);
+
+                    config
+                        .storage
+                        .set_mail_failed(
+                            mail.id,
+                            status,
+                            attempts,
+                            format!("{e:?}"),
+                            chrono::Utc::now() + retry_delay(attempts),
+                        )
+                        .await?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn adm_get_all(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        params: MailQueueParams,
+    ) -> Result<impl warp::Reply> {
+        Base::get_admin(config.clone(), username, password, ip).await?;
+        let mail = config
+            .storage
+            .mail_by_status(params.status.unwrap_or(MailStatus::Failed))
+            .await?;
+
+        Ok(warp::reply::json(&mail))
+    }
+
+    /// Puts a failed message back in the queue for immediate delivery.
+    pub async fn adm_retry(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        mailid: usize,
+    ) -> Result<impl warp::Reply> {
+        Base::get_admin(config.clone(), username, password, ip).await?;
+        if !config.storage.retry_mail(mailid, chrono::Utc::now()).await? {
+            return Err(Error::NotFound);
+        }
+
+        Ok(warp::reply())
+    }
+
+    /// Deletes every failed message.
+    pub async fn adm_purge(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+    ) -> Result<impl warp::Reply> {
+        Base::get_admin(config.clone(), username, password, ip).await?;
+        let purged = config.storage.purge_failed_mail().await?;
+
+        Ok(warp::reply::json(&PurgedMail { purged }))
+    }
+}
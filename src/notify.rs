@@ -0,0 +1,241 @@
+use crate::*;
+use mysql_async::Row;
+use serde::{Deserialize, Serialize};
+
+/// How often the digest task checks for users that are due a digest.
+const DIGEST_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestFrequency {
+    Daily,
+    Weekly,
+    Never,
+}
+impl std::str::FromStr for DigestFrequency {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "D" => Ok(DigestFrequency::Daily),
+            "W" => Ok(DigestFrequency::Weekly),
+            "N" => Ok(DigestFrequency::Never),
+            other => Err(Error::MalformedRow(format!("invalid digest frequency `{other}`"))),
+        }
+    }
+}
+impl DigestFrequency {
+    pub fn flag(self) -> &'static str {
+        match self {
+            DigestFrequency::Daily => "D",
+            DigestFrequency::Weekly => "W",
+            DigestFrequency::Never => "N",
+        }
+    }
+    pub fn period(self) -> Option<chrono::Duration> {
+        match self {
+            DigestFrequency::Daily => Some(chrono::Duration::days(1)),
+            DigestFrequency::Weekly => Some(chrono::Duration::days(7)),
+            DigestFrequency::Never => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationParams {
+    pub frequency: DigestFrequency,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UnsubscribeParams {
+    #[validate(length(equal = 32))]
+    pub token: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct NotifyPreferences {
+    pub frequency: DigestFrequency,
+}
+
+/// A user due a digest, along with their notification preferences.
+#[derive(Debug, Clone)]
+pub struct DigestRecipient {
+    pub usrid: usize,
+    pub username: String,
+    pub email_address: String,
+    pub locale: Option<String>,
+    pub frequency: DigestFrequency,
+    pub last_sent: Option<chrono::DateTime<chrono::Utc>>,
+    pub token: Option<String>,
+}
+impl TryFrom<Row> for DigestRecipient {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let frequency: Option<String> = take_column(&row, "frequency")?;
+        let lastsent: Option<String> = take_column(&row, "lastsent")?;
+
+        Ok(DigestRecipient {
+            usrid: take_column(&row, "usrid")?,
+            username: take_column(&row, "usrname")?,
+            email_address: take_column(&row, "emailadddr")?,
+            locale: take_column(&row, "locale")?,
+            frequency: frequency
+                .as_deref()
+                .map(str::parse)
+                .transpose()?
+                .unwrap_or(DigestFrequency::Weekly),
+            last_sent: parse_lex_date(&lastsent.unwrap_or_default())?,
+            token: take_column(&row, "unsubtoken")?,
+        })
+    }
+}
+
+/// A comment left on one of the recipient's lots.
+#[derive(Debug, Clone, Serialize)]
+pub struct NewComment {
+    pub lot_id: usize,
+    pub lot_name: String,
+    pub author: String,
+    pub date: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl TryFrom<Row> for NewComment {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+
+        Ok(NewComment {
+            lot_id: take_column(&row, "lotid")?,
+            lot_name: take_column::<String>(&row, "lotname")?.trim().to_string(),
+            author: take_column(&row, "usrname")?,
+            date: parse_lex_date(&dateon.unwrap_or_default())?,
+        })
+    }
+}
+
+pub struct Notify;
+impl Notify {
+    /// Runs forever, sending digests to every user that is due one.
+    pub async fn digest_task(config: Arc<Config>) {
+        let mut interval = tokio::time::interval(DIGEST_CHECK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = Notify::send_due_digests(config.clone()).await {
+                log::warn!("Digest run failed: {e:?}");
+            }
+        }
+    }
+
+    pub async fn send_due_digests(config: Arc<Config>) -> Result<()> {
+        let recipients = config.storage.digest_recipients(chrono::Utc::now()).await?;
+
+        for recipient in recipients {
+            if let Err(e) = Notify::send_digest(config.clone(), recipient).await {
+                log::warn!("Digest delivery failed: {e:?}");
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn send_digest(config: Arc<Config>, recipient: DigestRecipient) -> Result<()> {
+        let now = chrono::Utc::now();
+        let since = recipient
+            .last_sent
+            .or_else(|| recipient.frequency.period().map(|p| now - p))
+            .unwrap_or(now);
+
+        let updates = config
+            .storage
+            .downloads(
+                recipient.usrid,
+                DownloadFilter::Outdated,
+                &DownloadParams {
+                    order: Some(SortOrder::Desc),
+                    ..Default::default()
+                },
+            )
+            .await?
+            .into_iter()
+            .map(AvailableUpdate::from)
+            // lots only record the day they were updated, so the day of the last digest counts
+            .filter(|u| u.update_date.is_some_and(|d| d.date_naive() >= since.date_naive()))
+            .collect::<Vec<_>>();
+
+        let comments = config.storage.new_comments(recipient.usrid, since).await?;
+
+        let token = match recipient.token.clone() {
+            Some(token) => token,
+            None => Notify::create_token(&config, recipient.usrid, recipient.frequency).await?,
+        };
+
+        if !updates.is_empty() || !comments.is_empty() {
+            crate::email::Email::send_digest(
+                config.clone(),
+                recipient.email_address,
+                recipient.username,
+                recipient.locale,
+                updates,
+                comments,
+                token,
+            )
+            .await?;
+        }
+
+        config.storage.set_digest_sent(recipient.usrid, now).await
+    }
+
+    /// Creates the preferences row for `usrid` if it's missing, returning its unsubscribe token.
+    async fn create_token(
+        config: &Config,
+        usrid: usize,
+        frequency: DigestFrequency,
+    ) -> Result<String> {
+        let token = uuid::Uuid::new_v4().simple().to_string();
+
+        config.storage.notify_token(usrid, frequency, &token).await
+    }
+
+    pub async fn get_preferences(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let frequency = config
+            .storage
+            .digest_frequency(id)
+            .await?
+            .unwrap_or(DigestFrequency::Weekly);
+
+        Ok(warp::reply::json(&NotifyPreferences { frequency }))
+    }
+
+    pub async fn set_preferences(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        frequency: DigestFrequency,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        Notify::create_token(&config, id, frequency).await?;
+        config.storage.set_digest_frequency(id, frequency).await?;
+
+        Ok(warp::reply::json(&NotifyPreferences { frequency }))
+    }
+
+    pub async fn unsubscribe(config: Arc<Config>, token: String) -> Result<impl warp::Reply> {
+        if !config.storage.unsubscribe(&token).await? {
+            return Err(Error::Forbidden);
+        }
+
+        Ok(warp::reply())
+    }
+}
@@ -0,0 +1,160 @@
+use crate::*;
+use utoipa::{
+    openapi::{
+        path::Operation,
+        security::{Http, HttpAuthScheme, SecurityScheme},
+        ContentBuilder, Ref, ResponseBuilder,
+    },
+    Modify, OpenApi,
+};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "LEX API", description = "API for the SC4Devotion LEX file exchange."),
+    paths(
+        routes::get_endpoints,
+        routes::get_openapi,
+        routes::get_version,
+        routes::get_user,
+        routes::adm_get_all,
+        routes::adm_get_user,
+        routes::adm_set_active,
+        routes::adm_set_role,
+        routes::adm_set_level,
+        routes::adm_ban_user,
+        routes::get_download_history,
+        routes::get_download_list,
+        routes::get_updates,
+        routes::get_notifications,
+        routes::put_notifications,
+        routes::get_unsubscribe,
+        routes::post_register_user,
+        routes::get_activate_user,
+        routes::adm_get_ipbans,
+        routes::adm_add_ipban,
+        routes::adm_edit_ipban,
+        routes::adm_remove_ipban,
+        routes::adm_get_mail_queue,
+        routes::adm_retry_mail,
+        routes::adm_purge_mail,
+        routes::get_all_lots,
+        routes::get_lot_http,
+        routes::upload_lot,
+        routes::update_lot,
+        routes::get_lot_versions,
+        routes::get_version_download,
+        routes::set_lot_lock,
+        routes::adm_set_lot_lock,
+        routes::get_download,
+        routes::do_download_list,
+        routes::bulk_download,
+        routes::delete_download_list,
+        routes::get_comment_http,
+        routes::post_comment,
+        routes::get_vote_http,
+        routes::get_lot_dependency,
+        routes::get_dependency_string,
+        routes::update_dependency_string,
+        routes::do_search,
+        routes::get_broad_category,
+        routes::get_lex_category,
+        routes::get_lex_type,
+        routes::get_group,
+        routes::get_author,
+        routes::get_all_categories,
+    ),
+    components(schemas(ErrorBody)),
+    modifiers(&CommonResponses)
+)]
+struct ApiDoc;
+
+/// Adds what every operation shares: basic auth and the `ErrorBody` error response.
+struct CommonResponses;
+impl Modify for CommonResponses {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "basic",
+                SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)),
+            );
+        }
+
+        let error = ResponseBuilder::new()
+            .description("Error, see `code` for the reason")
+            .content(
+                "application/json",
+                ContentBuilder::new()
+                    .schema(Some(Ref::from_schema_name("ErrorBody")))
+                    .build(),
+            )
+            .build();
+
+        for item in openapi.paths.paths.values_mut() {
+            for (_, operation) in operations(item) {
+                operation
+                    .responses
+                    .responses
+                    .insert("default".into(), error.clone().into());
+            }
+        }
+    }
+}
+
+pub(crate) fn operations(
+    item: &mut utoipa::openapi::PathItem,
+) -> impl Iterator<Item = (&'static str, &mut Operation)> {
+    [
+        ("GET", item.get.as_mut()),
+        ("POST", item.post.as_mut()),
+        ("PUT", item.put.as_mut()),
+        ("DELETE", item.delete.as_mut()),
+        ("PATCH", item.patch.as_mut()),
+    ]
+    .into_iter()
+    .filter_map(|(method, operation)| operation.map(|o| (method, o)))
+}
+
+/// The OpenAPI document, with paths relative to the `/api` mount point.
+pub(crate) fn spec() -> utoipa::openapi::OpenApi {
+    ApiDoc::openapi()
+}
+
+/// The legacy endpoint index served at `/api/`: `{tag: {path: "(METHOD) summary, ..."}}`.
+pub(crate) fn endpoint_index(spec: &utoipa::openapi::OpenApi) -> serde_json::Value {
+    let mut index = serde_json::Map::new();
+
+    for (path, item) in spec.paths.paths.clone() {
+        let mut item = item;
+        // `{lotid}` placeholders are written as `:lotid` in the index
+        let path = path.replace('{', ":").replace('}', "");
+
+        for (method, operation) in operations(&mut item) {
+            let tag = operation
+                .tags
+                .as_ref()
+                .and_then(|t| t.first().cloned())
+                .unwrap_or_else(|| "basic".into());
+            let summary = operation.summary.clone().unwrap_or_default();
+            let summary = match summary.chars().next() {
+                Some(first) => first.to_lowercase().chain(summary.chars().skip(1)).collect(),
+                None => summary,
+            };
+
+            let section = index
+                .entry(tag)
+                .or_insert_with(|| serde_json::Value::Object(Default::default()));
+            if let Some(section) = section.as_object_mut() {
+                let entry = section
+                    .entry(path.clone())
+                    .or_insert_with(|| serde_json::Value::String(String::new()));
+                let description = match entry.as_str() {
+                    Some("") | None => format!("({method}) {summary}"),
+                    Some(existing) => format!("{existing}, ({method}) {summary}"),
+                };
+                *entry = serde_json::Value::String(description);
+            }
+        }
+    }
+
+    serde_json::Value::Object(index)
+}
@@ -1,228 +1,922 @@
 use crate::*;
 
+/// Retrieves all endpoints for this API
+#[utoipa::path(
+    get,
+    path = "/",
+    tag = "basic",
+    responses((status = 200, description = "Endpoints grouped by section, as `{section: {path: description}}`"))
+)]
 pub(crate) async fn get_endpoints() -> Result<impl warp::Reply> {
-    let response = serde_json::json!({
-        "basic": {
-            "/": "(GET) retrieves all endpoints for this API",
-            "/version": "(GET) retrieves the current version of this API"
-        },
-        "user": {
-            "/user": "(GET) retrieves profile information for the user",
-            "/user/download-history" : "(GET) retrieves download history for the user",
-            "/user/download-list" : "(GET) retrieves download list for the user",
-            "/user/register" : "(POST) registers a new user for the LEX",
-            "/user/activate" : "(GET) activates the registration for a LEX user"
-        },
-        "lot": {
-            "/lot/all" : "(GET) retrieves a list of all lots",
-            "/lot/:lotid" : "(GET) retrieves information about the lot with the supplied ID"
-        },
-        "search": {
-            "/search" : "(GET) retrieves search results"
-        },
-        "interaction": {
-            "/lot/:lotid/download" : "(POST) retrieves a download link for the lot with the supplied ID - also adds it to download history",
-            "/lot/:lotid/download-list" : "(POST) adds the lot with the supplied ID to the download-later list"
-        }
-    });
-
-    Ok(warp::reply::json(&response))
-}
-pub(crate) async fn get_version(config: Arc<Config>) -> Result<impl warp::Reply> {
-    let response = serde_json::json!({
-        "version": config.api_version,
-        "type": "public"
-    });
-
-    Ok(warp::reply::json(&response))
+    Ok(warp::reply::json(&openapi::endpoint_index(&openapi::spec())))
 }
+/// Retrieves the OpenAPI 3 document for this API
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "basic",
+    responses((status = 200, description = "The OpenAPI document"))
+)]
+pub(crate) async fn get_openapi(version: ApiVersion) -> Result<impl warp::Reply> {
+    let mut spec = openapi::spec();
+    spec.info.version = version.to_string();
+    spec.servers = Some(vec![utoipa::openapi::Server::new(format!("/api/{version}"))]);
+
+    Ok(warp::reply::json(&spec))
+}
+/// Retrieves the current version of this API
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "basic",
+    responses((status = 200, description = "The API version, along with every supported version and its deprecation status", body = VersionInfo))
+)]
+pub(crate) async fn get_version(config: Arc<Config>, version: ApiVersion) -> Result<impl warp::Reply> {
+    let versions = ApiVersion::ALL
+        .iter()
+        .map(|&v| VersionStatus {
+            version: v,
+            deprecated: v.is_deprecated(),
+            sunset: config.api_sunset.filter(|_| v.is_deprecated()),
+        })
+        .collect();
+
+    Ok(warp::reply::json(&VersionInfo {
+        version,
+        kind: "public",
+        latest: ApiVersion::LATEST,
+        versions,
+    }))
+}
+/// Retrieves profile information for the user
+#[utoipa::path(
+    get,
+    path = "/user",
+    tag = "user",
+    responses((status = 200, description = "The user's profile", body = UserProfile)),
+    security(("basic" = []))
+)]
 pub(crate) async fn get_user(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
+    version: ApiVersion,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    LEXUser::get_user(config, username, password, ip, None).await
+    LEXUser::get_user(config, username, password, ip, None, version).await
 }
+/// Searches, filters and sorts all users (admin)
+#[utoipa::path(
+    get,
+    path = "/user/all",
+    tag = "user",
+    params(UserSearchParams),
+    responses((status = 200, description = "The matching users, as `{id, username}` if `concise` is set", body = UserList<UserProfile>)),
+    security(("basic" = []))
+)]
 pub(crate) async fn adm_get_all(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
-    query: serde_json::Value,
+    query: UserSearchParams,
+    version: ApiVersion,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    LEXUser::adm_get_all(
-        config,
-        username,
-        password,
-        ip,
-        query
-            .get("rows_offset")
-            .and_then(|q| q.as_u64())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("rows_count")
-            .and_then(|q| q.as_u64())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("concise")
-            .and_then(|q| q.as_bool())
-            .ok_or(Error::MalformedRequest)?,
-    )
-    .await
+    LEXUser::adm_get_all(config, username, password, ip, query, version).await
 }
+/// Retrieves profile information for any user (admin)
+#[utoipa::path(
+    get,
+    path = "/user/{usrid}",
+    tag = "user",
+    params(("usrid" = usize, Path, description = "User ID")),
+    responses((status = 200, description = "The user's profile", body = UserProfile)),
+    security(("basic" = []))
+)]
 pub(crate) async fn adm_get_user(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
     usrid: usize,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    LEXUser::adm_get_user(config, username, password, ip, usrid, version).await
+}
+/// Activates or deactivates a user (admin)
+#[utoipa::path(
+    put,
+    path = "/user/{usrid}/status",
+    tag = "user",
+    params(
+        ("usrid" = usize, Path, description = "User ID"),
+        StatusParams,
+    ),
+    responses((status = 200, description = "The status was changed")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_set_active(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    usrid: usize,
+    query: StatusParams,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    LEXUser::adm_set_active(config, username, password, ip, usrid, query.active, version).await
+}
+/// Grants or revokes a user role (admin)
+#[utoipa::path(
+    put,
+    path = "/user/{usrid}/role",
+    tag = "user",
+    params(
+        ("usrid" = usize, Path, description = "User ID"),
+        RoleParams,
+    ),
+    responses((status = 200, description = "The role was changed")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_set_role(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    usrid: usize,
+    query: RoleParams,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    LEXUser::adm_set_role(config, username, password, ip, usrid, query.role, query.value, version).await
+}
+/// Changes the user level of a user (admin)
+#[utoipa::path(
+    put,
+    path = "/user/{usrid}/level",
+    tag = "user",
+    params(
+        ("usrid" = usize, Path, description = "User ID"),
+        LevelParams,
+    ),
+    responses((status = 200, description = "The level was changed")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_set_level(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    usrid: usize,
+    query: LevelParams,
+    version: ApiVersion,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    LEXUser::adm_get_user(config, username, password, ip, usrid).await
+    LEXUser::adm_set_level(config, username, password, ip, usrid, query.usrlvl, version).await
 }
+/// Deactivates a user and bans their IPs (admin)
+#[utoipa::path(
+    post,
+    path = "/user/{usrid}/ban",
+    tag = "user",
+    params(("usrid" = usize, Path, description = "User ID")),
+    responses((status = 200, description = "The user was banned")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_ban_user(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    usrid: usize,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    LEXUser::adm_ban(config, username, password, ip, usrid, version).await
+}
+/// Retrieves download history for the user
+#[utoipa::path(
+    get,
+    path = "/user/download-history",
+    tag = "user",
+    params(DownloadParams),
+    responses((status = 200, description = "Downloaded lots", body = Vec<DownloadRecord>)),
+    security(("basic" = []))
+)]
 pub(crate) async fn get_download_history(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
+    query: DownloadParams,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    LEXUser::get_download_history(config, username, password, ip).await
+    LEXUser::get_download_history(config, username, password, ip, query).await
 }
+/// Retrieves download list for the user
+#[utoipa::path(
+    get,
+    path = "/user/download-list",
+    tag = "user",
+    params(DownloadParams),
+    responses((status = 200, description = "Lots on the download-later list", body = Vec<DownloadRecord>)),
+    security(("basic" = []))
+)]
 pub(crate) async fn get_download_list(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
+    query: DownloadParams,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    LEXUser::get_download_list(config, username, password, ip).await
+    LEXUser::get_download_list(config, username, password, ip, query).await
 }
-pub(crate) async fn post_register_user(
+/// Retrieves downloaded lots that have been updated since they were last downloaded
+#[utoipa::path(
+    get,
+    path = "/user/updates",
+    tag = "user",
+    params(DownloadParams),
+    responses((status = 200, description = "Outdated downloads", body = Vec<AvailableUpdate>)),
+    security(("basic" = []))
+)]
+pub(crate) async fn get_updates(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    query: DownloadParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    LEXUser::get_updates(config, username, password, ip, query).await
+}
+/// Retrieves the email digest frequency for the user
+#[utoipa::path(
+    get,
+    path = "/user/notifications",
+    tag = "user",
+    responses((status = 200, description = "The digest frequency", body = NotifyPreferences)),
+    security(("basic" = []))
+)]
+pub(crate) async fn get_notifications(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
-    query: serde_json::Value,
 ) -> Result<impl warp::Reply> {
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Notify::get_preferences(config, username, password, ip).await
+}
+/// Sets the email digest frequency for the user
+#[utoipa::path(
+    put,
+    path = "/user/notifications",
+    tag = "user",
+    params(NotificationParams),
+    responses((status = 200, description = "The digest frequency", body = NotifyPreferences)),
+    security(("basic" = []))
+)]
+pub(crate) async fn put_notifications(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    query: NotificationParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Notify::set_preferences(config, username, password, ip, query.frequency).await
+}
+/// Unsubscribes from email digests using the token in the email
+#[utoipa::path(
+    get,
+    path = "/user/unsubscribe",
+    tag = "user",
+    params(UnsubscribeParams),
+    responses((status = 200, description = "Unsubscribed"))
+)]
+pub(crate) async fn get_unsubscribe(
+    config: Arc<Config>,
+    query: UnsubscribeParams,
+) -> Result<impl warp::Reply> {
+    Notify::unsubscribe(config, query.token).await
+}
+/// Registers a new user for the LEX
+#[utoipa::path(
+    post,
+    path = "/user/register",
+    tag = "user",
+    params(RegisterParams),
+    responses((status = 200, description = "The user was registered and an activation email queued"))
+)]
+pub(crate) async fn post_register_user(
+    config: Arc<Config>,
+    _headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    query: RegisterParams,
+) -> Result<impl warp::Reply> {
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
     LEXUser::register_user(
-        query
-            .get("username")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("password_1")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("password_2")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("email")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
-        query
-            .get("fullname")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
+        query.username,
+        query.password_1,
+        query.password_2,
+        query.email,
+        query.fullname,
+        query.locale,
         config,
         ip,
     )
     .await
 }
+/// Activates the registration for a LEX user
+#[utoipa::path(
+    get,
+    path = "/user/activate",
+    tag = "user",
+    params(ActivateParams),
+    responses((status = 200, description = "The user was activated"))
+)]
 pub(crate) async fn get_activate_user(
     config: Arc<Config>,
-    query: serde_json::Value,
+    query: ActivateParams,
+) -> Result<impl warp::Reply> {
+    LEXUser::activate_user(config, query.activation_key).await
+}
+/// Lists all IP bans (admin)
+#[utoipa::path(
+    get,
+    path = "/ipban",
+    tag = "ipban",
+    responses((status = 200, description = "Every IP ban", body = Vec<IpBan>)),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_get_ipbans(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    IpBan::adm_get_all(config, username, password, ip).await
+}
+/// Adds an IP ban (admin)
+#[utoipa::path(
+    post,
+    path = "/ipban",
+    tag = "ipban",
+    params(IpBanParams),
+    responses((status = 200, description = "The id of the new ban", body = IpBanCreated)),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_add_ipban(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    query: IpBanParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    IpBan::adm_add(config, username, password, ip, query).await
+}
+/// Edits the IP ban with the supplied ID (admin)
+#[utoipa::path(
+    put,
+    path = "/ipban/{banid}",
+    tag = "ipban",
+    params(
+        ("banid" = usize, Path, description = "IP ban ID"),
+        IpBanParams,
+    ),
+    responses((status = 200, description = "The ban was changed")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_edit_ipban(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    banid: usize,
+    query: IpBanParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    IpBan::adm_edit(config, username, password, ip, banid, query).await
+}
+/// Removes the IP ban with the supplied ID (admin)
+#[utoipa::path(
+    delete,
+    path = "/ipban/{banid}",
+    tag = "ipban",
+    params(("banid" = usize, Path, description = "IP ban ID")),
+    responses((status = 200, description = "The ban was removed")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_remove_ipban(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    banid: usize,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    IpBan::adm_remove(config, username, password, ip, banid).await
+}
+/// Lists queued emails by status, failed by default (admin)
+#[utoipa::path(
+    get,
+    path = "/mail-queue",
+    tag = "mail-queue",
+    params(MailQueueParams),
+    responses((status = 200, description = "Queued emails", body = Vec<QueuedMail>)),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_get_mail_queue(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    query: MailQueueParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    MailQueue::adm_get_all(config, username, password, ip, query).await
+}
+/// Queues a failed email for immediate delivery (admin)
+#[utoipa::path(
+    post,
+    path = "/mail-queue/{mailid}/retry",
+    tag = "mail-queue",
+    params(("mailid" = usize, Path, description = "Queued email ID")),
+    responses((status = 200, description = "The email was queued")),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_retry_mail(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    mailid: usize,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    MailQueue::adm_retry(config, username, password, ip, mailid).await
+}
+/// Purges failed emails (admin)
+#[utoipa::path(
+    delete,
+    path = "/mail-queue",
+    tag = "mail-queue",
+    responses((status = 200, description = "How many emails were purged", body = PurgedMail)),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_purge_mail(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
 ) -> Result<impl warp::Reply> {
-    LEXUser::activate_user(
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    MailQueue::adm_purge(config, username, password, ip).await
+}
+/// Retrieves a page of all lots
+#[utoipa::path(
+    get,
+    path = "/lot/all",
+    tag = "lot",
+    params(
+        LotListParams,
+        ("If-None-Match" = Option<String>, Header, description = "`ETag` of a previous response"),
+        ("If-Modified-Since" = Option<String>, Header, description = "`Last-Modified` of a previous response"),
+    ),
+    responses(
+        (status = 200, description = "A page of available lots", body = LotList),
+        (status = 304, description = "No lot changed since the previous response"),
+    )
+)]
+pub(crate) async fn get_all_lots(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    query: LotListParams,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let header = |name: warp::http::header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+
+    Lot::get_all(
         config,
-        query
-            .get("activation_key")
-            .and_then(|q| q.as_str())
-            .map(|q| q.to_string())
-            .ok_or(Error::MalformedRequest)?,
+        query,
+        header(warp::http::header::IF_NONE_MATCH),
+        header(warp::http::header::IF_MODIFIED_SINCE),
+        version,
     )
     .await
 }
-pub(crate) async fn get_all_lots() -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves information about the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}",
+    tag = "lot",
+    params(
+        ("lotid" = isize, Path, description = "Lot ID"),
+        LotParams,
+    ),
+    responses(
+        (status = 200, description = "The lot", body = LotView),
+        (status = 404, description = "No lot has this ID", body = ErrorBody),
+        (status = 410, description = "The lot has been deleted", body = ErrorBody),
+    ),
+    security((), ("basic" = []))
+)]
+pub(crate) async fn get_lot_http(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+    query: LotParams,
+    version: ApiVersion,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::get_lot_http(config, username, password, ip, lotid, query, version).await
+}
+/// Uploads a new lot, which stays uncertified until an admin certifies it (uploaders only)
+#[utoipa::path(
+    post,
+    path = "/lot",
+    tag = "lot",
+    request_body(
+        content = LotUploadParams,
+        content_type = "multipart/form-data",
+        description = "The lot's details, along with the lot as a `file` part and up to three `image` parts",
+    ),
+    responses(
+        (status = 200, description = "The lot was uploaded", body = LotCreated),
+        (status = 400, description = "A field, the file or an image is invalid", body = ErrorBody),
+        (status = 413, description = "The request body is too large", body = ErrorBody),
+    ),
+    security(("basic" = []))
+)]
+pub(crate) async fn upload_lot(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    form: warp::multipart::FormData,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::upload(config, username, password, ip, form).await
+}
+/// Publishes a new version of the lot with the supplied ID (author or admin)
+#[utoipa::path(
+    put,
+    path = "/lot/{lotid}",
+    tag = "lot",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    request_body(
+        content = LotUpdateParams,
+        content_type = "multipart/form-data",
+        description = "The new version and changelog, any details to change, and optionally a new `file` part and up to three `image` parts",
+    ),
+    responses(
+        (status = 200, description = "The version was published", body = LotVersion),
+        (status = 400, description = "A field, the file or an image is invalid", body = ErrorBody),
+        (status = 410, description = "The lot has been deleted", body = ErrorBody),
+        (status = 413, description = "The request body is too large", body = ErrorBody),
+    ),
+    security(("basic" = []))
+)]
+pub(crate) async fn update_lot(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+    form: warp::multipart::FormData,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::update(config, username, password, ip, lotid, form).await
+}
+/// Lists the versions of the lot with the supplied ID, newest first
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/versions",
+    tag = "lot",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses(
+        (status = 200, description = "The lot's versions", body = [LotVersion]),
+        (status = 410, description = "The lot has been deleted", body = ErrorBody),
+    )
+)]
+pub(crate) async fn get_lot_versions(config: Arc<Config>, lotid: isize) -> Result<impl warp::Reply> {
+    Lot::get_versions(config, lotid).await
+}
+/// Retrieves a download link for a version of the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/versions/{revid}/download",
+    tag = "interaction",
+    params(
+        ("lotid" = isize, Path, description = "Lot ID"),
+        ("revid" = usize, Path, description = "Version ID"),
+    ),
+    responses(
+        (status = 200, description = "Link to the version's file", body = DownloadLink),
+        (status = 403, description = "The author doesn't allow earlier versions to be downloaded", body = ErrorBody),
+    ),
+    security(("basic" = []))
+)]
+pub(crate) async fn get_version_download(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+    revid: usize,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::get_version_download(config, username, password, ip, lotid, revid).await
+}
+/// Locks or unlocks one of the user's own lots, unless an admin has locked it
+#[utoipa::path(
+    put,
+    path = "/lot/{lotid}/lock",
+    tag = "lot",
+    params(
+        ("lotid" = isize, Path, description = "Lot ID"),
+        LockParams,
+    ),
+    responses((status = 200, description = "The lock was changed", body = LotLockState)),
+    security(("basic" = []))
+)]
+pub(crate) async fn set_lot_lock(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+    query: LockParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::set_user_lock(config, username, password, ip, lotid, query).await
 }
-pub(crate) async fn get_lot_http(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Applies or clears an admin lock on a lot (admin)
+#[utoipa::path(
+    put,
+    path = "/lot/{lotid}/admin-lock",
+    tag = "lot",
+    params(
+        ("lotid" = isize, Path, description = "Lot ID"),
+        LockParams,
+    ),
+    responses((status = 200, description = "The lock was changed", body = LotLockState)),
+    security(("basic" = []))
+)]
+pub(crate) async fn adm_set_lot_lock(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+    query: LockParams,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::adm_set_lock(config, username, password, ip, lotid, query).await
 }
-pub(crate) async fn get_download(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves a download link for the lot with the supplied ID - also adds it to download history
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/download",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 200, description = "Link to the file", body = DownloadLink)),
+    security(("basic" = []))
+)]
+pub(crate) async fn get_download(
+    config: Arc<Config>,
+    headers: warp::hyper::HeaderMap,
+    remote: Option<std::net::SocketAddr>,
+    lotid: isize,
+) -> Result<impl warp::Reply> {
+    let (username, password) = get_auth_from_headers(headers);
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
+
+    Lot::get_download(config, username, password, ip, lotid).await
 }
-pub(crate) async fn do_download_list(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Adds the lot with the supplied ID to the download-later list
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/download-list",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody)),
+    security(("basic" = []))
+)]
+pub(crate) async fn do_download_list(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn bulk_download(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Adds the dependencies of the lot with the supplied ID to the download-later list
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/bulk-dependency",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody)),
+    security(("basic" = []))
+)]
+pub(crate) async fn bulk_download(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn delete_download_list(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Removes the lot with the supplied ID from the download-later list
+#[utoipa::path(
+    delete,
+    path = "/lot/{lotid}/download-list",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody)),
+    security(("basic" = []))
+)]
+pub(crate) async fn delete_download_list(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn get_comment_http(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves the comments on the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/comment",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody))
+)]
+pub(crate) async fn get_comment_http(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn post_comment(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Comments on the lot with the supplied ID
+#[utoipa::path(
+    post,
+    path = "/lot/{lotid}/comment",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody)),
+    security(("basic" = []))
+)]
+pub(crate) async fn post_comment(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn get_vote_http(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves the votes on the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/vote",
+    tag = "interaction",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody))
+)]
+pub(crate) async fn get_vote_http(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn get_lot_dependency(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves the dependencies of the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/dependency",
+    tag = "lot",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 200, description = "The lot's dependencies and their status", body = [LotDependency]))
+)]
+pub(crate) async fn get_lot_dependency(config: Arc<Config>, lotid: isize) -> Result<impl warp::Reply> {
+    Lot::get_lot_dependency(config, lotid).await
 }
-pub(crate) async fn get_dependency_string(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Retrieves the raw dependency string of the lot with the supplied ID
+#[utoipa::path(
+    get,
+    path = "/lot/{lotid}/dependency-string",
+    tag = "lot",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody))
+)]
+pub(crate) async fn get_dependency_string(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
-pub(crate) async fn update_dependency_string(lot: String) -> Result<impl warp::Reply> {
-    Ok(warp::reply())
+/// Updates the dependency string of the lot with the supplied ID
+#[utoipa::path(
+    put,
+    path = "/lot/{lotid}/dependency-string",
+    tag = "lot",
+    params(("lotid" = isize, Path, description = "Lot ID")),
+    responses((status = 501, description = "Not implemented yet", body = ErrorBody)),
+    security(("basic" = []))
+)]
+pub(crate) async fn update_dependency_string(_lotid: isize) -> Result<warp::reply::Json> {
+    Err(Error::NotImplemented)
 }
+/// Retrieves search results
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "search",
+    params(SearchParams),
+    responses((status = 200, description = "Matching lots, as `{id, name}` if `concise` is set", body = Vec<LotView>)),
+    security(("basic" = []))
+)]
 pub(crate) async fn do_search(
     config: Arc<Config>,
     headers: warp::hyper::HeaderMap,
     remote: Option<std::net::SocketAddr>,
     query: SearchParams,
+    version: ApiVersion,
 ) -> Result<impl warp::Reply> {
     let (username, password) = get_auth_from_headers(headers);
-    let ip = remote.ok_or(Error::Forbidden)?.to_string();
+    let ip = remote.ok_or(Error::Forbidden)?.ip().to_string();
 
-    Search::do_search(config, username, password, ip, query).await
+    Search::do_search(config, username, password, ip, query, version).await
 }
+/// Retrieves all broad categories
+#[utoipa::path(
+    get,
+    path = "/category/broad-category",
+    tag = "category",
+    responses((status = 200, description = "Broad categories", body = Vec<BroadCategory>))
+)]
 pub(crate) async fn get_broad_category(config: Arc<Config>) -> Result<impl warp::Reply> {
     Ok(warp::reply::json(
         &Category::get_broad_category(config).await?,
     ))
 }
+/// Retrieves all LEX categories
+#[utoipa::path(
+    get,
+    path = "/category/lex-category",
+    tag = "category",
+    responses((status = 200, description = "LEX categories", body = Vec<LexCategory>))
+)]
 pub(crate) async fn get_lex_category(config: Arc<Config>) -> Result<impl warp::Reply> {
     Ok(warp::reply::json(&Category::get_lex_category(config).await?))
 }
+/// Retrieves all LEX types
+#[utoipa::path(
+    get,
+    path = "/category/lex-type",
+    tag = "category",
+    responses((status = 200, description = "LEX types", body = Vec<LexType>))
+)]
 pub(crate) async fn get_lex_type(config: Arc<Config>) -> Result<impl warp::Reply> {
     Ok(warp::reply::json(&Category::get_lex_type(config).await?))
 }
+/// Retrieves all lot groups
+#[utoipa::path(
+    get,
+    path = "/category/group",
+    tag = "category",
+    responses((status = 200, description = "Lot groups", body = Vec<Group>))
+)]
 pub(crate) async fn get_group(config: Arc<Config>) -> Result<impl warp::Reply> {
     Ok(warp::reply::json(&Category::get_group(config).await?))
 }
+/// Retrieves all authors
+#[utoipa::path(
+    get,
+    path = "/category/author",
+    tag = "category",
+    responses((status = 200, description = "Authors", body = Vec<Author>))
+)]
 pub(crate) async fn get_author(config: Arc<Config>) -> Result<impl warp::Reply> {
     Ok(warp::reply::json(&Category::get_author(config).await?))
 }
+/// Retrieves every category list at once
+#[utoipa::path(
+    get,
+    path = "/category/all",
+    tag = "category",
+    responses((status = 200, description = "Every category", body = CategoryIndex))
+)]
 pub(crate) async fn get_all_categories(config: Arc<Config>) -> Result<impl warp::Reply> {
     Category::get_all(config).await
 }
@@ -1,14 +1,14 @@
 use crate::*;
 use futures::future;
 use md5::Digest;
-use mysql_async::prelude::{Query, WithParams};
+use mysql_async::Value;
 use serde::{Deserialize, Serialize};
 
-pub(crate) struct Search {}
+pub struct Search {}
 
 impl Search {
-    pub(crate) fn build_query(params: SearchParams) -> Result<String> {
-        // bail if no filtering params present
+    /// Fails unless at least one of the filtering params is present.
+    pub fn check_filters(params: &SearchParams) -> Result<()> {
         if params.creator.is_none()
             && params.broad_category.is_none()
             && params.lex_category.is_none()
@@ -23,238 +23,156 @@ impl Search {
             return Err(Error::MalformedRequest);
         }
 
-        let creator = if let Some(c) = params.creator {
-            if &c != "Select" && c.parse::<usize>().is_ok() {
-                Some(format!("USRID = {}", c.parse::<usize>().unwrap()))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
-
-        let broad_category = if let Some(bc) = params.broad_category {
-            if &bc != "Select" {
-                Some(format!("MAXISCAT = {}", bc))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        Ok(())
+    }
 
-        let lex_category = if let Some(lc) = params.lex_category {
-            if &lc != "Select" && lc.parse::<usize>().is_ok() {
-                Some(format!("CATID = {}", lc.parse::<usize>().unwrap()))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+    /// The broad category images that make up a broad type.
+    pub fn broad_type_images(broad_type: &str) -> Option<&'static [&'static str]> {
+        match broad_type {
+            "lotbat" => Some(&[
+                "250_MX_Agric.gif",
+                "250_MX_Civic.gif",
+                "250_MX_Comm.gif",
+                "250_MX_Ind.gif",
+                "250_MX_Lark.gif",
+                "250_MX_Parks.gif",
+                "250_MX_Res.gif",
+                "250_MX_Reward.gif",
+                "250_MX_Transport.gif",
+                "250_MX_Utility.gif",
+                "250_MXC_WFK-Canals.gif",
+                "250_MXC_Military.gif",
+            ]),
+            "dependency" => Some(&["250_MXC_Dependency.gif"]),
+            "map" => Some(&["250_MXC_Maps.gif"]),
+            "mod" => Some(&["250_MXC_Modd.gif"]),
+            "other" => Some(&["250_MXC_Tools.gif", "250_MXC_FilesDocs.gif"]),
+            _ => None,
+        }
+    }
 
-        let lex_type = if let Some(lt) = params.lex_type {
-            if &lt != "Select" && lt.parse::<usize>().is_ok() {
-                Some(format!("TYPEID = {}", lt.parse::<usize>().unwrap()))
-            } else {
-                None
+    /// Builds the lot query for `params`, along with its named parameters.
+    pub fn build_query(params: SearchParams) -> Result<(String, Vec<(String, Value)>)> {
+        // bail if no filtering params present
+        Search::check_filters(&params)?;
+
+        let mut clauses = vec!["ISACTIVE = 'T'".to_string()];
+        let mut values: Vec<(String, Value)> = Vec::new();
+
+        // "Select" is what the PHP forms sent for an unset filter
+        let set = |value: Option<String>| value.filter(|v| v != "Select" && !v.is_empty());
+        let id = |value: Option<String>| set(value).and_then(|v| v.parse::<usize>().ok());
+
+        for (column, value) in [
+            ("USRID", id(params.creator)),
+            ("CATID", id(params.lex_category)),
+            ("TYPEID", id(params.lex_type)),
+            ("LOTGROUP", id(params.group)),
+        ] {
+            if let Some(value) = value {
+                clauses.push(format!("{column} = :{}", column.to_lowercase()));
+                values.push((column.to_lowercase(), value.into()));
             }
-        } else {
-            None
-        };
+        }
 
-        let broad_type = if let Some(bt) = params.broad_type {
-            if &bt != "Select" {
-                match bt.as_str() {
-                    "lotbat" => Some("MAXISCAT IN ('250_MX_Agric.gif','250_MX_Civic.gif','250_MX_Comm.gif','250_MX_Ind.gif','250_MX_Lark.gif','250_MX_Parks.gif','250_MX_Res.gif','250_MX_Reward.gif','250_MX_Transport.gif','250_MX_Utility.gif','250_MXC_WFK-Canals.gif','250_MXC_Military.gif')"),
-                    "dependency" => Some("MAXISCAT = '250_MXC_Dependency.gif'"),
-                    "map" => Some("MAXISCAT = '250_MXC_Maps.gif'"),
-                    "mod" => Some("MAXISCAT = '250_MXC_Modd.gif'"),
-                    "other" => Some("MAXISCAT IN ('250_MXC_Tools.gif','250_MXC_FilesDocs.gif')"),
-                    _ => None
-               }.map(ToString::to_string)
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        if let Some(bc) = set(params.broad_category) {
+            clauses.push("MAXISCAT = :maxiscat".to_string());
+            values.push(("maxiscat".into(), bc.into()));
+        }
 
-        let last_updated = if let Some(ob) = &params.order_by {
-            if ob != "Update" {
-                Some("LASTUPDATE >= 0".to_string())
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        if let Some(images) = set(params.broad_type).and_then(|bt| Search::broad_type_images(&bt)) {
+            let names = (0..images.len()).map(|i| format!(":broad_type_{i}")).join(", ");
+            clauses.push(format!("MAXISCAT IN ({names})"));
+            values.extend(
+                images
+                    .iter()
+                    .enumerate()
+                    .map(|(i, image)| (format!("broad_type_{i}"), (*image).into())),
+            );
+        }
 
-        let group = if let Some(g) = params.group {
-            if &g != "Select" && g.parse::<usize>().is_ok() {
-                Some(format!("LOTGROUP = {}", g.parse::<usize>().unwrap()))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        if let Some(q) = set(params.query) {
+            clauses.push("UPPER(LOTNAME) LIKE :query".to_string());
+            values.push(("query".into(), like_pattern(&q).into()));
+        }
 
-        let query = if let Some(q) = params.query {
-            if &q != "Select" && !q.is_empty() {
-                Some(format!("UPPER(LOTNAME) LIKE = {}", q))
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        if params.exclude_locked.and_then(|el| el.parse::<bool>().ok()) == Some(true) {
+            clauses.push("ADMLOCK = 'F' AND USRLOCK = 'F'".to_string());
+        }
 
-        let exclude_locked = if let Some(el) = params.exclude_locked {
-            if el.parse::<bool>() == Ok(true) {
-                Some("ADMLOCK = 'F' AND USRLOCK = 'F'".to_string())
-            } else {
-                None
-            }
-        } else {
-            None
-        };
+        if params.exclude_notcert.and_then(|enc| enc.parse::<bool>().ok()) == Some(true) {
+            clauses.push("ACCLVL > 0".to_string());
+        }
 
-        let exclude_notcert = if let Some(enc) = params.exclude_notcert {
-            if enc.parse::<bool>() == Ok(true) {
-                Some("ACCLVL > 0".to_string())
-            } else {
-                None
-            }
-        } else {
-            None
+        let order_by = match params.order_by.as_deref() {
+            Some("download") | Some("popular") => "LOTDOWNLOADS",
+            Some("update") => "LASTUPDATE",
+            Some("random") => "RAND()",
+            _ => "LOTID",
         };
 
-        let order_by = if let Some(ob) = params.order_by {
-            match ob.as_str() {
-                "download" => Some("ORDER BY LOTDOWNLOADS"),
-                "popular" => Some("ORDER BY LOTDOWNLOADS"),
-                "update" => Some("ORDER BY LASTUPDATE"),
-                "recent" => Some("ORDER BY LOTID"),
-                "random" => Some("ORDER BY RAND()"),
-                _ => Some("ORDER BY LOTID"),
-            }
-        } else {
-            Some("ORDER BY LOTID")
-        }
-        .map(ToString::to_string);
-
-        let order = if let Some(o) = params.order {
-            match o.to_uppercase().as_str() {
-                "ASC" | "DESC" => Some(o),
-                _ => Some("DESC".into()),
-            }
-        } else {
-            None
+        let order = match params.order {
+            Some(o) if o.eq_ignore_ascii_case("ASC") => "ASC",
+            Some(_) => "DESC",
+            None => "",
         };
 
         let limit = {
-            let start = params
-                .start
-                .and_then(|opt| opt.parse::<usize>().ok())
-                .unwrap_or(0);
-            let amount = params
-                .amount
-                .and_then(|opt| opt.parse::<usize>().ok())
-                .unwrap_or(15);
+            let start = params.start.unwrap_or(0);
+            let amount = params.amount.unwrap_or(15);
 
             format!("LIMIT {start}, {amount}")
         };
 
-        let where_clauses = &[
-            creator,
-            broad_category,
-            lex_category,
-            lex_type,
-            broad_type,
-            last_updated,
-            group,
-            query,
-            exclude_locked,
-            exclude_notcert,
-            Some("ISACTIVE='T'".into()),
-            order_by,
-            order,
-        ];
-
-        let first_some = where_clauses
-            .iter()
-            .position(|wc| wc.is_some())
-            .ok_or(Error::MalformedRequest)?;
-
         let clause = format!(
-            "SELECT * FROM LEX_LOTS WHERE {} {} {limit}",
-            where_clauses[first_some]
-                .as_ref()
-                .cloned()
-                .unwrap_or_default(),
-            where_clauses[first_some + 1..]
-                .iter()
-                .filter_map(|wc| wc.as_ref())
-                .join(" AND ")
+            "SELECT * FROM LEX_LOTS WHERE {} ORDER BY {order_by} {order} {limit}",
+            clauses.join(" AND ")
         );
 
-        Ok(clause)
+        Ok((clause, values))
     }
-    pub(crate) async fn do_search(
+    pub async fn do_search(
         config: Arc<Config>,
         username: String,
         password: Digest,
         ip: String,
         params: SearchParams,
-    ) -> Result<impl warp::Reply> {
-        let query = Search::build_query(params.clone())?;
-
-        let mut conn = config.connect_db().await?;
+        version: ApiVersion,
+    ) -> Result<warp::reply::Json> {
+        Search::check_filters(&params)?;
 
         let user = Base::get_auth(config.clone(), username, password, ip).await?;
 
-        let q = if params.concise {
-            query
-                .with(())
-                .map(&mut conn, |(lotid, lotname): (String, String)| {
-                    serde_json::json!({
-                        "lotid": lotid,
-                        "lotname": lotname,
-                    })
-                })
-                .await?
-        } else {
-            let futs = query
-                .with(())
-                .map(&mut conn, crate::lot::Lot::new)
-                .await?
-                .into_iter()
-                .map(|lot| async {
-                    let lot = crate::lot::Lot::get_lot(
-                        config.clone(),
-                        params.clone(),
-                        lot,
-                        user,
-                    )
-                    .await;
+        let lots = config.storage.search_lots(&params).await?;
 
-                    lot.and_then(|l| serde_json::to_value(l).map_err(Error::SerdeJson))
-                });
-            future::join_all(futs)
-                .await
-                .into_iter()
-                .collect::<Result<Vec<_>>>()?
-        };
+        if params.concise {
+            // v1 clients read the raw column names
+            return Ok(if version == ApiVersion::V1 {
+                warp::reply::json(&lots.iter().map(LegacyLotSummary::from).collect::<Vec<_>>())
+            } else {
+                warp::reply::json(&lots.iter().map(LotSummary::from).collect::<Vec<_>>())
+            });
+        }
+
+        let include = LotParams::from(&params);
+        let futs = lots
+            .into_iter()
+            .map(|lot| Lot::get_lot(config.clone(), include, lot, Some(user)));
+        let lots = future::join_all(futs)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
 
-        Ok(warp::reply::json(&q))
+        Ok(warp::reply::json(&lots))
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct SearchParams {
-    pub start: Option<String>,
-    pub amount: Option<String>,
+#[derive(Debug, Clone, Default, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchParams {
+    pub start: Option<usize>,
+    #[validate(range(min = 1, max = 500))]
+    pub amount: Option<usize>,
     pub order: Option<String>,
     pub concise: bool,
     pub user: Option<bool>,
@@ -263,12 +181,14 @@ pub(crate) struct SearchParams {
     pub votes: Option<bool>,
     //  filtering params
     pub creator: Option<String>,
+    #[validate(regex(path = *crate::validate::IMAGE_NAME))]
     pub broad_category: Option<String>,
     pub lex_category: Option<String>,
     pub lex_type: Option<String>,
     pub broad_type: Option<String>,
     pub group: Option<String>,
     pub order_by: Option<String>,
+    #[validate(length(max = 100))]
     pub query: Option<String>,
     pub exclude_notcert: Option<String>,
     pub exclude_locked: Option<String>,
@@ -0,0 +1,217 @@
+use crate::*;
+use crate::email::RenderedEmail;
+use serde::{Deserialize, Serialize};
+
+mod memory;
+mod mysql;
+
+pub use memory::MemoryStorage;
+pub use mysql::MySqlStorage;
+
+/// Where the data lives: the LEX MySQL database, or memory for tests and local development.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Mysql,
+    Memory,
+}
+impl std::str::FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "mysql" => Ok(Backend::Mysql),
+            "memory" => Ok(Backend::Memory),
+            _ => Err(Error::MalformedRequest),
+        }
+    }
+}
+
+/// Every read and write the API makes. Dates are passed in by the caller, and passwords
+/// are hex md5 hashes as the PHP exchange stored them.
+#[async_trait::async_trait]
+pub trait Storage: std::fmt::Debug + Send + Sync {
+    // users
+    async fn user(&self, usrid: usize) -> Result<Option<LEXUser>>;
+    /// The active user with this username, compared case-insensitively, and password.
+    async fn user_by_login(&self, username: &str, password: &str) -> Result<Option<LEXUser>>;
+    async fn record_login(
+        &self,
+        usrid: usize,
+        ip: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()>;
+    /// Whether the username or email address is taken, compared case-insensitively.
+    async fn user_exists(&self, username: &str, email: &str) -> Result<bool>;
+    async fn insert_user(&self, user: NewUser, now: chrono::DateTime<chrono::Utc>) -> Result<usize>;
+    /// Activates the pending user with this username and password, returning whether there was one.
+    async fn activate_user(&self, username: &str, password: &str) -> Result<bool>;
+    /// One page of the users matching `params`, along with how many match in total.
+    async fn search_users(&self, params: &UserSearchParams) -> Result<(usize, Vec<LEXUser>)>;
+    async fn set_user_status(&self, usrid: usize, status: UserStatus) -> Result<()>;
+    async fn set_user_role(&self, usrid: usize, role: UserRole, value: bool) -> Result<()>;
+    async fn set_user_level(&self, usrid: usize, usrlvl: usize) -> Result<()>;
+    /// The addresses the user registered and last logged in from, where known.
+    async fn user_ips(&self, usrid: usize) -> Result<Vec<String>>;
+    async fn user_locale(&self, usrid: usize) -> Result<Option<String>>;
+
+    // lots
+    async fn lot(&self, lotid: isize) -> Result<Option<Lot>>;
+    /// Lots that haven't been deleted and list `lotid` in their `DEPS`, by ID.
+    async fn dependents(&self, lotid: isize) -> Result<Vec<Lot>>;
+    /// Number of lots that are active and not locked, and the newest `LASTUPDATE` among them.
+    async fn lots_modified(&self) -> Result<(usize, Option<chrono::DateTime<chrono::Utc>>)>;
+    /// A page of the lots that are active and not locked.
+    async fn list_lots(&self, params: &LotListParams) -> Result<Vec<Lot>>;
+    async fn search_lots(&self, params: &SearchParams) -> Result<Vec<Lot>>;
+    /// Inserts `lot`, ignoring its `lotid`, and returns the id it was given.
+    async fn insert_lot(&self, lot: &Lot) -> Result<isize>;
+    /// Writes the columns of `lot` given by `Lot::columns` back, returning false if it doesn't exist.
+    async fn update_lot(&self, lot: &Lot) -> Result<bool>;
+    /// Counts a view by `viewer` unless they had one counted since `since`, returning whether it was.
+    async fn record_view(
+        &self,
+        lotid: isize,
+        viewer: &str,
+        now: chrono::DateTime<chrono::Utc>,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<bool>;
+    /// The locks on the lot that have reasons recorded, at most one of each kind.
+    async fn lot_locks(&self, lotid: isize) -> Result<Vec<LotLock>>;
+    /// Sets the lot's lock of `kind`, its flag and `lock` with the reason, or clears both when
+    /// `lock` is `None`, all or nothing. Whether anything changes is decided on the row as it
+    /// is under the lock. Returns whether it did, or `None` if the lot doesn't exist.
+    async fn set_lot_lock(
+        &self,
+        lotid: isize,
+        kind: LockKind,
+        lock: Option<&LotLock>,
+    ) -> Result<Option<bool>>;
+    /// The lot's revisions, newest first.
+    async fn revisions(&self, lotid: isize) -> Result<Vec<Revision>>;
+    /// Re-reads the lot under a lock, lets `update` change it and give the revision for its
+    /// new version, then writes both back, all or nothing. A lot with no revisions yet gets one
+    /// for the version it had first. Returns the revision with the id it was given, or `None`
+    /// if the lot doesn't exist.
+    async fn update_lot_version(
+        &self,
+        lotid: isize,
+        update: &(dyn for<'a> Fn(&'a mut Lot) -> Result<Revision> + Send + Sync),
+    ) -> Result<Option<Revision>>;
+    /// Active user votes on the lot.
+    async fn votes(&self, lotid: isize) -> Result<Vec<Vote>>;
+    /// Active comments on the lot, oldest first.
+    async fn comments(&self, lotid: isize) -> Result<Vec<Comment>>;
+    /// Comments left by others on `usrid`'s lots since the day of `since`, newest first.
+    async fn new_comments(
+        &self,
+        usrid: usize,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<NewComment>>;
+
+    // download tracks
+    async fn download_track(&self, usrid: usize, lotid: isize) -> Result<Option<DownloadTrack>>;
+    /// Records a download of `lot` by `usrid` and bumps the lot's download counter.
+    async fn record_download(
+        &self,
+        usrid: usize,
+        lot: &Lot,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()>;
+    async fn downloads(
+        &self,
+        usrid: usize,
+        filter: DownloadFilter,
+        params: &DownloadParams,
+    ) -> Result<Vec<DownloadRecord>>;
+
+    // categories
+    async fn broad_categories(&self) -> Result<Vec<BroadCategory>>;
+    async fn lex_categories(&self) -> Result<Vec<LexCategory>>;
+    async fn lex_types(&self) -> Result<Vec<LexType>>;
+    async fn groups(&self) -> Result<Vec<Group>>;
+    async fn authors(&self) -> Result<Vec<Author>>;
+
+    // ip bans
+    async fn ip_bans(&self) -> Result<Vec<IpBan>>;
+    /// Bans that haven't expired and may cover `ip`, which must be canonical. Callers still
+    /// check each with `BanPattern::matches`.
+    async fn ip_bans_for(
+        &self,
+        ip: std::net::IpAddr,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<IpBan>>;
+    async fn insert_ip_ban(
+        &self,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize>;
+    /// Returns whether the ban exists.
+    async fn update_ip_ban(
+        &self,
+        banid: usize,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<bool>;
+    /// Returns whether the ban existed.
+    async fn delete_ip_ban(&self, banid: usize) -> Result<bool>;
+
+    // notifications
+    /// Active users due a digest at `now`. Users without preferences get the weekly one.
+    async fn digest_recipients(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<DigestRecipient>>;
+    async fn digest_frequency(&self, usrid: usize) -> Result<Option<DigestFrequency>>;
+    /// Creates the preferences for `usrid` with `token` if they're missing, returning their token.
+    async fn notify_token(
+        &self,
+        usrid: usize,
+        frequency: DigestFrequency,
+        token: &str,
+    ) -> Result<String>;
+    async fn set_digest_frequency(&self, usrid: usize, frequency: DigestFrequency) -> Result<()>;
+    async fn set_digest_sent(&self, usrid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<()>;
+    /// Turns digests off for the owner of `token`, returning whether there is one.
+    async fn unsubscribe(&self, token: &str) -> Result<bool>;
+
+    // mail queue
+    async fn enqueue_mail(
+        &self,
+        to_email: &str,
+        email: &RenderedEmail,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()>;
+    /// Pending messages whose next attempt is due, soonest first.
+    async fn due_mail(&self, now: chrono::DateTime<chrono::Utc>, limit: usize) -> Result<Vec<QueuedMail>>;
+    async fn set_mail_sent(&self, mailid: usize) -> Result<()>;
+    async fn set_mail_failed(
+        &self,
+        mailid: usize,
+        status: MailStatus,
+        attempts: usize,
+        error: String,
+        next_attempt: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()>;
+    /// Messages with this status, newest first.
+    async fn mail_by_status(&self, status: MailStatus) -> Result<Vec<QueuedMail>>;
+    /// Puts a failed message back in the queue, returning whether there was one.
+    async fn retry_mail(&self, mailid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<bool>;
+    /// Deletes every failed message, returning how many there were.
+    async fn purge_failed_mail(&self) -> Result<u64>;
+
+    // audit log
+    async fn insert_audit(
+        &self,
+        usrid: usize,
+        action: &str,
+        target: AuditTarget,
+        details: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()>;
+    /// Every action recorded against `target`, oldest first.
+    async fn audit_entries(&self, target: AuditTarget) -> Result<Vec<AuditEntry>>;
+}
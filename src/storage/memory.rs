@@ -0,0 +1,1189 @@
+use super::Storage;
+use crate::email::RenderedEmail;
+use crate::*;
+use serde::Deserialize;
+
+/// A user along with the columns `LEXUser` leaves out.
+#[derive(Debug, Clone, Deserialize)]
+struct UserRow {
+    #[serde(flatten)]
+    user: LEXUser,
+    /// Hex md5 of the password.
+    password: String,
+    #[serde(default)]
+    locale: Option<String>,
+    #[serde(default)]
+    registration_ip: Option<String>,
+    #[serde(default)]
+    last_ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VoteRow {
+    lotid: isize,
+    usrid: usize,
+    rating: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct CommentRow {
+    id: usize,
+    lotid: isize,
+    usrid: usize,
+    text: String,
+    date: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct DownloadRow {
+    id: usize,
+    lotid: isize,
+    usrid: usize,
+    last_downloaded: Option<chrono::DateTime<chrono::Utc>>,
+    version: String,
+    count: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct NotifyRow {
+    usrid: usize,
+    frequency: DigestFrequency,
+    last_sent: Option<chrono::DateTime<chrono::Utc>>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct ViewRow {
+    lotid: isize,
+    viewer: String,
+    last_view: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct AuditRow {
+    target: AuditTarget,
+    entry: AuditEntry,
+}
+
+/// Everything the in-memory backend holds. Fixture files have the same shape, with every table optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct Tables {
+    users: Vec<UserRow>,
+    lots: Vec<Lot>,
+    votes: Vec<VoteRow>,
+    comments: Vec<CommentRow>,
+    downloads: Vec<DownloadRow>,
+    broad_categories: Vec<BroadCategory>,
+    lex_categories: Vec<LexCategory>,
+    lex_types: Vec<LexType>,
+    groups: Vec<Group>,
+    ip_bans: Vec<IpBan>,
+    notify_prefs: Vec<NotifyRow>,
+    revisions: Vec<Revision>,
+    lot_locks: Vec<LotLock>,
+    #[serde(skip)]
+    mail: Vec<QueuedMail>,
+    #[serde(skip)]
+    audit: Vec<AuditRow>,
+    #[serde(skip)]
+    views: Vec<ViewRow>,
+}
+impl Tables {
+    fn user_mut(&mut self, usrid: usize) -> Option<&mut UserRow> {
+        self.users.iter_mut().find(|u| u.user.usrid == usrid)
+    }
+
+    fn username(&self, usrid: usize) -> Option<String> {
+        self.users
+            .iter()
+            .find(|u| u.user.usrid == usrid)
+            .map(|u| u.user.username.clone())
+    }
+
+    fn lot(&self, lotid: isize) -> Option<&Lot> {
+        self.lots.iter().find(|l| l.lotid == lotid)
+    }
+}
+
+fn next_id(ids: impl Iterator<Item = usize>) -> usize {
+    ids.max().unwrap_or(0) + 1
+}
+
+/// Writes `lot` over `stored`, as `update_lot` does. The counters are only ever incremented
+/// in place, and the status only changed by locks, so they're kept.
+fn replace_lot(stored: &mut Lot, lot: &Lot) {
+    *stored = Lot {
+        status: stored.status,
+        lotviews: stored.lotviews,
+        lotdownloads: stored.lotdownloads,
+        lastview: stored.lastview,
+        lastdownload: stored.lastdownload,
+        ..lot.clone()
+    };
+}
+
+fn is_available(lot: &Lot) -> bool {
+    lot.status == LotStatus::Active
+}
+
+/// Case-insensitive substring match, as `UPPER(column) LIKE '%VALUE%'`.
+fn contains(haystack: &str, needle: &str) -> bool {
+    haystack.to_uppercase().contains(&needle.to_uppercase())
+}
+
+fn ordered<T>(mut items: Vec<T>, desc: bool, start: u64, amount: u64) -> Vec<T> {
+    if desc {
+        items.reverse();
+    }
+    items
+        .into_iter()
+        .skip(start as usize)
+        .take(amount as usize)
+        .collect()
+}
+
+/// Storage backed by plain collections, seeded from a fixture file. Nothing is persisted.
+#[derive(Debug, Default)]
+pub struct MemoryStorage {
+    tables: std::sync::Mutex<Tables>,
+}
+impl MemoryStorage {
+    /// Seeds the storage from a JSON fixture, such as `fixtures/lex.json`.
+    pub fn from_json(json: &str) -> Result<Self> {
+        Ok(MemoryStorage {
+            tables: std::sync::Mutex::new(serde_json::from_str(json)?),
+        })
+    }
+
+    pub fn load(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        MemoryStorage::from_json(&std::fs::read_to_string(path)?)
+    }
+
+    fn tables(&self) -> std::sync::MutexGuard<'_, Tables> {
+        // a panic elsewhere can't leave the plain collections in a half-written state
+        self.tables.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for MemoryStorage {
+    async fn user(&self, usrid: usize) -> Result<Option<LEXUser>> {
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .find(|u| u.user.usrid == usrid)
+            .map(|u| u.user.clone()))
+    }
+
+    async fn user_by_login(&self, username: &str, password: &str) -> Result<Option<LEXUser>> {
+        let users = self
+            .tables()
+            .users
+            .iter()
+            .filter(|u| {
+                u.user.username.eq_ignore_ascii_case(username)
+                    && u.password == password
+                    && u.user.status == UserStatus::Active
+            })
+            .map(|u| u.user.clone())
+            .collect::<Vec<_>>();
+
+        Ok(if users.len() == 1 {
+            users.into_iter().next()
+        } else {
+            None
+        })
+    }
+
+    async fn record_login(
+        &self,
+        usrid: usize,
+        ip: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        if let Some(row) = self.tables().user_mut(usrid) {
+            row.last_ip = Some(ip.to_string());
+            row.user.last_login = Some(now);
+            row.user.login_count += 1;
+        }
+
+        Ok(())
+    }
+
+    async fn user_exists(&self, username: &str, email: &str) -> Result<bool> {
+        Ok(self.tables().users.iter().any(|u| {
+            u.user.username.eq_ignore_ascii_case(username)
+                || u.user.email_address.eq_ignore_ascii_case(email)
+        }))
+    }
+
+    async fn insert_user(&self, user: NewUser, now: chrono::DateTime<chrono::Utc>) -> Result<usize> {
+        let mut tables = self.tables();
+        let usrid = next_id(tables.users.iter().map(|u| u.user.usrid));
+
+        tables.users.push(UserRow {
+            user: LEXUser {
+                usrid,
+                email_address: user.email,
+                username: user.username,
+                full_name: user.fullname,
+                login_count: 0,
+                // registration dates are stored without a time
+                registered: parse_lex_date(&now.format("%Y%m%d").to_string())?,
+                last_login: None,
+                status: UserStatus::Pending,
+                usrlvl: 0,
+                donator: false,
+                rater: false,
+                uploader: false,
+                author: false,
+                is_admin: false,
+            },
+            password: user.password,
+            locale: user.locale,
+            registration_ip: Some(user.ip),
+            last_ip: None,
+        });
+
+        Ok(usrid)
+    }
+
+    async fn activate_user(&self, username: &str, password: &str) -> Result<bool> {
+        let mut tables = self.tables();
+        let mut pending = tables.users.iter_mut().filter(|u| {
+            u.user.username.eq_ignore_ascii_case(username)
+                && u.password == password
+                && u.user.status == UserStatus::Pending
+        });
+
+        match (pending.next(), pending.next()) {
+            (Some(row), None) => {
+                row.user.status = UserStatus::Active;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn search_users(&self, params: &UserSearchParams) -> Result<(usize, Vec<LEXUser>)> {
+        let tables = self.tables();
+
+        let mut users = tables
+            .users
+            .iter()
+            .filter(|row| {
+                let u = &row.user;
+                let role = |role: UserRole| match role {
+                    UserRole::Donator => u.donator,
+                    UserRole::Rater => u.rater,
+                    UserRole::Uploader => u.uploader,
+                    UserRole::Author => u.author,
+                    UserRole::Admin => u.is_admin,
+                };
+                let registered = u.registered.map(|d| d.date_naive());
+
+                params.username.as_ref().is_none_or(|n| contains(&u.username, n))
+                    && params.email.as_ref().is_none_or(|e| contains(&u.email_address, e))
+                    && params.last_ip.as_ref().is_none_or(|ip| {
+                        row.last_ip.as_ref().is_some_and(|last| last.starts_with(ip.as_str()))
+                    })
+                    && params.status.is_none_or(|s| u.status == s)
+                    && [
+                        (UserRole::Donator, params.donator),
+                        (UserRole::Rater, params.rater),
+                        (UserRole::Uploader, params.uploader),
+                        (UserRole::Author, params.author),
+                        (UserRole::Admin, params.is_admin),
+                    ]
+                    .into_iter()
+                    .all(|(r, value)| value.is_none_or(|v| role(r) == v))
+                    && params.registered_from.is_none_or(|from| registered >= Some(from))
+                    && params.registered_to.is_none_or(|to| registered.is_some_and(|r| r <= to))
+            })
+            .collect::<Vec<_>>();
+        let total = users.len();
+
+        users.sort_by(|a, b| match params.order_by.unwrap_or(UserOrder::Id) {
+            UserOrder::Id => a.user.usrid.cmp(&b.user.usrid),
+            UserOrder::Username => a.user.username.cmp(&b.user.username),
+            UserOrder::Email => a.user.email_address.cmp(&b.user.email_address),
+            UserOrder::Status => a.user.status.flag().cmp(b.user.status.flag()),
+            UserOrder::Registered => a.user.registered.cmp(&b.user.registered),
+            UserOrder::LastLogin => a.user.last_login.cmp(&b.user.last_login),
+            UserOrder::LastIp => a.last_ip.cmp(&b.last_ip),
+            UserOrder::Level => a.user.usrlvl.cmp(&b.user.usrlvl),
+            UserOrder::Donator => a.user.donator.cmp(&b.user.donator),
+            UserOrder::Rater => a.user.rater.cmp(&b.user.rater),
+            UserOrder::Uploader => a.user.uploader.cmp(&b.user.uploader),
+            UserOrder::Author => a.user.author.cmp(&b.user.author),
+            UserOrder::IsAdmin => a.user.is_admin.cmp(&b.user.is_admin),
+        });
+
+        let users = ordered(
+            users.into_iter().map(|row| row.user.clone()).collect(),
+            matches!(params.order, Some(SortOrder::Desc)),
+            params.rows_offset.unwrap_or(0),
+            params.rows_count.unwrap_or(50),
+        );
+
+        Ok((total, users))
+    }
+
+    async fn set_user_status(&self, usrid: usize, status: UserStatus) -> Result<()> {
+        if let Some(row) = self.tables().user_mut(usrid) {
+            row.user.status = status;
+        }
+
+        Ok(())
+    }
+
+    async fn set_user_role(&self, usrid: usize, role: UserRole, value: bool) -> Result<()> {
+        if let Some(row) = self.tables().user_mut(usrid) {
+            let user = &mut row.user;
+            *match role {
+                UserRole::Donator => &mut user.donator,
+                UserRole::Rater => &mut user.rater,
+                UserRole::Uploader => &mut user.uploader,
+                UserRole::Author => &mut user.author,
+                UserRole::Admin => &mut user.is_admin,
+            } = value;
+        }
+
+        Ok(())
+    }
+
+    async fn set_user_level(&self, usrid: usize, usrlvl: usize) -> Result<()> {
+        if let Some(row) = self.tables().user_mut(usrid) {
+            row.user.usrlvl = usrlvl;
+        }
+
+        Ok(())
+    }
+
+    async fn user_ips(&self, usrid: usize) -> Result<Vec<String>> {
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .find(|u| u.user.usrid == usrid)
+            .map(|u| {
+                [u.registration_ip.clone(), u.last_ip.clone()]
+                    .into_iter()
+                    .flatten()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn user_locale(&self, usrid: usize) -> Result<Option<String>> {
+        Ok(self
+            .tables()
+            .users
+            .iter()
+            .find(|u| u.user.usrid == usrid)
+            .and_then(|u| u.locale.clone()))
+    }
+
+    async fn lot(&self, lotid: isize) -> Result<Option<Lot>> {
+        Ok(self.tables().lot(lotid).cloned())
+    }
+
+    async fn dependents(&self, lotid: isize) -> Result<Vec<Lot>> {
+        Ok(self
+            .tables()
+            .lots
+            .iter()
+            .filter(|l| l.status != LotStatus::Inactive && dependency_ids(&l.deps).contains(&lotid))
+            .cloned()
+            .collect())
+    }
+
+    async fn lots_modified(&self) -> Result<(usize, Option<chrono::DateTime<chrono::Utc>>)> {
+        let tables = self.tables();
+        let lots = tables.lots.iter().filter(|l| is_available(l));
+
+        Ok((lots.clone().count(), lots.filter_map(|l| l.lastupdate).max()))
+    }
+
+    async fn list_lots(&self, params: &LotListParams) -> Result<Vec<Lot>> {
+        let desc = matches!(params.order, Some(SortOrder::Desc));
+
+        let mut lots = self
+            .tables()
+            .lots
+            .iter()
+            .filter(|l| is_available(l))
+            .filter(|l| {
+                params
+                    .after
+                    .is_none_or(|after| if desc { l.lotid < after } else { l.lotid > after })
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        lots.sort_by_key(|l| l.lotid);
+        match params.order_by.unwrap_or(LotOrder::Id) {
+            LotOrder::Id => {}
+            LotOrder::Name => lots.sort_by_cached_key(|l| l.lotname.to_uppercase()),
+            LotOrder::Updated => lots.sort_by_key(|l| l.lastupdate),
+            LotOrder::Downloads => lots.sort_by_key(|l| l.lotdownloads),
+        }
+
+        Ok(ordered(
+            lots,
+            desc,
+            params.start.unwrap_or(0),
+            params.amount.unwrap_or(LOT_PAGE_SIZE),
+        ))
+    }
+
+    async fn search_lots(&self, params: &SearchParams) -> Result<Vec<Lot>> {
+        // mirrors `Search::build_query`, where "Select" is the placeholder of an unset filter
+        let set = |value: &Option<String>| {
+            value.clone().filter(|v| v != "Select" && !v.is_empty())
+        };
+        let id = |value: &Option<String>| {
+            set(value).and_then(|v| v.parse::<usize>().ok()).map(|v| v as isize)
+        };
+        let broad_types = set(&params.broad_type).and_then(|t| Search::broad_type_images(&t));
+
+        let mut lots = self
+            .tables()
+            .lots
+            .iter()
+            .filter(|lot| {
+                lot.status != LotStatus::Inactive
+                    && id(&params.creator).is_none_or(|c| lot.usrid as isize == c)
+                    && set(&params.broad_category).is_none_or(|bc| lot.maxiscat == bc)
+                    && id(&params.lex_category).is_none_or(|c| lot.catid == c)
+                    && id(&params.lex_type).is_none_or(|t| lot.typeid == t)
+                    && broad_types.is_none_or(|images| images.contains(&lot.maxiscat.as_str()))
+                    && id(&params.group).is_none_or(|g| lot.lotgroup == g)
+                    && set(&params.query).is_none_or(|q| contains(&lot.lotname, &q))
+                    && (params.exclude_locked.as_deref() != Some("true")
+                        || !lot.status.is_locked())
+                    && (params.exclude_notcert.as_deref() != Some("true") || lot.acclvl > 0)
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        match params.order_by.as_deref() {
+            Some("download") | Some("popular") => lots.sort_by_key(|l| l.lotdownloads),
+            Some("update") => lots.sort_by_key(|l| l.lastupdate),
+            Some("random") => lots.sort_by_cached_key(|_| uuid::Uuid::new_v4()),
+            _ => lots.sort_by_key(|l| l.lotid),
+        }
+        let desc = params
+            .order
+            .as_ref()
+            .is_some_and(|o| !o.eq_ignore_ascii_case("ASC"));
+
+        Ok(ordered(
+            lots,
+            desc,
+            params.start.unwrap_or(0) as u64,
+            params.amount.unwrap_or(15) as u64,
+        ))
+    }
+
+    async fn insert_lot(&self, lot: &Lot) -> Result<isize> {
+        let mut tables = self.tables();
+        let lotid = tables.lots.iter().map(|l| l.lotid).max().unwrap_or(0) + 1;
+
+        tables.lots.push(Lot {
+            lotid,
+            ..lot.clone()
+        });
+
+        Ok(lotid)
+    }
+
+    async fn update_lot(&self, lot: &Lot) -> Result<bool> {
+        let mut tables = self.tables();
+        let Some(stored) = tables.lots.iter_mut().find(|l| l.lotid == lot.lotid) else {
+            return Ok(false);
+        };
+
+        replace_lot(stored, lot);
+
+        Ok(true)
+    }
+
+    async fn record_view(
+        &self,
+        lotid: isize,
+        viewer: &str,
+        now: chrono::DateTime<chrono::Utc>,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<bool> {
+        let mut tables = self.tables();
+
+        match tables
+            .views
+            .iter_mut()
+            .find(|v| v.lotid == lotid && v.viewer == viewer)
+        {
+            Some(view) if view.last_view >= since => return Ok(false),
+            Some(view) => view.last_view = now,
+            None => tables.views.push(ViewRow {
+                lotid,
+                viewer: viewer.to_string(),
+                last_view: now,
+            }),
+        }
+
+        if let Some(lot) = tables.lots.iter_mut().find(|l| l.lotid == lotid) {
+            lot.lotviews += 1;
+            lot.lastview = Some(now);
+        }
+
+        Ok(true)
+    }
+
+    async fn lot_locks(&self, lotid: isize) -> Result<Vec<LotLock>> {
+        Ok(self
+            .tables()
+            .lot_locks
+            .iter()
+            .filter(|l| l.lotid == lotid)
+            .cloned()
+            .collect())
+    }
+
+    async fn set_lot_lock(
+        &self,
+        lotid: isize,
+        kind: LockKind,
+        lock: Option<&LotLock>,
+    ) -> Result<Option<bool>> {
+        let mut tables = self.tables();
+        let tables = &mut *tables;
+        let Some(stored) = tables.lots.iter_mut().find(|l| l.lotid == lotid) else {
+            return Ok(None);
+        };
+        let row = |locks: &[LotLock], kind: LockKind| {
+            locks.iter().position(|l| l.lotid == lotid && l.kind == kind)
+        };
+
+        // the status can only hold one of the flags, so an author's lock under an admin lock
+        // is kept as its row, as MySQL keeps USRLOCK
+        let user_locked = stored.status == LotStatus::UserLocked
+            || row(&tables.lot_locks, LockKind::User).is_some();
+        let locked = match kind {
+            LockKind::Admin => stored.status == LotStatus::AdminLocked,
+            LockKind::User => user_locked,
+        };
+        let unchanged = match lock {
+            Some(lock) => {
+                locked
+                    && row(&tables.lot_locks, kind)
+                        .is_some_and(|i| tables.lot_locks[i].reason == lock.reason)
+            }
+            None => !locked,
+        };
+        if unchanged {
+            return Ok(Some(false));
+        }
+
+        if kind == LockKind::Admin && user_locked && row(&tables.lot_locks, LockKind::User).is_none() {
+            tables.lot_locks.push(LotLock {
+                lotid,
+                kind: LockKind::User,
+                usrid: stored.usrid,
+                reason: String::new(),
+                date: None,
+            });
+        }
+        if let Some(i) = row(&tables.lot_locks, kind) {
+            tables.lot_locks.remove(i);
+        }
+        tables.lot_locks.extend(lock.cloned());
+
+        let user_locked = row(&tables.lot_locks, LockKind::User).is_some();
+        let admin_locked = match kind {
+            LockKind::Admin => lock.is_some(),
+            LockKind::User => stored.status == LotStatus::AdminLocked,
+        };
+        if stored.status != LotStatus::Inactive {
+            stored.status = LotStatus::from_flags(true, admin_locked, user_locked);
+        }
+
+        Ok(Some(true))
+    }
+
+    async fn revisions(&self, lotid: isize) -> Result<Vec<Revision>> {
+        let mut revisions = self
+            .tables()
+            .revisions
+            .iter()
+            .filter(|r| r.lotid == lotid)
+            .cloned()
+            .collect::<Vec<_>>();
+        revisions.sort_by_key(|r| std::cmp::Reverse(r.revid));
+
+        Ok(revisions)
+    }
+
+    async fn update_lot_version(
+        &self,
+        lotid: isize,
+        update: &(dyn for<'a> Fn(&'a mut Lot) -> Result<Revision> + Send + Sync),
+    ) -> Result<Option<Revision>> {
+        let mut tables = self.tables();
+        let Some(stored) = tables.lots.iter_mut().find(|l| l.lotid == lotid) else {
+            return Ok(None);
+        };
+
+        let mut lot = stored.clone();
+        let previous = Lot::current_revision(&lot);
+        let revision = update(&mut lot)?;
+        replace_lot(stored, &lot);
+
+        if !tables.revisions.iter().any(|r| r.lotid == lotid) {
+            let revid = next_id(tables.revisions.iter().map(|r| r.revid));
+            tables.revisions.push(Revision {
+                revid,
+                ..previous
+            });
+        }
+        let revision = Revision {
+            revid: next_id(tables.revisions.iter().map(|r| r.revid)),
+            ..revision
+        };
+        tables.revisions.push(revision.clone());
+
+        Ok(Some(revision))
+    }
+
+    async fn votes(&self, lotid: isize) -> Result<Vec<Vote>> {
+        Ok(self
+            .tables()
+            .votes
+            .iter()
+            .filter(|v| v.lotid == lotid)
+            .map(|v| Vote {
+                usrid: v.usrid,
+                rating: v.rating,
+            })
+            .collect())
+    }
+
+    async fn comments(&self, lotid: isize) -> Result<Vec<Comment>> {
+        let tables = self.tables();
+
+        let mut comments = tables
+            .comments
+            .iter()
+            .filter(|c| c.lotid == lotid)
+            .filter_map(|c| {
+                Some(Comment {
+                    id: c.id,
+                    author: tables.username(c.usrid)?,
+                    text: c.text.clone(),
+                    date: c.date,
+                })
+            })
+            .collect::<Vec<_>>();
+        comments.sort_by_key(|c| (c.date, c.id));
+
+        Ok(comments)
+    }
+
+    async fn new_comments(
+        &self,
+        usrid: usize,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<NewComment>> {
+        let tables = self.tables();
+
+        let mut comments = tables
+            .comments
+            .iter()
+            .filter(|c| {
+                c.usrid != usrid && c.date.is_some_and(|d| d.date_naive() >= since.date_naive())
+            })
+            .filter_map(|c| {
+                let lot = tables.lot(c.lotid).filter(|l| l.usrid == usrid)?;
+
+                Some(NewComment {
+                    lot_id: lot.lotid as usize,
+                    lot_name: lot.lotname.trim().to_string(),
+                    author: tables.username(c.usrid)?,
+                    date: c.date,
+                })
+            })
+            .collect::<Vec<_>>();
+        comments.sort_by_key(|c| std::cmp::Reverse(c.date));
+
+        Ok(comments)
+    }
+
+    async fn download_track(&self, usrid: usize, lotid: isize) -> Result<Option<DownloadTrack>> {
+        Ok(self
+            .tables()
+            .downloads
+            .iter()
+            .find(|d| d.usrid == usrid && d.lotid == lotid)
+            .map(|d| DownloadTrack {
+                id: d.id,
+                last_downloaded: d.last_downloaded,
+                last_version: d.version.trim().to_string(),
+                download_count: d.count,
+            }))
+    }
+
+    async fn record_download(
+        &self,
+        usrid: usize,
+        lot: &Lot,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        let version = lot.version.trim().to_string();
+
+        match tables
+            .downloads
+            .iter_mut()
+            .find(|d| d.usrid == usrid && d.lotid == lot.lotid)
+        {
+            Some(record) => {
+                record.last_downloaded = Some(now);
+                record.count += 1;
+                record.version = version;
+            }
+            None => {
+                let id = next_id(tables.downloads.iter().map(|d| d.id));
+                tables.downloads.push(DownloadRow {
+                    id,
+                    lotid: lot.lotid,
+                    usrid,
+                    last_downloaded: Some(now),
+                    version,
+                    count: 1,
+                });
+            }
+        }
+
+        if let Some(lot) = tables.lots.iter_mut().find(|l| l.lotid == lot.lotid) {
+            lot.lotdownloads += 1;
+            lot.lastdownload = Some(now);
+        }
+
+        Ok(())
+    }
+
+    async fn downloads(
+        &self,
+        usrid: usize,
+        filter: DownloadFilter,
+        params: &DownloadParams,
+    ) -> Result<Vec<DownloadRecord>> {
+        let tables = self.tables();
+
+        let mut records = tables
+            .downloads
+            .iter()
+            .filter(|d| d.usrid == usrid)
+            .filter_map(|d| {
+                let lot = tables.lot(d.lotid)?;
+                let update_date = lot.lastupdate;
+
+                let included = match filter {
+                    DownloadFilter::History => d.count >= 1,
+                    DownloadFilter::List => d.count == 0 && is_available(lot),
+                    DownloadFilter::Outdated => {
+                        d.count >= 1
+                            && lot.status != LotStatus::Inactive
+                            && (lot.version.trim() != d.version.trim()
+                                || update_date.map(|u| u.date_naive())
+                                    > d.last_downloaded.map(|l| l.date_naive()))
+                    }
+                };
+
+                included.then(|| {
+                    Some(DownloadRecord {
+                        lot: DownloadedLot {
+                            id: lot.lotid as usize,
+                            name: lot.lotname.trim().to_string(),
+                            update_date,
+                            version: lot.version.trim().to_string(),
+                            author: tables.username(lot.usrid)?,
+                        },
+                        record: DownloadTrack {
+                            id: d.id,
+                            last_downloaded: d.last_downloaded,
+                            last_version: d.version.trim().to_string(),
+                            download_count: d.count,
+                        },
+                    })
+                })?
+            })
+            .collect::<Vec<_>>();
+
+        records.sort_by(|a, b| {
+            match params.order_by.unwrap_or(DownloadOrder::Update) {
+                DownloadOrder::Id => a.lot.id.cmp(&b.lot.id),
+                DownloadOrder::Name => a.lot.name.cmp(&b.lot.name),
+                DownloadOrder::Update => a.lot.update_date.cmp(&b.lot.update_date),
+                DownloadOrder::Downloaded => {
+                    a.record.last_downloaded.cmp(&b.record.last_downloaded)
+                }
+                DownloadOrder::DownloadCount => {
+                    a.record.download_count.cmp(&b.record.download_count)
+                }
+            }
+            .then(a.record.id.cmp(&b.record.id))
+        });
+
+        Ok(ordered(
+            records,
+            matches!(params.order, Some(SortOrder::Desc)),
+            params.start.unwrap_or(0),
+            params.amount.unwrap_or(100),
+        ))
+    }
+
+    async fn broad_categories(&self) -> Result<Vec<BroadCategory>> {
+        let mut categories = self.tables().broad_categories.clone();
+        categories.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(categories)
+    }
+
+    async fn lex_categories(&self) -> Result<Vec<LexCategory>> {
+        let mut categories = self.tables().lex_categories.clone();
+        categories.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(categories)
+    }
+
+    async fn lex_types(&self) -> Result<Vec<LexType>> {
+        let mut types = self.tables().lex_types.clone();
+        types.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(types)
+    }
+
+    async fn groups(&self) -> Result<Vec<Group>> {
+        let mut groups = self.tables().groups.clone();
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(groups)
+    }
+
+    async fn authors(&self) -> Result<Vec<Author>> {
+        let mut authors = self
+            .tables()
+            .users
+            .iter()
+            .filter(|u| u.user.author)
+            .map(|u| Author {
+                id: u.user.usrid as isize,
+                name: u.user.username.clone(),
+            })
+            .collect::<Vec<_>>();
+        authors.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(authors)
+    }
+
+    async fn ip_bans(&self) -> Result<Vec<IpBan>> {
+        Ok(self.tables().ip_bans.clone())
+    }
+
+    async fn ip_bans_for(
+        &self,
+        ip: std::net::IpAddr,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<IpBan>> {
+        Ok(self
+            .tables()
+            .ip_bans
+            .iter()
+            .filter(|ban| ban.expires.is_none_or(|e| e > now) && ban.pattern.matches(ip))
+            .cloned()
+            .collect())
+    }
+
+    async fn insert_ip_ban(
+        &self,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        let mut tables = self.tables();
+        let id = next_id(tables.ip_bans.iter().map(|b| b.id));
+
+        tables.ip_bans.push(IpBan {
+            id,
+            pattern: pattern.clone(),
+            reason,
+            created: Some(now),
+            expires,
+        });
+
+        Ok(id)
+    }
+
+    async fn update_ip_ban(
+        &self,
+        banid: usize,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<bool> {
+        let mut tables = self.tables();
+        let Some(ban) = tables.ip_bans.iter_mut().find(|b| b.id == banid) else {
+            return Ok(false);
+        };
+
+        ban.pattern = pattern.clone();
+        ban.reason = reason;
+        ban.expires = expires;
+
+        Ok(true)
+    }
+
+    async fn delete_ip_ban(&self, banid: usize) -> Result<bool> {
+        let mut tables = self.tables();
+        let before = tables.ip_bans.len();
+        tables.ip_bans.retain(|b| b.id != banid);
+
+        Ok(tables.ip_bans.len() < before)
+    }
+
+    async fn digest_recipients(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<DigestRecipient>> {
+        let tables = self.tables();
+
+        Ok(tables
+            .users
+            .iter()
+            .filter(|u| u.user.status == UserStatus::Active)
+            .filter_map(|u| {
+                let prefs = tables.notify_prefs.iter().find(|p| p.usrid == u.user.usrid);
+                let frequency = prefs.map_or(DigestFrequency::Weekly, |p| p.frequency);
+                let last_sent = prefs.and_then(|p| p.last_sent);
+
+                let due = match (frequency.period(), last_sent) {
+                    (None, _) => false,
+                    (Some(_), None) => true,
+                    (Some(period), Some(sent)) => sent <= now - period,
+                };
+
+                due.then(|| DigestRecipient {
+                    usrid: u.user.usrid,
+                    username: u.user.username.clone(),
+                    email_address: u.user.email_address.clone(),
+                    locale: u.locale.clone(),
+                    frequency,
+                    last_sent,
+                    token: prefs.and_then(|p| p.token.clone()),
+                })
+            })
+            .collect())
+    }
+
+    async fn digest_frequency(&self, usrid: usize) -> Result<Option<DigestFrequency>> {
+        Ok(self
+            .tables()
+            .notify_prefs
+            .iter()
+            .find(|p| p.usrid == usrid)
+            .map(|p| p.frequency))
+    }
+
+    async fn notify_token(
+        &self,
+        usrid: usize,
+        frequency: DigestFrequency,
+        token: &str,
+    ) -> Result<String> {
+        let mut tables = self.tables();
+
+        match tables.notify_prefs.iter_mut().find(|p| p.usrid == usrid) {
+            Some(prefs) => Ok(prefs.token.get_or_insert_with(|| token.to_string()).clone()),
+            None => {
+                tables.notify_prefs.push(NotifyRow {
+                    usrid,
+                    frequency,
+                    last_sent: None,
+                    token: Some(token.to_string()),
+                });
+                Ok(token.to_string())
+            }
+        }
+    }
+
+    async fn set_digest_frequency(&self, usrid: usize, frequency: DigestFrequency) -> Result<()> {
+        if let Some(prefs) = self.tables().notify_prefs.iter_mut().find(|p| p.usrid == usrid) {
+            prefs.frequency = frequency;
+        }
+
+        Ok(())
+    }
+
+    async fn set_digest_sent(&self, usrid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<()> {
+        if let Some(prefs) = self.tables().notify_prefs.iter_mut().find(|p| p.usrid == usrid) {
+            prefs.last_sent = Some(now);
+        }
+
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, token: &str) -> Result<bool> {
+        let mut tables = self.tables();
+        let Some(prefs) = tables
+            .notify_prefs
+            .iter_mut()
+            .find(|p| p.token.as_deref() == Some(token))
+        else {
+            return Ok(false);
+        };
+
+        prefs.frequency = DigestFrequency::Never;
+
+        Ok(true)
+    }
+
+    async fn enqueue_mail(
+        &self,
+        to_email: &str,
+        email: &RenderedEmail,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut tables = self.tables();
+        let id = next_id(tables.mail.iter().map(|m| m.id));
+
+        tables.mail.push(QueuedMail {
+            id,
+            to: to_email.to_string(),
+            subject: email.subject.clone(),
+            html: email.html.clone(),
+            text: email.text.clone(),
+            status: MailStatus::Pending,
+            attempts: 0,
+            last_error: None,
+            next_attempt: Some(now),
+            created: Some(now),
+        });
+
+        Ok(())
+    }
+
+    async fn due_mail(&self, now: chrono::DateTime<chrono::Utc>, limit: usize) -> Result<Vec<QueuedMail>> {
+        let mut due = self
+            .tables()
+            .mail
+            .iter()
+            .filter(|m| m.status == MailStatus::Pending && m.next_attempt.is_some_and(|n| n <= now))
+            .cloned()
+            .collect::<Vec<_>>();
+        due.sort_by_key(|m| m.next_attempt);
+        due.truncate(limit);
+
+        Ok(due)
+    }
+
+    async fn set_mail_sent(&self, mailid: usize) -> Result<()> {
+        if let Some(mail) = self.tables().mail.iter_mut().find(|m| m.id == mailid) {
+            mail.status = MailStatus::Sent;
+            mail.attempts += 1;
+            mail.last_error = None;
+        }
+
+        Ok(())
+    }
+
+    async fn set_mail_failed(
+        &self,
+        mailid: usize,
+        status: MailStatus,
+        attempts: usize,
+        error: String,
+        next_attempt: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        if let Some(mail) = self.tables().mail.iter_mut().find(|m| m.id == mailid) {
+            mail.status = status;
+            mail.attempts = attempts;
+            mail.last_error = Some(error);
+            mail.next_attempt = Some(next_attempt);
+        }
+
+        Ok(())
+    }
+
+    async fn mail_by_status(&self, status: MailStatus) -> Result<Vec<QueuedMail>> {
+        let mut mail = self
+            .tables()
+            .mail
+            .iter()
+            .filter(|m| m.status == status)
+            .cloned()
+            .collect::<Vec<_>>();
+        mail.sort_by_key(|m| std::cmp::Reverse(m.id));
+
+        Ok(mail)
+    }
+
+    async fn retry_mail(&self, mailid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<bool> {
+        let mut tables = self.tables();
+        let Some(mail) = tables
+            .mail
+            .iter_mut()
+            .find(|m| m.id == mailid && m.status == MailStatus::Failed)
+        else {
+            return Ok(false);
+        };
+
+        mail.status = MailStatus::Pending;
+        mail.attempts = 0;
+        mail.next_attempt = Some(now);
+
+        Ok(true)
+    }
+
+    async fn purge_failed_mail(&self) -> Result<u64> {
+        let mut tables = self.tables();
+        let before = tables.mail.len();
+        tables.mail.retain(|m| m.status != MailStatus::Failed);
+
+        Ok((before - tables.mail.len()) as u64)
+    }
+
+    async fn insert_audit(
+        &self,
+        usrid: usize,
+        action: &str,
+        target: AuditTarget,
+        details: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        self.tables().audit.push(AuditRow {
+            target,
+            entry: AuditEntry {
+                usrid,
+                action: action.to_string(),
+                details: details.to_string(),
+                date: Some(now),
+            },
+        });
+
+        Ok(())
+    }
+
+    async fn audit_entries(&self, target: AuditTarget) -> Result<Vec<AuditEntry>> {
+        Ok(self
+            .tables()
+            .audit
+            .iter()
+            .filter(|row| row.target == target)
+            .map(|row| row.entry.clone())
+            .collect())
+    }
+}
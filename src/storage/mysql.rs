@@ -0,0 +1,1173 @@
+use super::Storage;
+use crate::email::RenderedEmail;
+use crate::*;
+use mysql_async::{
+    params,
+    prelude::{Query, WithParams},
+    Params, Row, TxOpts,
+};
+
+/// Download records whose lot has changed since it was last downloaded.
+/// LASTUPDATE is a bare date, so it is only compared against the date part of LASTDL.
+const OUTDATED_DOWNLOADS: &str = "DT.DLCOUNT >= 1 AND LL.ISACTIVE = 'T'
+    AND (TRIM(LL.VERSION) <> TRIM(DT.VERSION) OR LL.LASTUPDATE > LEFT(DT.LASTDL, LENGTH(LL.LASTUPDATE)))";
+
+fn flag(value: bool) -> &'static str {
+    if value {
+        "T"
+    } else {
+        "F"
+    }
+}
+
+/// Writes the columns of `lot` given by `Lot::columns` back to its row.
+fn update_lot_query(lot: &Lot) -> impl Query + '_ {
+    let columns = lot.columns();
+    let set = columns
+        .iter()
+        .map(|(column, _)| format!("{column} = :{}", column.to_lowercase()))
+        .join(", ");
+    let mut values = columns
+        .into_iter()
+        .map(|(column, value)| (column.to_lowercase(), value))
+        .collect::<Vec<_>>();
+    values.push(("lotid".into(), lot.lotid.into()));
+
+    format!("UPDATE LEX_LOTS SET {set} WHERE LOTID = :lotid").with(Params::from(values))
+}
+
+fn revision_params(revision: &Revision) -> Params {
+    params! {
+        "lotid" => revision.lotid,
+        "usrid" => revision.usrid,
+        "version" => string_to_latin1(&revision.version),
+        "lotfile" => string_to_latin1(&revision.lotfile),
+        "changelog" => string_to_latin1(&revision.changelog),
+        "dateon" => revision.dateon.map(lex_timestamp).unwrap_or_default(),
+    }
+}
+
+/// The LEX MySQL database, accessed through a connection pool.
+#[derive(Debug, Clone)]
+pub struct MySqlStorage {
+    pool: mysql_async::Pool,
+}
+impl MySqlStorage {
+    pub fn new(host: &str, user: &str, pass: &str, db_name: &str) -> Self {
+        let opts = mysql_async::OptsBuilder::default()
+            .ip_or_hostname(host)
+            .user(Some(user))
+            .pass(Some(pass))
+            .db_name(Some(db_name));
+
+        MySqlStorage {
+            pool: mysql_async::Pool::new(opts),
+        }
+    }
+
+    async fn conn(&self) -> Result<mysql_async::Conn> {
+        Ok(self.pool.get_conn().await?)
+    }
+}
+
+#[async_trait::async_trait]
+impl Storage for MySqlStorage {
+    async fn user(&self, usrid: usize) -> Result<Option<LEXUser>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_USERS WHERE USRID = :usrid"
+            .with(params! {
+                usrid,
+            })
+            .map(&mut conn, |row: Row| LEXUser::try_from(row))
+            .await?
+            .into_iter()
+            .next()
+            .transpose()
+    }
+
+    async fn user_by_login(&self, username: &str, password: &str) -> Result<Option<LEXUser>> {
+        let mut conn = self.conn().await?;
+
+        let users = "SELECT * FROM LEX_USERS WHERE UPPER(USRNAME) = :username AND USRPASS = :password AND ISACTIVE = 'T'"
+            .with(params! {
+                "username" => username.to_uppercase(),
+                password,
+            })
+            .map(&mut conn, |row: Row| LEXUser::try_from(row))
+            .await?
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        // a duplicated login can't tell the users apart
+        Ok(if users.len() == 1 {
+            users.into_iter().next()
+        } else {
+            None
+        })
+    }
+
+    async fn record_login(
+        &self,
+        usrid: usize,
+        ip: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_USERS SET LASTIP = :ip, LASTLOGIN = :date, LOGINCNT = LOGINCNT + 1 WHERE USRID = :usrid"
+            .with(params! {
+                ip,
+                "date" => lex_timestamp(now),
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn user_exists(&self, username: &str, email: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        let count: usize = "SELECT COUNT(*) FROM LEX_USERS WHERE UPPER(USRNAME) = :tun OR UPPER(EMAILADDDR) = :tem"
+            .with(params! {
+                "tun" => username.to_uppercase(),
+                "tem" => email.to_uppercase(),
+            })
+            .first(&mut conn)
+            .await?
+            .unwrap_or(0);
+
+        Ok(count > 0)
+    }
+
+    async fn insert_user(&self, user: NewUser, now: chrono::DateTime<chrono::Utc>) -> Result<usize> {
+        let mut conn = self.conn().await?;
+
+        "INSERT INTO LEX_USERS (FULLNAME,USRNAME,USRPASS,DATEON,EMAILADDDR,ISACTIVE,REGIP,LOCALE)
+            VALUES (:fullname, :username, :pass, :now, :email, 'P', :regip, :locale)"
+            .with(params! {
+                "fullname" => user.fullname,
+                "username" => user.username,
+                "pass" => user.password,
+                "now" => now.format("%Y%m%d").to_string(),
+                "email" => user.email,
+                "regip" => user.ip,
+                "locale" => user.locale,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.last_insert_id().unwrap_or_default() as usize)
+    }
+
+    async fn activate_user(&self, username: &str, password: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_USERS SET ISACTIVE = 'T' WHERE UPPER(USRNAME) = :username AND USRPASS = :password AND ISACTIVE = 'P'"
+            .with(params! {
+                "username" => username.to_uppercase(),
+                password,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.affected_rows() == 1)
+    }
+
+    async fn search_users(&self, params: &UserSearchParams) -> Result<(usize, Vec<LEXUser>)> {
+        let mut conn = self.conn().await?;
+
+        let (clause, values) = LEXUser::build_filter(params);
+        let order_by = params.order_by.unwrap_or(UserOrder::Id).column();
+        let order = match params.order {
+            Some(SortOrder::Desc) => "DESC",
+            _ => "ASC",
+        };
+        let limit = format!(
+            "LIMIT {}, {}",
+            params.rows_offset.unwrap_or(0),
+            params.rows_count.unwrap_or(50)
+        );
+
+        let total: usize = format!("SELECT COUNT(*) FROM LEX_USERS {clause}")
+            .with(Params::from(values.clone()))
+            .first(&mut conn)
+            .await?
+            .unwrap_or(0);
+
+        let users = format!("SELECT * FROM LEX_USERS {clause} ORDER BY {order_by} {order} {limit}")
+            .with(Params::from(values))
+            .map(&mut conn, |row: Row| LEXUser::try_from(row))
+            .await?
+            .into_iter()
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok((total, users))
+    }
+
+    async fn set_user_status(&self, usrid: usize, status: UserStatus) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_USERS SET ISACTIVE = :isactive WHERE USRID = :usrid"
+            .with(params! {
+                "isactive" => status.flag(),
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_role(&self, usrid: usize, role: UserRole, value: bool) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        format!("UPDATE LEX_USERS SET {} = :value WHERE USRID = :usrid", role.column())
+            .with(params! {
+                "value" => flag(value),
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_user_level(&self, usrid: usize, usrlvl: usize) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_USERS SET USRLVL = :usrlvl WHERE USRID = :usrid"
+            .with(params! {
+                usrlvl,
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn user_ips(&self, usrid: usize) -> Result<Vec<String>> {
+        let mut conn = self.conn().await?;
+
+        let ips: Option<(Option<String>, Option<String>)> =
+            "SELECT REGIP, LASTIP FROM LEX_USERS WHERE USRID = :usrid"
+                .with(params! {
+                    usrid,
+                })
+                .first(&mut conn)
+                .await?;
+
+        Ok(ips
+            .map(|(regip, lastip)| [regip, lastip].into_iter().flatten().collect())
+            .unwrap_or_default())
+    }
+
+    async fn user_locale(&self, usrid: usize) -> Result<Option<String>> {
+        let mut conn = self.conn().await?;
+
+        let locale: Option<Option<String>> = "SELECT LOCALE FROM LEX_USERS WHERE USRID = :usrid"
+            .with(params! {
+                usrid,
+            })
+            .first(&mut conn)
+            .await?;
+
+        Ok(locale.flatten())
+    }
+
+    async fn lot(&self, lotid: isize) -> Result<Option<Lot>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT * FROM LEX_LOTS WHERE LOTID = :lotid"
+            .with(params! {
+                lotid,
+            })
+            .map(&mut conn, |row: Row| Lot::try_from(row))
+            .await?
+            .into_iter()
+            .next()
+            .transpose()?)
+    }
+
+    async fn dependents(&self, lotid: isize) -> Result<Vec<Lot>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_LOTS
+            WHERE ISACTIVE = 'T' AND CONCAT('$', REPLACE(DEPS, ' ', ''), '$') LIKE :pattern
+            ORDER BY LOTID"
+            .with(params! {
+                "pattern" => format!("%${lotid}$%"),
+            })
+            .map(&mut conn, |row: Row| Lot::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn lots_modified(&self) -> Result<(usize, Option<chrono::DateTime<chrono::Utc>>)> {
+        let mut conn = self.conn().await?;
+
+        let (total, last_updated): (usize, Option<String>) =
+            "SELECT COUNT(*), MAX(LASTUPDATE) FROM LEX_LOTS WHERE USRLOCK='F' AND ADMLOCK='F' AND ISACTIVE='T'"
+                .with(())
+                .first(&mut conn)
+                .await?
+                .unwrap_or_default();
+
+        Ok((total, parse_lex_date(&last_updated.unwrap_or_default())?))
+    }
+
+    async fn list_lots(&self, params: &LotListParams) -> Result<Vec<Lot>> {
+        let mut conn = self.conn().await?;
+
+        let order_by = params.order_by.unwrap_or(LotOrder::Id).column();
+        let (order, after) = match params.order {
+            Some(SortOrder::Desc) => ("DESC", "AND LOTID < :after"),
+            _ => ("ASC", "AND LOTID > :after"),
+        };
+        let after = if params.after.is_some() { after } else { "" };
+        let limit = format!(
+            "LIMIT {}, {}",
+            params.start.unwrap_or(0),
+            params.amount.unwrap_or(LOT_PAGE_SIZE)
+        );
+
+        format!(
+            "SELECT * FROM LEX_LOTS WHERE USRLOCK='F' AND ADMLOCK='F' AND ISACTIVE='T' {after}
+                ORDER BY {order_by} {order}, LOTID {order} {limit}"
+        )
+        .with(params! {
+            "after" => params.after.unwrap_or_default(),
+        })
+        .map(&mut conn, |row: Row| Lot::try_from(row))
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    async fn search_lots(&self, params: &SearchParams) -> Result<Vec<Lot>> {
+        let (query, values) = Search::build_query(params.clone())?;
+        let mut conn = self.conn().await?;
+
+        Ok(query
+            .with(Params::from(values))
+            .map(&mut conn, |row: Row| Lot::try_from(row))
+            .await?
+            .into_iter()
+            .collect::<Result<_>>()?)
+    }
+
+    async fn insert_lot(&self, lot: &Lot) -> Result<isize> {
+        let mut conn = self.conn().await?;
+
+        let mut columns = lot.columns();
+        columns.extend(lot.counter_columns());
+        columns.extend(lot.status_columns());
+        let names = columns.iter().map(|(column, _)| *column).join(", ");
+        let placeholders = columns
+            .iter()
+            .map(|(column, _)| format!(":{}", column.to_lowercase()))
+            .join(", ");
+        let values = columns
+            .into_iter()
+            .map(|(column, value)| (column.to_lowercase(), value))
+            .collect::<Vec<_>>();
+
+        format!("INSERT INTO LEX_LOTS ({names}) VALUES ({placeholders})")
+            .with(Params::from(values))
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.last_insert_id().unwrap_or_default() as isize)
+    }
+
+    async fn update_lot(&self, lot: &Lot) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        let exists: Option<isize> = "SELECT LOTID FROM LEX_LOTS WHERE LOTID = :lotid"
+            .with(params! {
+                "lotid" => lot.lotid,
+            })
+            .first(&mut conn)
+            .await?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+
+        update_lot_query(lot).ignore(&mut conn).await?;
+
+        Ok(true)
+    }
+
+    async fn record_view(
+        &self,
+        lotid: isize,
+        viewer: &str,
+        now: chrono::DateTime<chrono::Utc>,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<bool> {
+        let mut conn = self.conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        // one statement, so that concurrent first views can't both be counted. MySQL reports
+        // 1 affected row for a new viewer, 2 for a repeat view that's counted again and 0 for
+        // one that isn't
+        "INSERT INTO LEX_LOTVIEWS (LOTID, VIEWER, LASTVIEW) VALUES (:lotid, :viewer, :now)
+            ON DUPLICATE KEY UPDATE LASTVIEW = IF(LASTVIEW < :since, :now, LASTVIEW)"
+            .with(params! {
+                lotid,
+                viewer,
+                "now" => lex_timestamp(now),
+                "since" => lex_timestamp(since),
+            })
+            .ignore(&mut tx)
+            .await?;
+        if tx.affected_rows() == 0 {
+            return Ok(false);
+        }
+
+        "UPDATE LEX_LOTS SET LOTVIEWS = LOTVIEWS + 1, LASTVIEW = :today WHERE LOTID = :lotid"
+            .with(params! {
+                "today" => lex_date(now),
+                lotid,
+            })
+            .ignore(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    async fn lot_locks(&self, lotid: isize) -> Result<Vec<LotLock>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_LOTLOCKS WHERE LOTID = :lotid"
+            .with(params! {
+                lotid,
+            })
+            .map(&mut conn, |row: Row| LotLock::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn set_lot_lock(
+        &self,
+        lotid: isize,
+        kind: LockKind,
+        lock: Option<&LotLock>,
+    ) -> Result<Option<bool>> {
+        let mut conn = self.conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        let column = kind.column();
+        let locked: Option<String> =
+            format!("SELECT {column} FROM LEX_LOTS WHERE LOTID = :lotid FOR UPDATE")
+                .with(params! {
+                    lotid,
+                })
+                .first(&mut tx)
+                .await?;
+        let Some(locked) = locked else {
+            return Ok(None);
+        };
+        let reason: Option<Vec<u8>> =
+            "SELECT REASON FROM LEX_LOTLOCKS WHERE LOTID = :lotid AND KIND = :kind"
+                .with(params! {
+                    lotid,
+                    "kind" => kind.flag(),
+                })
+                .first(&mut tx)
+                .await?;
+        let unchanged = match lock {
+            Some(lock) => {
+                parse_flag(&locked)?
+                    && reason.is_some_and(|reason| latin1_to_string(&reason) == lock.reason)
+            }
+            None => !parse_flag(&locked)?,
+        };
+        if unchanged {
+            return Ok(Some(false));
+        }
+
+        format!("UPDATE LEX_LOTS SET {column} = :locked WHERE LOTID = :lotid")
+            .with(params! {
+                "locked" => flag(lock.is_some()),
+                lotid,
+            })
+            .ignore(&mut tx)
+            .await?;
+        match lock {
+            Some(lock) => {
+                "REPLACE INTO LEX_LOTLOCKS (LOTID, KIND, USRID, REASON, DATEON) VALUES (:lotid, :kind, :usrid, :reason, :dateon)"
+                    .with(params! {
+                        lotid,
+                        "kind" => kind.flag(),
+                        "usrid" => lock.usrid,
+                        "reason" => string_to_latin1(&lock.reason),
+                        "dateon" => lock.date.map(lex_timestamp).unwrap_or_default(),
+                    })
+                    .ignore(&mut tx)
+                    .await?
+            }
+            None => {
+                "DELETE FROM LEX_LOTLOCKS WHERE LOTID = :lotid AND KIND = :kind"
+                    .with(params! {
+                        lotid,
+                        "kind" => kind.flag(),
+                    })
+                    .ignore(&mut tx)
+                    .await?
+            }
+        }
+
+        tx.commit().await?;
+
+        Ok(Some(true))
+    }
+
+    async fn revisions(&self, lotid: isize) -> Result<Vec<Revision>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_LOTREVISIONS WHERE LOTID = :lotid ORDER BY REVID DESC"
+            .with(params! {
+                lotid,
+            })
+            .map(&mut conn, |row: Row| Revision::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn update_lot_version(
+        &self,
+        lotid: isize,
+        update: &(dyn for<'a> Fn(&'a mut Lot) -> Result<Revision> + Send + Sync),
+    ) -> Result<Option<Revision>> {
+        let mut conn = self.conn().await?;
+        let mut tx = conn.start_transaction(TxOpts::default()).await?;
+
+        // locking the lot row keeps concurrent changes from being lost, and concurrent updates
+        // from both recording the previous version
+        let row: Option<Row> = "SELECT * FROM LEX_LOTS WHERE LOTID = :lotid FOR UPDATE"
+            .with(params! {
+                lotid,
+            })
+            .first(&mut tx)
+            .await?;
+        let Some(mut lot) = row.map(Lot::try_from).transpose()? else {
+            return Ok(None);
+        };
+        let previous = Lot::current_revision(&lot);
+        let revision = update(&mut lot)?;
+
+        "INSERT INTO LEX_LOTREVISIONS (LOTID, USRID, VERSION, LOTFILE, CHANGELOG, DATEON)
+            SELECT :lotid, :usrid, :version, :lotfile, :changelog, :dateon FROM DUAL
+            WHERE NOT EXISTS (SELECT 1 FROM LEX_LOTREVISIONS WHERE LOTID = :lotid)"
+            .with(revision_params(&previous))
+            .ignore(&mut tx)
+            .await?;
+        update_lot_query(&lot).ignore(&mut tx).await?;
+        "INSERT INTO LEX_LOTREVISIONS (LOTID, USRID, VERSION, LOTFILE, CHANGELOG, DATEON) VALUES (:lotid, :usrid, :version, :lotfile, :changelog, :dateon)"
+            .with(revision_params(&revision))
+            .ignore(&mut tx)
+            .await?;
+        let revid = tx.last_insert_id().unwrap_or_default() as usize;
+
+        tx.commit().await?;
+
+        Ok(Some(Revision { revid, ..revision }))
+    }
+
+    async fn votes(&self, lotid: isize) -> Result<Vec<Vote>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT USRID, RATING FROM LEX_VOTES WHERE LOTID = :lotid AND ISACTIVE = 'T' AND RATETYPE = 'U'"
+            .with(params! {
+                lotid,
+            })
+            .map(&mut conn, |(usrid, rating)| Vote { usrid, rating })
+            .await?)
+    }
+
+    async fn comments(&self, lotid: isize) -> Result<Vec<Comment>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT LC.COMMID, LU.USRNAME, LC.LOTCOMMENT, LC.DATEON
+            FROM LEX_COMMENTS LC INNER JOIN LEX_USERS LU ON (LC.USRID = LU.USRID)
+            WHERE LC.LOTID = :lotid AND LC.ISACTIVE = 'T'
+            ORDER BY LC.DATEON, LC.COMMID"
+            .with(params! {
+                lotid,
+            })
+            .map(&mut conn, |row: Row| Comment::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn new_comments(
+        &self,
+        usrid: usize,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<NewComment>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT LL.LOTID, LL.LOTNAME, LU.USRNAME, LC.DATEON
+            FROM LEX_COMMENTS LC
+                INNER JOIN LEX_LOTS LL ON (LC.LOTID = LL.LOTID)
+                INNER JOIN LEX_USERS LU ON (LC.USRID = LU.USRID)
+            WHERE LL.USRID = :usrid AND LC.USRID <> :usrid AND LC.ISACTIVE = 'T' AND LC.DATEON >= :since
+            ORDER BY LC.DATEON DESC"
+            .with(params! {
+                usrid,
+                // comment dates are compared by day, as lot dates are, whether they hold the
+                // time or not
+                "since" => lex_date(since),
+            })
+            .map(&mut conn, |row: Row| NewComment::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn download_track(&self, usrid: usize, lotid: isize) -> Result<Option<DownloadTrack>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_DOWNLOADTRACK WHERE LOTID = :lotid AND USRID = :usrid AND ISACTIVE = 'T'"
+            .with(params! {
+                lotid,
+                usrid,
+            })
+            .map(&mut conn, |row: Row| DownloadTrack::try_from(row))
+            .await?
+            .into_iter()
+            .next()
+            .transpose()
+    }
+
+    async fn record_download(
+        &self,
+        usrid: usize,
+        lot: &Lot,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut conn = self.conn().await?;
+        let today = lex_date(now);
+        let now = lex_timestamp(now);
+
+        let record: Option<usize> =
+            "SELECT DLRECID FROM LEX_DOWNLOADTRACK WHERE LOTID = :lotid AND USRID = :usrid"
+                .with(params! {
+                    "lotid" => lot.lotid,
+                    usrid,
+                })
+                .first(&mut conn)
+                .await?;
+
+        match record {
+            Some(dlrecid) => {
+                "UPDATE LEX_DOWNLOADTRACK SET LASTDL = :now, DLCOUNT = DLCOUNT + 1, VERSION = :version, ISACTIVE = 'T' WHERE DLRECID = :dlrecid"
+                    .with(params! {
+                        "now" => &now,
+                        "version" => lot.version.trim(),
+                        dlrecid,
+                    })
+                    .ignore(&mut conn)
+                    .await?
+            }
+            None => {
+                "INSERT INTO LEX_DOWNLOADTRACK (LOTID, USRID, LASTDL, DLCOUNT, VERSION, ISACTIVE) VALUES (:lotid, :usrid, :now, 1, :version, 'T')"
+                    .with(params! {
+                        "lotid" => lot.lotid,
+                        usrid,
+                        "now" => &now,
+                        "version" => lot.version.trim(),
+                    })
+                    .ignore(&mut conn)
+                    .await?
+            }
+        }
+
+        "UPDATE LEX_LOTS SET LOTDOWNLOADS = LOTDOWNLOADS + 1, LASTDOWNLOAD = :today WHERE LOTID = :lotid"
+            .with(params! {
+                today,
+                "lotid" => lot.lotid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn downloads(
+        &self,
+        usrid: usize,
+        filter: DownloadFilter,
+        params: &DownloadParams,
+    ) -> Result<Vec<DownloadRecord>> {
+        let mut conn = self.conn().await?;
+
+        let condition = match filter {
+            DownloadFilter::History => "DT.DLCOUNT >= 1",
+            DownloadFilter::List => {
+                "DT.DLCOUNT = 0 AND LL.ISACTIVE = 'T' AND LL.ADMLOCK = 'F' AND LL.USRLOCK = 'F'"
+            }
+            DownloadFilter::Outdated => OUTDATED_DOWNLOADS,
+        };
+        let order_by = params.order_by.unwrap_or(DownloadOrder::Update).column();
+        let order = match params.order {
+            Some(SortOrder::Desc) => "DESC",
+            _ => "ASC",
+        };
+        let limit = format!(
+            "LIMIT {}, {}",
+            params.start.unwrap_or(0),
+            params.amount.unwrap_or(100)
+        );
+
+        format!(
+            "SELECT DT.DLRECID, DT.LASTDL, DT.DLCOUNT, DT.VERSION AS DLVERSION,
+                LL.LOTID, LL.LOTNAME, LL.LASTUPDATE, LL.VERSION, LU.USRNAME
+            FROM LEX_DOWNLOADTRACK DT
+                INNER JOIN LEX_LOTS LL ON (DT.LOTID = LL.LOTID)
+                INNER JOIN LEX_USERS LU ON (LL.USRID = LU.USRID)
+            WHERE DT.ISACTIVE = 'T' AND DT.USRID = :usrid AND {condition}
+            ORDER BY {order_by} {order}, DT.DLRECID {order}
+            {limit}"
+        )
+        .with(params! {
+            usrid,
+        })
+        .map(&mut conn, |row: Row| DownloadRecord::try_from(row))
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    async fn broad_categories(&self) -> Result<Vec<BroadCategory>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT MAXCNT, MAXISCAT, LOTIMG FROM LEX_MAXISTYPES WHERE ISACTIVE = 'T' ORDER BY MAXISCAT"
+            .with(())
+            .map(&mut conn, |(id, name, image)| BroadCategory { id, name, image })
+            .await?)
+    }
+
+    async fn lex_categories(&self) -> Result<Vec<LexCategory>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT CATID, CATNAME FROM LEX_CATAGORIES WHERE ISACTIVE = 'T' ORDER BY CATNAME"
+            .with(())
+            .map(&mut conn, |(id, name)| LexCategory { id, name })
+            .await?)
+    }
+
+    async fn lex_types(&self) -> Result<Vec<LexType>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT TYPEID, TYPENAME, TYPEDESC FROM LEX_TYPES WHERE ISACTIVE = 'T' ORDER BY TYPENAME"
+            .with(())
+            .map(&mut conn, |(id, name, description)| LexType {
+                id,
+                name,
+                description,
+            })
+            .await?)
+    }
+
+    async fn groups(&self) -> Result<Vec<Group>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT LEX_GROUPS.GROUPID, LEX_GROUPS.NAME, LEX_USERS.USRNAME
+            FROM LEX_GROUPS INNER JOIN LEX_USERS ON LEX_GROUPS.AUTHOR = LEX_USERS.USRID
+            WHERE LEX_GROUPS.ISACTIVE = 'T' ORDER BY NAME"
+            .with(())
+            .map(&mut conn, |(id, name, author)| Group { id, name, author })
+            .await?)
+    }
+
+    async fn authors(&self) -> Result<Vec<Author>> {
+        let mut conn = self.conn().await?;
+
+        Ok("SELECT USRID, USRNAME FROM LEX_USERS WHERE AUTHOR='T' ORDER BY USRNAME ASC"
+            .with(())
+            .map(&mut conn, |(id, name)| Author { id, name })
+            .await?)
+    }
+
+    async fn ip_bans(&self) -> Result<Vec<IpBan>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_IPBANS ORDER BY BANID"
+            .with(())
+            .map(&mut conn, |row: Row| IpBan::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn ip_bans_for(
+        &self,
+        ip: std::net::IpAddr,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<IpBan>> {
+        let mut conn = self.conn().await?;
+
+        // exact addresses and legacy patterns are matched here; ranges and IPv6 addresses,
+        // which may be written in more than one way, are left to `BanPattern::matches`
+        "SELECT * FROM LEX_IPBANS
+            WHERE (EXPIRES IS NULL OR EXPIRES = '' OR EXPIRES > :now)
+            AND (:ip LIKE IPADDR OR IPADDR LIKE '%/%' OR IPADDR LIKE '%:%')
+            ORDER BY BANID"
+            .with(params! {
+                "ip" => ip.to_string(),
+                "now" => lex_timestamp(now),
+            })
+            .map(&mut conn, |row: Row| IpBan::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn insert_ip_ban(
+        &self,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<usize> {
+        let mut conn = self.conn().await?;
+
+        "INSERT INTO LEX_IPBANS (IPADDR, REASON, DATEON, EXPIRES) VALUES (:ipaddr, :reason, :now, :expires)"
+            .with(params! {
+                "ipaddr" => pattern.to_string(),
+                reason,
+                "now" => lex_timestamp(now),
+                "expires" => expires.map(lex_timestamp),
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.last_insert_id().unwrap_or_default() as usize)
+    }
+
+    async fn update_ip_ban(
+        &self,
+        banid: usize,
+        pattern: &BanPattern,
+        reason: Option<String>,
+        expires: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        let exists: Option<usize> = "SELECT BANID FROM LEX_IPBANS WHERE BANID = :banid"
+            .with(params! {
+                banid,
+            })
+            .first(&mut conn)
+            .await?;
+        if exists.is_none() {
+            return Ok(false);
+        }
+
+        "UPDATE LEX_IPBANS SET IPADDR = :ipaddr, REASON = :reason, EXPIRES = :expires WHERE BANID = :banid"
+            .with(params! {
+                "ipaddr" => pattern.to_string(),
+                reason,
+                "expires" => expires.map(lex_timestamp),
+                banid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn delete_ip_ban(&self, banid: usize) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        "DELETE FROM LEX_IPBANS WHERE BANID = :banid"
+            .with(params! {
+                banid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn digest_recipients(
+        &self,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<DigestRecipient>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT LU.USRID, LU.USRNAME, LU.EMAILADDDR, LU.LOCALE, NP.FREQUENCY, NP.LASTSENT, NP.UNSUBTOKEN
+            FROM LEX_USERS LU LEFT JOIN LEX_NOTIFYPREFS NP ON (LU.USRID = NP.USRID)
+            WHERE LU.ISACTIVE = 'T' AND COALESCE(NP.FREQUENCY, 'W') <> 'N'
+                AND (NP.LASTSENT IS NULL
+                    OR (NP.FREQUENCY = 'D' AND NP.LASTSENT <= :day_ago)
+                    OR (COALESCE(NP.FREQUENCY, 'W') = 'W' AND NP.LASTSENT <= :week_ago))"
+            .with(params! {
+                "day_ago" => lex_timestamp(now - chrono::Duration::days(1)),
+                "week_ago" => lex_timestamp(now - chrono::Duration::days(7)),
+            })
+            .map(&mut conn, |row: Row| DigestRecipient::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn digest_frequency(&self, usrid: usize) -> Result<Option<DigestFrequency>> {
+        let mut conn = self.conn().await?;
+
+        let frequency: Option<String> = "SELECT FREQUENCY FROM LEX_NOTIFYPREFS WHERE USRID = :usrid"
+            .with(params! {
+                usrid,
+            })
+            .first(&mut conn)
+            .await?;
+
+        frequency.as_deref().map(str::parse).transpose()
+    }
+
+    async fn notify_token(
+        &self,
+        usrid: usize,
+        frequency: DigestFrequency,
+        token: &str,
+    ) -> Result<String> {
+        let mut conn = self.conn().await?;
+
+        "INSERT INTO LEX_NOTIFYPREFS (USRID, FREQUENCY, UNSUBTOKEN) VALUES (:usrid, :frequency, :token)
+            ON DUPLICATE KEY UPDATE UNSUBTOKEN = COALESCE(UNSUBTOKEN, VALUES(UNSUBTOKEN))"
+            .with(params! {
+                usrid,
+                "frequency" => frequency.flag(),
+                token,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        let token: Option<String> = "SELECT UNSUBTOKEN FROM LEX_NOTIFYPREFS WHERE USRID = :usrid"
+            .with(params! {
+                usrid,
+            })
+            .first(&mut conn)
+            .await?;
+
+        token.ok_or(Error::NotFound)
+    }
+
+    async fn set_digest_frequency(&self, usrid: usize, frequency: DigestFrequency) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_NOTIFYPREFS SET FREQUENCY = :frequency WHERE USRID = :usrid"
+            .with(params! {
+                "frequency" => frequency.flag(),
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_digest_sent(&self, usrid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_NOTIFYPREFS SET LASTSENT = :now WHERE USRID = :usrid"
+            .with(params! {
+                "now" => lex_timestamp(now),
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn unsubscribe(&self, token: &str) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        let usrid: Option<usize> = "SELECT USRID FROM LEX_NOTIFYPREFS WHERE UNSUBTOKEN = :token"
+            .with(params! {
+                token,
+            })
+            .first(&mut conn)
+            .await?;
+        let Some(usrid) = usrid else {
+            return Ok(false);
+        };
+
+        "UPDATE LEX_NOTIFYPREFS SET FREQUENCY = 'N' WHERE USRID = :usrid"
+            .with(params! {
+                usrid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(true)
+    }
+
+    async fn enqueue_mail(
+        &self,
+        to_email: &str,
+        email: &RenderedEmail,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "INSERT INTO LEX_MAILQUEUE (TOEMAIL, SUBJECT, HTML, TEXT, STATUS, ATTEMPTS, NEXTATTEMPT, DATEON)
+            VALUES (:to_email, :subject, :html, :text, 'P', 0, :now, :now)"
+            .with(params! {
+                to_email,
+                "subject" => &email.subject,
+                "html" => &email.html,
+                "text" => &email.text,
+                "now" => lex_timestamp(now),
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn due_mail(&self, now: chrono::DateTime<chrono::Utc>, limit: usize) -> Result<Vec<QueuedMail>> {
+        let mut conn = self.conn().await?;
+
+        format!(
+            "SELECT * FROM LEX_MAILQUEUE WHERE STATUS = 'P' AND NEXTATTEMPT <= :now ORDER BY NEXTATTEMPT LIMIT {limit}"
+        )
+        .with(params! {
+            "now" => lex_timestamp(now),
+        })
+        .map(&mut conn, |row: Row| QueuedMail::try_from(row))
+        .await?
+        .into_iter()
+        .collect()
+    }
+
+    async fn set_mail_sent(&self, mailid: usize) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_MAILQUEUE SET STATUS = 'S', ATTEMPTS = ATTEMPTS + 1, LASTERROR = NULL WHERE MAILID = :mailid"
+            .with(params! {
+                mailid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn set_mail_failed(
+        &self,
+        mailid: usize,
+        status: MailStatus,
+        attempts: usize,
+        error: String,
+        next_attempt: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_MAILQUEUE SET STATUS = :status, ATTEMPTS = :attempts, LASTERROR = :error, NEXTATTEMPT = :next
+            WHERE MAILID = :mailid"
+            .with(params! {
+                "status" => status.flag(),
+                attempts,
+                error,
+                "next" => lex_timestamp(next_attempt),
+                mailid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn mail_by_status(&self, status: MailStatus) -> Result<Vec<QueuedMail>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_MAILQUEUE WHERE STATUS = :status ORDER BY MAILID DESC"
+            .with(params! {
+                "status" => status.flag(),
+            })
+            .map(&mut conn, |row: Row| QueuedMail::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+
+    async fn retry_mail(&self, mailid: usize, now: chrono::DateTime<chrono::Utc>) -> Result<bool> {
+        let mut conn = self.conn().await?;
+
+        "UPDATE LEX_MAILQUEUE SET STATUS = 'P', ATTEMPTS = 0, NEXTATTEMPT = :now WHERE MAILID = :mailid AND STATUS = 'F'"
+            .with(params! {
+                "now" => lex_timestamp(now),
+                mailid,
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.affected_rows() > 0)
+    }
+
+    async fn purge_failed_mail(&self) -> Result<u64> {
+        let mut conn = self.conn().await?;
+
+        "DELETE FROM LEX_MAILQUEUE WHERE STATUS = 'F'"
+            .with(())
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(conn.affected_rows())
+    }
+
+    async fn insert_audit(
+        &self,
+        usrid: usize,
+        action: &str,
+        target: AuditTarget,
+        details: &str,
+        now: chrono::DateTime<chrono::Utc>,
+    ) -> Result<()> {
+        let mut conn = self.conn().await?;
+
+        "INSERT INTO LEX_AUDITLOG (USRID, ACTION, TARGETTYPE, TARGETID, DETAILS, DATEON)
+            VALUES (:usrid, :action, :target_type, :target_id, :details, :now)"
+            .with(params! {
+                usrid,
+                action,
+                "target_type" => target.kind(),
+                "target_id" => target.id(),
+                details,
+                "now" => lex_timestamp(now),
+            })
+            .ignore(&mut conn)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn audit_entries(&self, target: AuditTarget) -> Result<Vec<AuditEntry>> {
+        let mut conn = self.conn().await?;
+
+        "SELECT * FROM LEX_AUDITLOG
+            WHERE TARGETTYPE = :target_type AND TARGETID = :target_id
+            ORDER BY AUDITID"
+            .with(params! {
+                "target_type" => target.kind(),
+                "target_id" => target.id(),
+            })
+            .map(&mut conn, |row: Row| AuditEntry::try_from(row))
+            .await?
+            .into_iter()
+            .collect()
+    }
+}
@@ -1,52 +1,334 @@
 use crate::*;
 use base64::Engine;
-use mysql_async::{
-    params,
-    prelude::{FromValue, Query, WithParams},
-    Row,
-};
+use mysql_async::{Row, Value};
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserStatus {
+    Active,
+    Inactive,
+    Pending,
+}
+impl std::str::FromStr for UserStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "T" => Ok(UserStatus::Active),
+            "F" => Ok(UserStatus::Inactive),
+            "P" => Ok(UserStatus::Pending),
+            other => Err(Error::MalformedRow(format!("invalid user status `{other}`"))),
+        }
+    }
+}
+impl UserStatus {
+    pub fn flag(self) -> &'static str {
+        match self {
+            UserStatus::Active => "T",
+            UserStatus::Inactive => "F",
+            UserStatus::Pending => "P",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserRole {
+    Donator,
+    Rater,
+    Uploader,
+    Author,
+    Admin,
+}
+impl UserRole {
+    pub fn column(self) -> &'static str {
+        match self {
+            UserRole::Donator => "DONATOR",
+            UserRole::Rater => "RATER",
+            UserRole::Uploader => "UPLOADER",
+            UserRole::Author => "AUTHOR",
+            UserRole::Admin => "ISADMIN",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UserOrder {
+    Id,
+    Username,
+    Email,
+    Status,
+    Registered,
+    LastLogin,
+    LastIp,
+    Level,
+    Donator,
+    Rater,
+    Uploader,
+    Author,
+    IsAdmin,
+}
+impl UserOrder {
+    pub fn column(self) -> &'static str {
+        match self {
+            UserOrder::Id => "USRID",
+            UserOrder::Username => "USRNAME",
+            UserOrder::Email => "EMAILADDDR",
+            UserOrder::Status => "ISACTIVE",
+            UserOrder::Registered => "DATEON",
+            UserOrder::LastLogin => "LASTLOGIN",
+            UserOrder::LastIp => "LASTIP",
+            UserOrder::Level => "USRLVL",
+            UserOrder::Donator => UserRole::Donator.column(),
+            UserOrder::Rater => UserRole::Rater.column(),
+            UserOrder::Uploader => UserRole::Uploader.column(),
+            UserOrder::Author => UserRole::Author.column(),
+            UserOrder::IsAdmin => UserRole::Admin.column(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearchParams {
+    pub rows_offset: Option<u64>,
+    #[validate(range(min = 1, max = 500))]
+    pub rows_count: Option<u64>,
+    pub concise: Option<bool>,
+    //  filtering params
+    #[validate(length(max = 30))]
+    pub username: Option<String>,
+    #[validate(length(max = 100))]
+    pub email: Option<String>,
+    pub status: Option<UserStatus>,
+    pub donator: Option<bool>,
+    pub rater: Option<bool>,
+    pub uploader: Option<bool>,
+    pub author: Option<bool>,
+    pub is_admin: Option<bool>,
+    pub registered_from: Option<chrono::NaiveDate>,
+    pub registered_to: Option<chrono::NaiveDate>,
+    #[validate(length(max = 45))]
+    pub last_ip: Option<String>,
+    pub order_by: Option<UserOrder>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StatusParams {
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RoleParams {
+    pub role: UserRole,
+    pub value: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LevelParams {
+    #[validate(range(max = 255))]
+    pub usrlvl: usize,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RegisterParams {
+    #[validate(length(min = 2, max = 30), regex(path = *crate::validate::USERNAME))]
+    pub username: String,
+    #[validate(length(min = 6, max = 64))]
+    pub password_1: String,
+    #[validate(must_match(other = "password_1", message = "must match password_1"))]
+    pub password_2: String,
+    #[validate(email, length(max = 100))]
+    pub email: String,
+    #[validate(length(max = 100))]
+    pub fullname: String,
+    #[validate(regex(path = *crate::validate::LOCALE))]
+    pub locale: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ActivateParams {
+    #[validate(length(min = 1, max = 256))]
+    pub activation_key: String,
+}
+
+/// A user as submitted for registration, before they have an id.
+#[derive(Debug, Clone)]
+pub struct NewUser {
+    pub username: String,
+    /// Hex md5 of the password.
+    pub password: String,
+    pub email: String,
+    pub fullname: String,
+    pub locale: Option<String>,
+    /// Address the user registered from.
+    pub ip: String,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
-pub(crate) struct LEXUser {
-    pub(crate) usrid: usize,
-    pub(crate) email_address: String,
-    pub(crate) username: String,
-    pub(crate) full_name: String,
-    pub(crate) login_count: usize,
-    pub(crate) registered: chrono::NaiveDateTime,
-    pub(crate) last_login: chrono::NaiveDateTime,
-    pub(crate) is_active: bool,
-    pub(crate) usrlvl: usize,
-    pub(crate) donator: bool,
-    pub(crate) rater: bool,
-    pub(crate) uploader: bool,
-    pub(crate) author: bool,
-    pub(crate) is_admin: bool,
+pub struct LEXUser {
+    pub usrid: usize,
+    pub email_address: String,
+    pub username: String,
+    pub full_name: String,
+    pub login_count: usize,
+    pub registered: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_login: Option<chrono::DateTime<chrono::Utc>>,
+    pub status: UserStatus,
+    pub usrlvl: usize,
+    pub donator: bool,
+    pub rater: bool,
+    pub uploader: bool,
+    pub author: bool,
+    pub is_admin: bool,
 }
-impl LEXUser {
-    pub(crate) fn new(row: Row) -> Self {
-        let isactive: String = FromValue::from_value(row["isactive"].clone());
-        let usrlvl: String = FromValue::from_value(row["usrlvl"].clone());
-        LEXUser {
-            usrid: FromValue::from_value(row["usrid"].clone()),
-            email_address: FromValue::from_value(row["fullname"].clone()),
-            username: FromValue::from_value(row["usrname"].clone()),
-            full_name: FromValue::from_value(row["dateon"].clone()),
-            login_count: FromValue::from_value(row["lastlogin"].clone()),
-            registered: chrono::NaiveDateTime::parse_from_str(&isactive, "%Y%m%d").unwrap(),
-            last_login: chrono::NaiveDateTime::parse_from_str(&usrlvl, "%Y%m%d%H%M%S").unwrap(),
-            is_active: FromValue::from_value(row["emailadddr"].clone()),
-            usrlvl: FromValue::from_value(row["logincnt"].clone()),
-            donator: FromValue::from_value(row["donator"].clone()),
-            rater: FromValue::from_value(row["rater"].clone()),
-            uploader: FromValue::from_value(row["uploader"].clone()),
-            author: FromValue::from_value(row["author"].clone()),
-            is_admin: FromValue::from_value(row["isadmin"].clone()),
+/// A user's profile, as returned to clients.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct UserProfile {
+    pub id: usize,
+    pub fullname: String,
+    pub username: String,
+    pub registered: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_login: Option<chrono::DateTime<chrono::Utc>>,
+    pub is_active: bool,
+    pub status: UserStatus,
+    pub user_level: usize,
+    pub email: String,
+    pub login_count: usize,
+    pub is_donator: bool,
+    pub is_rater: bool,
+    pub is_uploader: bool,
+    pub is_author: bool,
+    pub is_admin: bool,
+}
+impl From<LEXUser> for UserProfile {
+    fn from(user: LEXUser) -> Self {
+        UserProfile {
+            id: user.usrid,
+            fullname: user.full_name,
+            username: user.username,
+            registered: user.registered,
+            last_login: user.last_login,
+            is_active: user.status == UserStatus::Active,
+            status: user.status,
+            user_level: user.usrlvl,
+            email: user.email_address,
+            login_count: user.login_count,
+            is_donator: user.donator,
+            is_rater: user.rater,
+            is_uploader: user.uploader,
+            is_author: user.author,
+            is_admin: user.is_admin,
+        }
+    }
+}
+
+/// The v1 shape of a profile, from before registration, last login and status were returned.
+#[derive(Debug, Clone, Serialize)]
+pub struct LegacyUserProfile {
+    pub id: usize,
+    pub fullname: String,
+    pub username: String,
+    pub is_active: bool,
+    pub user_level: usize,
+    pub email: String,
+    pub login_count: usize,
+    pub is_donator: bool,
+    pub is_rater: bool,
+    pub is_uploader: bool,
+    pub is_author: bool,
+    pub is_admin: bool,
+}
+impl From<LEXUser> for LegacyUserProfile {
+    fn from(user: LEXUser) -> Self {
+        LegacyUserProfile {
+            id: user.usrid,
+            fullname: user.full_name,
+            username: user.username,
+            is_active: user.status == UserStatus::Active,
+            user_level: user.usrlvl,
+            email: user.email_address,
+            login_count: user.login_count,
+            is_donator: user.donator,
+            is_rater: user.rater,
+            is_uploader: user.uploader,
+            is_author: user.author,
+            is_admin: user.is_admin,
         }
     }
-    pub(crate) async fn check_register(
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct UserSummary {
+    pub id: usize,
+    pub username: String,
+}
+impl From<LEXUser> for UserSummary {
+    fn from(user: LEXUser) -> Self {
+        UserSummary {
+            id: user.usrid,
+            username: user.username,
+        }
+    }
+}
+
+/// One page of a user search, with the number of users matching it.
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct UserList<T> {
+    pub total: usize,
+    pub users: Vec<T>,
+}
+
+impl TryFrom<Row> for LEXUser {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let dateon: Option<String> = take_column(&row, "dateon")?;
+        let lastlogin: Option<String> = take_column(&row, "lastlogin")?;
+        let isactive: String = take_column(&row, "isactive")?;
+        let flag = |column: &str| -> Result<bool> {
+            parse_flag(&take_column::<String>(&row, column)?)
+        };
+
+        Ok(LEXUser {
+            usrid: take_column(&row, "usrid")?,
+            email_address: take_column(&row, "emailadddr")?,
+            username: take_column(&row, "usrname")?,
+            full_name: take_column::<Option<String>>(&row, "fullname")?.unwrap_or_default(),
+            login_count: take_column::<Option<usize>>(&row, "logincnt")?.unwrap_or_default(),
+            registered: parse_lex_date(&dateon.unwrap_or_default())?,
+            last_login: parse_lex_date(&lastlogin.unwrap_or_default())?,
+            status: isactive.parse()?,
+            usrlvl: take_column::<Option<usize>>(&row, "usrlvl")?.unwrap_or_default(),
+            donator: flag("donator")?,
+            rater: flag("rater")?,
+            uploader: flag("uploader")?,
+            author: flag("author")?,
+            is_admin: flag("isadmin")?,
+        })
+    }
+}
+impl LEXUser {
+    pub async fn fetch(config: &Config, usrid: usize) -> Result<Self> {
+        config.storage.user(usrid).await?.ok_or(Error::NotFound)
+    }
+    pub async fn check_register(
         username: String,
         password_1: String,
         password_2: String,
@@ -55,47 +337,38 @@ impl LEXUser {
         config: Arc<Config>,
         ip: String,
     ) -> Result<bool> {
-        let mut conn = config.connect_db().await?;
-
-        if password_1 == password_2 {
-            let user =
-                "SELECT * FROM LEX_USERS WHERE UPPER(USRNAME) = :tun OR UPPER(EMAILADDDR) = :tem"
-                    .with(params! {
-                        "tun" => &username.to_uppercase(),
-                        "tem" => &email.to_uppercase(),
-                    })
-                    .map(&mut conn, LEXUser::new)
-                    .await?;
-
-            if user.len() == 1 {
-                let ban_list = "SELECT * FROM LEX_IPBANS WHERE REGIP LIKE :ip1 OR LASTIP LIKE :ip2"
-                    .with(params! {
-                        "ip1" => &ip,
-                        "ip2" => ip,
-                    })
-                    .map(&mut conn, LEXUser::new)
-                    .await?;
-                // if user is in the ban list return forbidden
-                if ban_list.len() == 1 {
-                    return Err(Error::Forbidden);
-                } else {
-                    return Ok(true);
-                }
-            }
+        if password_1 != password_2 {
+            return Err(Error::MalformedRequest);
+        }
+
+        // if user is in the ban list return forbidden
+        if IpBan::is_banned(config.clone(), &ip).await? {
+            return Err(Error::Forbidden);
         }
-        Err(Error::Forbidden)
+
+        if config.storage.user_exists(&username, &email).await? {
+            return Err(Error::Conflict(
+                "That username or email address is already registered.".into(),
+            ));
+        }
+
+        Ok(true)
     }
-    pub(crate) async fn register_user(
+    #[allow(clippy::too_many_arguments)]
+    pub async fn register_user(
         username: String,
         password_1: String,
         password_2: String,
         email: String,
         fullname: String,
+        locale: Option<String>,
         config: Arc<Config>,
         ip: String,
     ) -> Result<impl warp::Reply> {
-        let hashed_password = md5::compute(&password_1);
-        let mut conn = config.connect_db().await?;
+        let hashed_password = format!("{:x}", md5::compute(&password_1));
+
+        // reject undeliverable addresses before the user row is written
+        email.parse::<lettre::Address>()?;
 
         LEXUser::check_register(
             username.clone(),
@@ -108,358 +381,506 @@ impl LEXUser {
         )
         .await?;
 
-        "INSERT INTO LEX_USERS (FULLNAME,USRNAME,USRPASS,DATE ON,EMAILADDDR,ISACTIVE,REGIP)
-            VALUES (:fullname, :username, :pass, :now, :email, 'P', :regip)"
-            .with(params! {
-                "fullname" => &fullname,
-                "username" => &username,
-                "pass" => String::from_utf8_lossy(&hashed_password.to_vec()).to_string(),
-                "now" => chrono::Utc::now().date_naive().to_string(),
-                "email" => &email,
-                "regip" => &ip,
-            })
-            .ignore(&mut conn)
+        config
+            .storage
+            .insert_user(
+                NewUser {
+                    username: username.clone(),
+                    password: hashed_password.clone(),
+                    email: email.clone(),
+                    fullname,
+                    locale: locale.clone(),
+                    ip,
+                },
+                chrono::Utc::now(),
+            )
             .await?;
 
-        crate::email::Email::send_registration(
-            config,
-            email,
-            username,
-            String::from_utf8_lossy(&hashed_password.to_vec()).to_string(),
-        )
-        .await?;
+        crate::email::Email::send_registration(config, email, username, hashed_password, locale)
+            .await?;
 
         Ok(warp::reply())
     }
-    pub(crate) async fn activate_user(
+    pub async fn activate_user(
         config: Arc<Config>,
         activation_key: String,
     ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-
+        // older emails used the standard alphabet, whose `+` arrives as a space in a query string
+        let activation_key = activation_key
+            .trim_end_matches('=')
+            .replace(['+', ' '], "-")
+            .replace('/', "_");
         let decoded = String::from_utf8_lossy(
-            &base64::engine::general_purpose::STANDARD.decode(activation_key)?,
+            &base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(activation_key)?,
         )
         .to_string();
-        let key = decoded.split(':').collect::<Vec<_>>();
-
-        let username = key[0];
-        let hash = key[1];
-
-        let test = "SELECT * FROM LEX_USERS WHERE UPPER(USRNAME) = :username AND USRPASS = :hash AND ISACTIVE = 'P'"
-            .with(params! {
-                username,
-                hash,
-            })
-            .map(&mut conn, LEXUser::new)
-            .await?;
-
-        if test.len() == 1 {
-            "UPDATE LEX_USERS SET ISACTIVE = 'T' WHERE UPPER(USRNAME) = :username AND USRPASS = :hash AND ISACTIVE = 'P'"
-            .with(params! {
-                username,
-                hash,
-            })
-            .ignore(&mut conn)
-            .await?;
+        let (username, hash) = decoded.split_once(':').ok_or(Error::MalformedRequest)?;
 
+        if config.storage.activate_user(username, hash).await? {
             Ok(warp::reply())
         } else {
             Err(Error::Forbidden)
         }
     }
-    pub(crate) async fn get_user(
+    pub async fn get_user(
         config: Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
         usrid: Option<usize>,
-    ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-
+        version: ApiVersion,
+    ) -> Result<warp::reply::Json> {
         let id = if let Some(id) = usrid {
             id
         } else {
-            Base::get_auth(config, username, password, ip).await?
+            Base::get_auth(config.clone(), username, password, ip).await?
         };
 
-        let user = "SELECT * FROM LEX_USERS WHERE USRID = :usrid"
-            .with(params! {
-                "usrid" => id,
+        let user = LEXUser::fetch(&config, id).await?;
+
+        Ok(if version == ApiVersion::V1 {
+            warp::reply::json(&LegacyUserProfile::from(user))
+        } else {
+            warp::reply::json(&UserProfile::from(user))
+        })
+    }
+
+    pub async fn adm_get_user(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        usrid: usize,
+        version: ApiVersion,
+    ) -> Result<warp::reply::Json> {
+        Base::get_admin(config.clone(), username.clone(), password, ip.clone()).await?;
+
+        LEXUser::get_user(config, username, password, ip, Some(usrid), version).await
+    }
+
+    pub fn build_filter(params: &UserSearchParams) -> (String, Vec<(String, Value)>) {
+        let mut clauses = Vec::new();
+        let mut values: Vec<(String, Value)> = Vec::new();
+
+        if let Some(username) = &params.username {
+            clauses.push("UPPER(USRNAME) LIKE :username".to_string());
+            values.push(("username".into(), like_pattern(username).into()));
+        }
+        if let Some(email) = &params.email {
+            clauses.push("UPPER(EMAILADDDR) LIKE :email".to_string());
+            values.push(("email".into(), like_pattern(email).into()));
+        }
+        if let Some(last_ip) = &params.last_ip {
+            clauses.push("LASTIP LIKE :last_ip".to_string());
+            values.push(("last_ip".into(), format!("{}%", escape_like(last_ip)).into()));
+        }
+        if let Some(status) = params.status {
+            clauses.push("ISACTIVE = :isactive".to_string());
+            values.push(("isactive".into(), status.flag().into()));
+        }
+        for (role, value) in [
+            (UserRole::Donator, params.donator),
+            (UserRole::Rater, params.rater),
+            (UserRole::Uploader, params.uploader),
+            (UserRole::Author, params.author),
+            (UserRole::Admin, params.is_admin),
+        ] {
+            if let Some(value) = value {
+                let column = role.column();
+                clauses.push(format!("{column} = :{}", column.to_lowercase()));
+                values.push((column.to_lowercase(), if value { "T" } else { "F" }.into()));
+            }
+        }
+        if let Some(from) = params.registered_from {
+            clauses.push("LEFT(DATEON, 8) >= :registered_from".to_string());
+            values.push(("registered_from".into(), from.format("%Y%m%d").to_string().into()));
+        }
+        if let Some(to) = params.registered_to {
+            clauses.push("LEFT(DATEON, 8) <= :registered_to".to_string());
+            values.push(("registered_to".into(), to.format("%Y%m%d").to_string().into()));
+        }
+
+        let clause = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+
+        (clause, values)
+    }
+
+    pub async fn adm_get_all(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        params: UserSearchParams,
+        version: ApiVersion,
+    ) -> Result<warp::reply::Json> {
+        Base::get_admin(config.clone(), username, password, ip).await?;
+
+        let (total, users) = config.storage.search_users(&params).await?;
+
+        Ok(if params.concise.unwrap_or(false) {
+            warp::reply::json(&UserList {
+                total,
+                users: users.into_iter().map(UserSummary::from).collect(),
             })
-            .map(&mut conn, LEXUser::new)
-            .await?
-            .first()
-            .unwrap()
-            .clone();
-
-        let response = serde_json::json!({
-            "id": user.usrid,
-            "fullname": user.full_name,
-            "username": user.username,
-            // "registered": user.registered,
-            // "last_login": user.last_login,
-            "is_active": user.is_active,
-            "user_level": user.usrlvl,
-            "email": user.email_address,
-            "login_count": user.login_count,
-            "is_donator": user.donator,
-            "is_rater": user.rater,
-            "is_uploader": user.uploader,
-            "is_author": user.author,
-            "is_admin": user.is_admin,
-        });
-
-        Ok(warp::reply::json(&response))
+        } else if version == ApiVersion::V1 {
+            // v1 clients read the stored field names
+            warp::reply::json(&UserList { total, users })
+        } else {
+            warp::reply::json(&UserList {
+                total,
+                users: users.into_iter().map(UserProfile::from).collect(),
+            })
+        })
     }
 
-    pub(crate) async fn adm_get_user(
+    #[allow(clippy::too_many_arguments)]
+    pub async fn adm_set_active(
         config: Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
         usrid: usize,
+        active: bool,
+        version: ApiVersion,
     ) -> Result<impl warp::Reply> {
-        let id = Base::get_auth(config.clone(), username.clone(), password, ip.clone()).await?;
+        let id = Base::get_admin(config.clone(), username.clone(), password, ip.clone()).await?;
+        let user = LEXUser::fetch(&config, usrid).await?;
 
-        if Base::is_admin(config.clone(), id).await? {
-            Ok(LEXUser::get_user(config, username, password, ip, Some(usrid)).await?)
+        let status = if active {
+            UserStatus::Active
         } else {
-            Err(Error::Forbidden)
-        }
+            UserStatus::Inactive
+        };
+        config.storage.set_user_status(usrid, status).await?;
+
+        Base::audit(
+            config.clone(),
+            id,
+            if active { "user_activate" } else { "user_deactivate" },
+            AuditTarget::User(usrid),
+            format!("{} -> {}", user.status.flag(), status.flag()),
+        )
+        .await?;
+
+        LEXUser::get_user(config, username, password, ip, Some(usrid), version).await
     }
 
     #[allow(clippy::too_many_arguments)]
-    pub(crate) async fn adm_get_all(
+    pub async fn adm_set_role(
         config: Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
-        rows_offset: u64,
-        rows_count: u64,
-        concise: bool,
+        usrid: usize,
+        role: UserRole,
+        value: bool,
+        version: ApiVersion,
     ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-        let id = Base::get_auth(config.clone(), username.clone(), password, ip.clone()).await?;
-
-        if Base::is_admin(config.clone(), id).await? {
-            let users: Vec<serde_json::Value> = if concise {
-                format!("SELECT USRID, USRNAME FROM LEX_USERS LIMIT {rows_offset}, {rows_count}")
-                    .with(params! {
-                        "usrid" => id,
-                    })
-                    .map(&mut conn, |(usrid, usrname): (String, String)| {
-                        serde_json::json!({
-                            "id" : usrid,
-                            "username" : usrname,
-                        })
-                    })
-                    .await?
-            } else {
-                format!("SELECT * FROM LEX_USERS LIMIT {rows_offset}, {rows_count}")
-                    .with(params! {
-                        "usrid" => id,
-                    })
-                    .map(&mut conn, LEXUser::new)
-                    .await?
-                    .into_iter()
-                    .map(|usr| serde_json::to_value(usr).unwrap())
-                    .collect()
-            };
-
-            Ok(warp::reply::json(&users))
-        } else {
-            Err(Error::Forbidden)
+        let id = Base::get_admin(config.clone(), username.clone(), password, ip.clone()).await?;
+        LEXUser::fetch(&config, usrid).await?;
+
+        // an admin can't revoke their own admin rights and lock everyone out
+        if role == UserRole::Admin && !value && usrid == id {
+            return Err(Error::Forbidden);
         }
+
+        config.storage.set_user_role(usrid, role, value).await?;
+
+        Base::audit(
+            config.clone(),
+            id,
+            "user_set_role",
+            AuditTarget::User(usrid),
+            format!("{} = {}", role.column(), if value { "T" } else { "F" }),
+        )
+        .await?;
+
+        LEXUser::get_user(config, username, password, ip, Some(usrid), version).await
+    }
+
+    pub async fn adm_set_level(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        usrid: usize,
+        usrlvl: usize,
+        version: ApiVersion,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_admin(config.clone(), username.clone(), password, ip.clone()).await?;
+        let user = LEXUser::fetch(&config, usrid).await?;
+
+        config.storage.set_user_level(usrid, usrlvl).await?;
+
+        Base::audit(
+            config.clone(),
+            id,
+            "user_set_level",
+            AuditTarget::User(usrid),
+            format!("{} -> {usrlvl}", user.usrlvl),
+        )
+        .await?;
+
+        LEXUser::get_user(config, username, password, ip, Some(usrid), version).await
     }
 
-    pub(crate) async fn get_download_history(
+    /// Deactivates the user and adds their registration and last known IPs to `LEX_IPBANS`.
+    pub async fn adm_ban(
         config: Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
+        usrid: usize,
+        version: ApiVersion,
     ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-        let id = Base::get_auth(config.clone(), username.clone(), password, ip.clone()).await?;
-
-        let history =
-            "SELECT DT.LASTDL, DT.DLRECID, DT.USRID, DT.DLCOUNT, DT.LOTID, DT.VERSION, LL.LASTUPDATE
-            FROM LEX_DOWNLOADTRACK DT INNER JOIN LEX_LOTS LL ON (DT.LOTID = LL.LOTID)
-            WHERE DT.ISACTIVE = 'T' AND DT.USRID = :usrid AND DT.DLCOUNT >= 1
-            ORDER BY LL.LASTUPDATE"
-                .with(params! {
-                    "usrid" => id,
-                })
-                .map(&mut conn, |
-                        (lastdl, dlrecid, usrid, dlcount, lotid, version, lastupdate):
-                            (String, String, String, String, String, String, String)
-                    |
-                    serde_json::json!({
-                        "lastdl": lastdl,
-                        "dlrecid": dlrecid,
-                        "usrid": usrid,
-                        "dlcount": dlcount,
-                        "lotid": lotid,
-                        "version": version,
-                        "lastupdate": lastupdate,
-                    })
-                ).await?;
-
-        let mut items = Vec::new();
-        for item in history {
-            let lot = "SELECT * FROM LEX_LOTS WHERE LOTID = :lotid"
-                .with(params! {
-                    "lotid" => item["lotid"].clone(),
-                })
-                .map(
-                    &mut conn,
-                    |(usrid, lotname, lastupdate, version, usrname): (
-                        String,
-                        String,
-                        String,
-                        String,
-                        String,
-                    )| {
-                        serde_json::json!({
-                            "usrid": usrid,
-                            "lotname": lotname,
-                            "lastupdate": lastupdate,
-                            "version": version,
-                            "usrname": usrname
-                        })
-                    },
+        let id = Base::get_admin(config.clone(), username.clone(), password, ip.clone()).await?;
+        if usrid == id {
+            return Err(Error::Forbidden);
+        }
+
+        LEXUser::fetch(&config, usrid).await?;
+
+        let ips = config
+            .storage
+            .user_ips(usrid)
+            .await?
+            .into_iter()
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty())
+            .unique()
+            .collect::<Vec<_>>();
+
+        // legacy rows can hold anything, so skip those rather than ban only some addresses
+        let patterns = ips
+            .iter()
+            .filter_map(|ip| match ip.parse::<BanPattern>() {
+                Ok(pattern) => Some(pattern),
+                Err(_) => {
+                    log::warn!("Not banning `{ip}` of user {usrid}: not an address");
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for pattern in &patterns {
+            config
+                .storage
+                .insert_ip_ban(
+                    pattern,
+                    Some(format!("Ban of user {usrid}")),
+                    None,
+                    chrono::Utc::now(),
                 )
-                .await?
-                .first()
-                .unwrap()
-                .clone();
-
-            let author = "SELECT USRNAME FROM LEX_USERS WHERE USRID = :usrid"
-                .with(params! {
-                    "usrid" => lot["usrid"].clone(),
-                })
-                .map(&mut conn, |usrname: String| {
-                    serde_json::json!({
-                        "username": usrname,
-                    })
-                })
-                .await?
-                .first()
-                .unwrap()
-                .clone();
-
-            items.push(serde_json::json!({
-                "lot": {
-                    "id": lot["lotid"].clone(),
-                    "name": lot["lotname"].clone(),
-                    "update_date": lot["lastupdate"].clone(),
-                    "version": lot["version"].clone(),
-                    "author" : author["usrname"].clone(),
-                },
-                "record": {
-                    "id": item["dlrecid"].clone(),
-                    "last_downloaded": item["lastdl"].clone(),
-                    "last_version": item["version"].clone(),
-                    "download_count": item["dlcount"].clone(),
-                },
-            }));
+                .await?;
         }
 
+        config
+            .storage
+            .set_user_status(usrid, UserStatus::Inactive)
+            .await?;
+
+        Base::audit(
+            config.clone(),
+            id,
+            "user_ban",
+            AuditTarget::User(usrid),
+            format!("banned ips: {}", patterns.iter().join(", ")),
+        )
+        .await?;
+
+        LEXUser::get_user(config, username, password, ip, Some(usrid), version).await
+    }
+
+    pub async fn get_download_history(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        params: DownloadParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let items = config
+            .storage
+            .downloads(id, DownloadFilter::History, &params)
+            .await?;
+
         Ok(warp::reply::json(&items))
     }
 
-    pub(crate) async fn get_download_list(
+    pub async fn get_download_list(
         config: Arc<Config>,
         username: String,
         password: md5::Digest,
         ip: String,
+        params: DownloadParams,
     ) -> Result<impl warp::Reply> {
-        let mut conn = config.connect_db().await?;
-        let id = Base::get_auth(config.clone(), username.clone(), password, ip.clone()).await?;
-
-        let history =
-            "SELECT DT.LASTDL,DT.DLRECID,DT.USRID,DT.DLCOUNT,DT.LOTID,DT.VERSION,LL.LASTUPDATE
-            FROM LEX_DOWNLOADTRACK DT INNER JOIN LEX_LOTS LL ON (DT.LOTID = LL.LOTID)
-            WHERE DT.ISACTIVE = 'T' AND DT.USRID = :usrid AND DT.DLCOUNT = 0 AND LL.ISACTIVE = 'T' AND LL.ADMLOCK = 'F' AND LL.USRLOCK = 'F'
-            ORDER BY LL.LASTUPDATE"
-                .with(params! {
-                    "usrid" => id,
-                })
-                .map(&mut conn, |
-                        (lastdl, dlrecid, usrid, dlcount, lotid, version, lastupdate):
-                            (String, String, String, String, String, String, String)
-                    |
-                    serde_json::json!({
-                        "lastdl": lastdl,
-                        "dlrecid": dlrecid,
-                        "usrid": usrid,
-                        "dlcount": dlcount,
-                        "lotid": lotid,
-                        "version": version,
-                        "lastupdate": lastupdate,
-                    })
-                ).await?;
-
-        let mut items = Vec::new();
-        for item in history {
-            let lot = "SELECT * FROM LEX_LOTS WHERE LOTID = :lotid"
-                .with(params! {
-                    "lotid" => item["lotid"].clone(),
-                })
-                .map(
-                    &mut conn,
-                    |(usrid, lotname, lastupdate, version, usrname): (
-                        String,
-                        String,
-                        String,
-                        String,
-                        String,
-                    )| {
-                        serde_json::json!({
-                            "usrid": usrid,
-                            "lotname": lotname,
-                            "lastupdate": lastupdate,
-                            "version": version,
-                            "usrname": usrname
-                        })
-                    },
-                )
-                .await?
-                .first()
-                .unwrap()
-                .clone();
-
-            let author = "SELECT USRNAME FROM LEX_USERS WHERE USRID = :usrid"
-                .with(params! {
-                    "usrid" => lot["usrid"].clone(),
-                })
-                .map(&mut conn, |usrname: String| {
-                    serde_json::json!({
-                        "username": usrname,
-                    })
-                })
-                .await?
-                .first()
-                .unwrap()
-                .clone();
-
-            items.push(serde_json::json!({
-                "lot": {
-                    "id": lot["lotid"].clone(),
-                    "name": lot["lotname"].clone(),
-                    "update_date": lot["lastupdate"].clone(),
-                    "version": lot["version"].clone(),
-                    "author" : author["usrname"].clone(),
-                },
-                "record": {
-                    "id": item["dlrecid"].clone(),
-                    "last_downloaded": item["lastdl"].clone(),
-                    "last_version": item["version"].clone(),
-                    "download_count": item["dlcount"].clone(),
-                },
-            }));
-        }
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let items = config
+            .storage
+            .downloads(id, DownloadFilter::List, &params)
+            .await?;
 
         Ok(warp::reply::json(&items))
     }
+
+    /// Lists downloaded lots that have been updated since the user last downloaded them.
+    pub async fn get_updates(
+        config: Arc<Config>,
+        username: String,
+        password: md5::Digest,
+        ip: String,
+        params: DownloadParams,
+    ) -> Result<impl warp::Reply> {
+        let id = Base::get_auth(config.clone(), username, password, ip).await?;
+        let updates = config
+            .storage
+            .downloads(id, DownloadFilter::Outdated, &params)
+            .await?
+            .into_iter()
+            .map(AvailableUpdate::from)
+            .collect::<Vec<_>>();
+
+        Ok(warp::reply::json(&updates))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct AvailableUpdate {
+    pub id: usize,
+    pub name: String,
+    pub author: String,
+    pub old_version: String,
+    pub new_version: String,
+    pub last_downloaded: Option<chrono::DateTime<chrono::Utc>>,
+    pub update_date: Option<chrono::DateTime<chrono::Utc>>,
+}
+impl From<DownloadRecord> for AvailableUpdate {
+    fn from(record: DownloadRecord) -> Self {
+        AvailableUpdate {
+            id: record.lot.id,
+            name: record.lot.name,
+            author: record.lot.author,
+            old_version: record.record.last_version,
+            new_version: record.lot.version,
+            last_downloaded: record.record.last_downloaded,
+            update_date: record.lot.update_date,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadOrder {
+    Id,
+    Name,
+    Update,
+    Downloaded,
+    DownloadCount,
+}
+impl DownloadOrder {
+    pub fn column(self) -> &'static str {
+        match self {
+            DownloadOrder::Id => "LL.LOTID",
+            DownloadOrder::Name => "LL.LOTNAME",
+            DownloadOrder::Update => "LL.LASTUPDATE",
+            DownloadOrder::Downloaded => "DT.LASTDL",
+            DownloadOrder::DownloadCount => "DT.DLCOUNT",
+        }
+    }
+}
+
+/// Which of a user's download records to list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadFilter {
+    /// Lots the user has downloaded.
+    History,
+    /// Lots on the user's download list that they haven't downloaded yet.
+    List,
+    /// Downloaded lots that have been updated since.
+    Outdated,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize, validator::Validate, utoipa::IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadParams {
+    pub start: Option<u64>,
+    #[validate(range(min = 1, max = 500))]
+    pub amount: Option<u64>,
+    pub order_by: Option<DownloadOrder>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DownloadRecord {
+    pub lot: DownloadedLot,
+    pub record: DownloadTrack,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DownloadedLot {
+    pub id: usize,
+    pub name: String,
+    pub update_date: Option<chrono::DateTime<chrono::Utc>>,
+    pub version: String,
+    pub author: String,
+}
+
+#[derive(Debug, Clone, Serialize, utoipa::ToSchema)]
+pub struct DownloadTrack {
+    pub id: usize,
+    pub last_downloaded: Option<chrono::DateTime<chrono::Utc>>,
+    pub last_version: String,
+    pub download_count: usize,
+}
+
+impl TryFrom<Row> for DownloadRecord {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let lastupdate: Option<String> = take_column(&row, "lastupdate")?;
+        let lastdl: Option<String> = take_column(&row, "lastdl")?;
+
+        Ok(DownloadRecord {
+            lot: DownloadedLot {
+                id: take_column(&row, "lotid")?,
+                name: take_column::<String>(&row, "lotname")?.trim().to_string(),
+                update_date: parse_lex_date(&lastupdate.unwrap_or_default())?,
+                version: take_column::<Option<String>>(&row, "version")?
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                author: take_column(&row, "usrname")?,
+            },
+            record: DownloadTrack {
+                id: take_column(&row, "dlrecid")?,
+                last_downloaded: parse_lex_date(&lastdl.unwrap_or_default())?,
+                last_version: take_column::<Option<String>>(&row, "dlversion")?
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string(),
+                download_count: take_column(&row, "dlcount")?,
+            },
+        })
+    }
+}
+impl TryFrom<Row> for DownloadTrack {
+    type Error = Error;
+
+    fn try_from(row: Row) -> Result<Self> {
+        let lastdl: Option<String> = take_column(&row, "lastdl")?;
+
+        Ok(DownloadTrack {
+            id: take_column(&row, "dlrecid")?,
+            last_downloaded: parse_lex_date(&lastdl.unwrap_or_default())?,
+            last_version: take_column::<Option<String>>(&row, "version")?
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            download_count: take_column(&row, "dlcount")?,
+        })
+    }
 }
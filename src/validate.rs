@@ -0,0 +1,119 @@
+use crate::*;
+use serde::{de::DeserializeOwned, Serialize};
+use validator::{Validate, ValidationError, ValidationErrors, ValidationErrorsKind};
+
+/// Usernames as the PHP exchange accepted them.
+pub(crate) static USERNAME: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r"^[A-Za-z0-9_.\- ]+$").unwrap());
+/// Locale tags such as `en`, `de-DE` or `pt_BR`.
+pub(crate) static LOCALE: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r"^[A-Za-z]{2,3}([-_][A-Za-z0-9]{2,8})*$").unwrap());
+/// Category image file names, which are interpolated into search queries.
+pub(crate) static IMAGE_NAME: std::sync::LazyLock<regex::Regex> =
+    std::sync::LazyLock::new(|| regex::Regex::new(r"^[A-Za-z0-9_.\-]+$").unwrap());
+
+/// A problem with a single request field.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+/// Rejection for a query string that can't be parsed into, or fails validation of, its params struct.
+#[derive(Debug)]
+pub(crate) struct InvalidParams(pub(crate) Vec<FieldError>);
+impl warp::reject::Reject for InvalidParams {}
+
+/// Drop-in replacement for `warp::query()` that also runs the struct's validation rules,
+/// rejecting with every problem found.
+pub(crate) fn query<T>() -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone
+where
+    T: DeserializeOwned + Validate + Send + 'static,
+{
+    warp::query::raw()
+        .or(warp::any().map(String::new))
+        .unify()
+        .and_then(|raw: String| async move {
+            parse_query::<T>(&raw).map_err(|e| warp::reject::custom(InvalidParams(e)))
+        })
+}
+
+pub(crate) fn parse_query<T: DeserializeOwned + Validate>(
+    raw: &str,
+) -> std::result::Result<T, Vec<FieldError>> {
+    let deserializer =
+        serde_urlencoded::Deserializer::new(form_urlencoded::parse(raw.as_bytes()));
+
+    let params: T = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+        let field = e.path().to_string();
+        let message = e.inner().to_string();
+
+        // missing fields are reported against the struct, so pull the name out of the message
+        let field = match (field.as_str(), message.strip_prefix("missing field `")) {
+            (".", Some(rest)) => rest.trim_end_matches('`').to_string(),
+            _ => field,
+        };
+
+        vec![FieldError {
+            message: format!("{field}: {message}"),
+            field,
+        }]
+    })?;
+
+    params.validate().map_err(|e| field_errors(&e))?;
+
+    Ok(params)
+}
+
+pub(crate) fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields = errors
+        .errors()
+        .iter()
+        .flat_map(|(field, kind)| match kind {
+            ValidationErrorsKind::Field(errors) => errors
+                .iter()
+                .map(|e| FieldError {
+                    field: field.to_string(),
+                    message: describe(field, e),
+                })
+                .collect(),
+            ValidationErrorsKind::Struct(errors) => field_errors(errors),
+            ValidationErrorsKind::List(errors) => {
+                errors.values().flat_map(|e| field_errors(e)).collect()
+            }
+        })
+        .collect::<Vec<_>>();
+    fields.sort_by(|a, b| a.field.cmp(&b.field));
+
+    fields
+}
+
+/// Turns a validation failure into a sentence such as "rows_count must be ≤ 500".
+fn describe(field: &str, error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return format!("{field} {message}");
+    }
+
+    let param = |name: &str| error.params.get(name).map(|v| v.to_string());
+
+    match error.code.as_ref() {
+        "range" => match (param("min"), param("max")) {
+            (Some(min), Some(max)) => format!("{field} must be between {min} and {max}"),
+            (Some(min), None) => format!("{field} must be ≥ {min}"),
+            (None, Some(max)) => format!("{field} must be ≤ {max}"),
+            (None, None) => format!("{field} is out of range"),
+        },
+        "length" => match (param("equal"), param("min"), param("max")) {
+            (Some(equal), _, _) => format!("{field} must be exactly {equal} characters long"),
+            (None, Some(min), Some(max)) => {
+                format!("{field} must be between {min} and {max} characters long")
+            }
+            (None, Some(min), None) => format!("{field} must be at least {min} characters long"),
+            (None, None, Some(max)) => format!("{field} must be at most {max} characters long"),
+            (None, None, None) => format!("{field} has an invalid length"),
+        },
+        "email" => format!("{field} must be a valid email address"),
+        "regex" => format!("{field} contains invalid characters"),
+        _ => format!("{field} is invalid"),
+    }
+}
@@ -0,0 +1,810 @@
+//! End-to-end tests, driving the same routes the server serves against the in-memory backend.
+
+use sc4d_lex_api::apiversion::ApiVersion;
+use sc4d_lex_api::base::AuditTarget;
+use sc4d_lex_api::notify::{DigestFrequency, Notify};
+use sc4d_lex_api::{Config, MemoryStorage};
+use std::sync::Arc;
+
+use base64::Engine;
+use warp::http::StatusCode;
+
+const TEST_CONFIG: &str = r#"
+db_arch = "memory"
+ext_file_dir = "http://lex.test/files/"
+index_link = "http://lex.test/"
+img_link = "http://lex.test/images/"
+cat_link = "http://lex.test/category_images/"
+email_orig = "lex@lex.test"
+mail_transport = "memory"
+"#;
+const FIXTURES: &str = include_str!("../fixtures/lex.json");
+const CLIENT: &str = "127.0.0.1:50000";
+/// Inside the range banned by the fixtures.
+const BANNED_CLIENT: &str = "203.0.113.7:50000";
+
+/// A config over the fixtures, storing uploads in a directory removed once the test is done.
+fn test_config() -> (Arc<Config>, tempfile::TempDir) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().display();
+    let toml = format!("{TEST_CONFIG}int_file_dir = \"{path}/files/\"\nint_img_dir = \"{path}/images/\"\n");
+
+    let mut config = Config::from_toml("test config", &toml).unwrap();
+    config.storage = Arc::new(MemoryStorage::from_json(FIXTURES).unwrap());
+
+    (Arc::new(config), dir)
+}
+
+fn basic(username: &str, password: &str) -> String {
+    let credentials = base64::engine::general_purpose::STANDARD.encode(format!("{username}:{password}"));
+    format!("Basic {credentials}")
+}
+
+fn request(method: &str, path: &str) -> warp::test::RequestBuilder {
+    warp::test::request()
+        .method(method)
+        .path(path)
+        .remote_addr(CLIENT.parse().unwrap())
+}
+
+async fn send(
+    config: &Arc<Config>,
+    request: warp::test::RequestBuilder,
+) -> (StatusCode, serde_json::Value) {
+    let response = request.reply(&sc4d_lex_api::build_routes(config.clone())).await;
+    let body = if response.body().is_empty() {
+        serde_json::Value::Null
+    } else {
+        serde_json::from_slice(response.body()).unwrap()
+    };
+
+    (response.status(), body)
+}
+
+/// A `multipart/form-data` request, with `(name, filename, contents)` parts.
+fn multipart(method: &str, path: &str, parts: &[(&str, Option<&str>, &[u8])]) -> warp::test::RequestBuilder {
+    const BOUNDARY: &str = "lex-test-boundary";
+
+    let mut body = Vec::new();
+    for (name, filename, contents) in parts {
+        body.extend_from_slice(format!("--{BOUNDARY}\r\n").as_bytes());
+        match filename {
+            Some(filename) => body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{name}\"; filename=\"{filename}\"\r\nContent-Type: application/octet-stream\r\n\r\n").as_bytes(),
+            ),
+            None => body.extend_from_slice(
+                format!("Content-Disposition: form-data; name=\"{name}\"\r\n\r\n").as_bytes(),
+            ),
+        }
+        body.extend_from_slice(contents);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+    request(method, path)
+        .header("content-type", format!("multipart/form-data; boundary={BOUNDARY}"))
+        .body(body)
+}
+
+#[tokio::test]
+async fn registration_to_download_history() {
+    let (config, _files) = test_config();
+
+    let (status, _) = send(
+        &config,
+        request(
+            "POST",
+            "/api/v2/user/register?username=newbie&password_1=hunter22&password_2=hunter22\
+             &email=newbie%40example.com&fullname=New%20Player",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // not active yet
+    let login = || request("GET", "/api/v2/user").header("authorization", basic("newbie", "hunter22"));
+    let (status, body) = send(&config, login()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["code"], "unauthorized");
+
+    // follow the link from the queued activation email
+    let mail = config.storage.due_mail(chrono::Utc::now(), 10).await.unwrap();
+    assert_eq!(mail.len(), 1);
+    assert_eq!(mail[0].to, "newbie@example.com");
+    let link = mail[0]
+        .text
+        .split_whitespace()
+        .find(|word| word.contains("/user/activate?"))
+        .expect("the email contains the activation link");
+    let path = link.strip_prefix("http://lex.test").unwrap();
+    let (status, _) = send(&config, request("GET", path)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(&config, login()).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["username"], "newbie");
+    assert_eq!(body["fullname"], "New Player");
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/search?concise=true&query=park")
+            .header("authorization", basic("newbie", "hunter22")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, serde_json::json!([{ "id": 1, "name": "Central Park" }]));
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/lot/1/download").header("authorization", basic("newbie", "hunter22")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["link"], "http://lex.test/files/Central_Park.zip");
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/user/download-history")
+            .header("authorization", basic("newbie", "hunter22")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let history = body.as_array().unwrap();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0]["lot"]["id"], 1);
+    assert_eq!(history[0]["lot"]["name"], "Central Park");
+}
+
+#[tokio::test]
+async fn registration_conflicts_with_existing_user() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(
+        &config,
+        request(
+            "POST",
+            "/api/v2/user/register?username=player&password_1=hunter22&password_2=hunter22\
+             &email=someone%40example.com&fullname=Someone",
+        ),
+    )
+    .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["code"], "conflict");
+}
+
+#[tokio::test]
+async fn search_hides_inactive_lots() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/search?concise=true&order_by=id&order=ASC")
+            .header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let ids = body
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|lot| lot["id"].as_i64().unwrap())
+        .collect::<Vec<_>>();
+    assert!(!ids.contains(&5));
+    assert!(ids.contains(&1));
+}
+
+#[tokio::test]
+async fn v1_keeps_legacy_shapes() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v1/search?concise=true&query=park")
+            .header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, serde_json::json!([{ "lotid": 1, "lotname": "Central Park" }]));
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v1/user/all?username=player").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"][0]["email_address"], "player@example.com");
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/user/all?username=player").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"][0]["email"], "player@example.com");
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/user/all?order_by=is_admin&order=desc").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"][0]["username"], "admin");
+
+    let (status, v1) = send(&config, request("GET", "/api/v1/user").header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, v2) = send(&config, request("GET", "/api/v2/user").header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(v1, v2);
+    assert_eq!(v1["email"], v2["email"]);
+    assert!(v1.get("registered").is_none() && v1.get("status").is_none());
+    assert!(v2.get("registered").is_some() && v2.get("status").is_some());
+
+    let (status, v1) = send(&config, request("GET", "/api/v1/lot/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v1["lotname"], "Central Park");
+    assert!(v1.get("name").is_none());
+    let (status, v2) = send(&config, request("GET", "/api/v2/lot/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(v2.get("lotname").is_none());
+
+    let (status, v1) = send(&config, request("GET", "/api/v1/lot/all")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v1[0], serde_json::json!({ "id": 1, "name": "Central Park" }));
+    let (status, v2) = send(&config, request("GET", "/api/v2/lot/all")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(v2["lots"], v1);
+}
+
+#[tokio::test]
+async fn lots_are_returned_without_their_internals() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], 1);
+    assert_eq!(body["name"], "Central Park");
+    assert_eq!(body["author"], "author");
+    assert_eq!(body["description"], "Central Park for SimCity 4.");
+    assert_eq!(body["images"]["primary"], "http://lex.test/images/1_day.jpg");
+    assert_eq!(body["is_active"], true);
+    assert!(body.get("lotdesc").is_none());
+    assert!(body.get("isactive").is_none());
+
+    let (status, _) = send(&config, request("GET", "/api/v2/lot/999")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn lot_views_are_counted_once_per_viewer() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/1?comments=true&votes=true")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["num_views"], 1);
+    assert_eq!(body["comments"].as_array().unwrap().len(), 2);
+    assert!(body["votes"].is_object());
+    assert!(body["categories"].is_null());
+
+    let (_, body) = send(&config, request("GET", "/api/v2/lot/1")).await;
+    assert_eq!(body["num_views"], 1);
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/1?dependents=true")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        body["dependents"],
+        serde_json::json!([{ "id": 2, "name": "Harbour Terminal", "status": "available" }])
+    );
+
+    let logins = config.storage.user(3).await.unwrap().unwrap().login_count;
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/lot/1?user=true").header("Authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["num_views"], 2);
+    // viewing a lot isn't a login
+    assert_eq!(config.storage.user(3).await.unwrap().unwrap().login_count, logins);
+    assert!(!body["last_downloaded"].is_null());
+
+    let (status, _) = send(&config, request("GET", "/api/v2/lot/1?user=true")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/5")).await;
+    assert_eq!(status, StatusCode::GONE);
+    assert_eq!(body["code"], "gone");
+}
+
+#[tokio::test]
+async fn uploaders_can_publish_lots() {
+    let (config, _files) = test_config();
+    let fields: [(&str, Option<&str>, &[u8]); 7] = [
+        ("name", None, b"Ferry Pier"),
+        ("version", None, b"1.0"),
+        ("description", None, b"A pier for the ferry."),
+        ("category", None, b"1"),
+        ("type", None, b"1"),
+        ("broad_category", None, b"2"),
+        ("dependencies", None, b"1, 2"),
+    ];
+    let upload = |file: (&'static str, Option<&'static str>, &'static [u8])| {
+        let mut parts = fields.to_vec();
+        parts.push(file);
+        parts.push(("image", Some("pier day.png"), b"\x89PNG\r\n\x1a\nday"));
+        multipart("POST", "/api/v2/lot", &parts)
+    };
+    let archive = ("file", Some("../Ferry Pier.zip"), &b"PK\x03\x04lot"[..]);
+
+    let (status, _) = send(&config, upload(archive).header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(
+        &config,
+        upload(("file", Some("Ferry Pier.zip"), b"not an archive")).header("authorization", basic("author", "author")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["details"]["fields"][0]["field"], "file");
+
+    let (status, body) = send(&config, upload(archive).header("authorization", basic("author", "author"))).await;
+    assert_eq!(status, StatusCode::OK);
+    let id = body["id"].as_i64().unwrap();
+
+    let lot = config.storage.lot(id as isize).await.unwrap().unwrap();
+    assert!(lot.lotfile.ends_with("_Ferry_Pier.zip"));
+    assert!(!lot.lotfile.contains('/'));
+    assert_eq!(lot.deps, "1$2");
+    assert_eq!(lot.maxiscat, "transport.gif");
+    let stored = std::fs::read(format!("{}{}", config.int_file_dir, lot.lotfile)).unwrap();
+    assert_eq!(stored, b"PK\x03\x04lot");
+
+    let (status, body) = send(&config, request("GET", &format!("/api/v2/lot/{id}"))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["name"], "Ferry Pier");
+    assert_eq!(body["author"], "author");
+    assert_eq!(body["is_certified"], false);
+    assert!(body["images"]["primary"].as_str().unwrap().ends_with("_pier_day.png"));
+}
+
+#[tokio::test]
+async fn lot_updates_keep_earlier_versions() {
+    let (config, _files) = test_config();
+    let author = || basic("author", "author");
+    let update = |version: &'static [u8]| {
+        multipart(
+            "PUT",
+            "/api/v2/lot/1",
+            &[
+                ("version", None, version),
+                ("changelog", None, b"Added more trees."),
+                ("file", Some("Central Park 2.zip"), b"PK\x03\x04v2"),
+            ],
+        )
+    };
+
+    let (status, _) = send(&config, update(b"2.0").header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // listing the versions of a lot without stored revisions doesn't store one
+    for _ in 0..2 {
+        let (status, versions) = send(&config, request("GET", "/api/v2/lot/1/versions")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(versions.as_array().unwrap().len(), 1);
+        assert_eq!(versions[0]["version"], "1.1");
+    }
+    assert!(config.storage.revisions(1).await.unwrap().is_empty());
+
+    let (status, body) = send(&config, update(b"1.1").header("authorization", author())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["details"]["fields"][0]["field"], "version");
+
+    let (status, body) = send(&config, update(b"2.0").header("authorization", author())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], "2.0");
+    assert_eq!(body["changelog"], "Added more trees.");
+
+    let (_, body) = send(&config, request("GET", "/api/v2/lot/1")).await;
+    assert_eq!(body["version"], "2.0");
+    assert!(body["update_date"].as_str().unwrap() > "2021-03-01");
+
+    let (status, versions) = send(&config, request("GET", "/api/v2/lot/1/versions")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(versions[0]["version"], "2.0");
+    assert_eq!(versions[0]["is_current"], true);
+    assert_eq!(versions.as_array().unwrap().len(), 2);
+    assert_eq!(versions[1]["version"], "1.1");
+    assert_eq!(versions[1]["is_downloadable"], false);
+
+    let old = format!("/api/v2/lot/1/versions/{}/download", versions[1]["id"]);
+    let (status, _) = send(&config, request("GET", &old).header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, body) = send(&config, request("GET", &old).header("authorization", author())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["link"], "http://lex.test/files/Central_Park.zip");
+
+    let allow = multipart(
+        "PUT",
+        "/api/v2/lot/1",
+        &[("version", None, b"2.0.1"), ("changelog", None, b"Fixed a typo."), ("allow_old_downloads", None, b"true")],
+    );
+    let (status, _) = send(&config, allow.header("authorization", author())).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&config, request("GET", &old).header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // locked lots can't be updated, by their author or an admin, and stay locked
+    let (status, _) = send(
+        &config,
+        request("PUT", "/api/v2/lot/1/admin-lock?locked=true&reason=Reported").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    for user in ["author", "admin"] {
+        let (status, _) = send(&config, update(b"3.0").header("authorization", basic(user, user))).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    let lot = config.storage.lot(1).await.unwrap().unwrap();
+    assert_eq!(lot.version, "2.0.1");
+    assert_eq!(lot.status, sc4d_lex_api::lot::LotStatus::AdminLocked);
+}
+
+#[tokio::test]
+async fn digests_include_changes_from_the_day_of_the_last_one() {
+    let (config, _files) = test_config();
+    let at = |date: &str| date.parse::<chrono::DateTime<chrono::Utc>>().unwrap();
+
+    // lot 1 was updated on 2021-03-01 and commented on at 10:00 on 2021-03-02, both before
+    // the day's digests went out in the evening
+    config.storage.set_digest_sent(3, at("2021-03-01T18:00:00Z")).await.unwrap();
+    config
+        .storage
+        .notify_token(2, DigestFrequency::Weekly, "fedcba9876543210fedcba9876543210")
+        .await
+        .unwrap();
+    config.storage.set_digest_sent(2, at("2021-03-02T18:00:00Z")).await.unwrap();
+
+    Notify::send_due_digests(config.clone()).await.unwrap();
+
+    let mail = config.storage.due_mail(chrono::Utc::now(), 10).await.unwrap();
+    let digest = |to: &str| mail.iter().find(|m| m.to.contains(to)).map(|m| m.text.clone());
+    assert!(digest("player@").unwrap().contains("Central Park by author: 1.0 -> 1.1"));
+    assert!(digest("author@").unwrap().contains("admin commented on Central Park"));
+}
+
+#[tokio::test]
+async fn lot_locks_reach_dependents_and_the_author() {
+    let (config, _files) = test_config();
+    let lock = |path: &str, user: &str| request("PUT", path).header("authorization", basic(user, user));
+    let dependency_status = || async {
+        let (_, body) = send(&config, request("GET", "/api/v2/lot/2/dependency")).await;
+        body[0]["status"].clone()
+    };
+
+    let (status, _) = send(&config, lock("/api/v2/lot/1/lock?locked=true&reason=Fixing", "player")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(&config, lock("/api/v2/lot/1/lock?locked=true", "author")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) =
+        send(&config, lock("/api/v2/lot/1/lock?locked=true&reason=Updating%20textures", "author")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "user_locked");
+    assert_eq!(dependency_status().await, "locked");
+
+    let (status, body) =
+        send(&config, lock("/api/v2/lot/1/admin-lock?locked=true&reason=Reported", "admin")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "admin_locked");
+
+    // requests that change nothing are neither audited nor mailed
+    let (status, body) =
+        send(&config, lock("/api/v2/lot/1/admin-lock?locked=true&reason=Reported", "admin")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "admin_locked");
+    assert_eq!(body["reason"], "Reported");
+
+    let (status, _) = send(&config, lock("/api/v2/lot/1/lock?locked=false", "author")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    // clearing the admin lock leaves the author's own lock in place
+    let (status, body) = send(&config, lock("/api/v2/lot/1/admin-lock?locked=false", "admin")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "user_locked");
+    assert_eq!(body["reason"], "Updating textures");
+
+    let (status, body) = send(&config, lock("/api/v2/lot/1/lock?locked=false", "author")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "active");
+    assert_eq!(dependency_status().await, "available");
+
+    for path in ["/api/v2/lot/1/lock?locked=false", "/api/v2/lot/1/admin-lock?locked=false"] {
+        let user = if path.contains("admin") { "admin" } else { "author" };
+        let (status, body) = send(&config, lock(path, user)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "active");
+    }
+
+    let (_, body) = send(&config, request("GET", "/api/v2/lot/2?dependencies=true")).await;
+    assert_eq!(body["dependencies"][0]["name"], "Central Park");
+
+    let mail = config.storage.due_mail(chrono::Utc::now(), 10).await.unwrap();
+    assert_eq!(mail.len(), 4);
+    assert!(mail.iter().all(|m| m.subject.starts_with("LEX: Central Park has been")));
+    assert!(mail[1].text.contains("Reason: Reported"));
+
+    let audit = config.storage.audit_entries(AuditTarget::Lot(1)).await.unwrap();
+    let actions = audit.iter().map(|e| (e.usrid, e.action.as_str())).collect::<Vec<_>>();
+    assert_eq!(
+        actions,
+        [(2, "lot_lock"), (1, "lot_admin_lock"), (1, "lot_admin_unlock"), (2, "lot_unlock")]
+    );
+    assert_eq!(audit[1].details, "Reported");
+}
+
+#[tokio::test]
+async fn lot_list_is_paginated_and_sorted() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/all?amount=2")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 3);
+    assert_eq!(
+        body["lots"],
+        serde_json::json!([{ "id": 1, "name": "Central Park" }, { "id": 2, "name": "Harbour Terminal" }])
+    );
+    assert_eq!(body["next"], 2);
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/all?amount=2&after=2")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["lots"], serde_json::json!([{ "id": 3, "name": "Corner Bakery" }]));
+    assert!(body["next"].is_null());
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/all?order_by=name&start=1&amount=1")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["lots"][0]["name"], "Corner Bakery");
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/all?order_by=updated&order=desc")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["lots"][0]["id"], 1);
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/all?order_by=name&after=1")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["details"]["fields"][0]["field"], "after");
+    assert_eq!(body["details"]["fields"][0]["message"], "after must only be used when ordering by id");
+}
+
+#[tokio::test]
+async fn lot_list_supports_conditional_requests() {
+    let (config, _files) = test_config();
+
+    let response = request("GET", "/api/v2/lot/all")
+        .reply(&sc4d_lex_api::build_routes(config.clone()))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let etag = response.headers()["etag"].to_str().unwrap().to_string();
+    let last_modified = response.headers()["last-modified"].to_str().unwrap().to_string();
+    // the newest update is on 1 March, which only holds the day
+    assert_eq!(last_modified, "Tue, 02 Mar 2021 00:00:00 GMT");
+
+    let (status, _) = send(&config, request("GET", "/api/v2/lot/all").header("if-none-match", &etag)).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/lot/all").header("if-modified-since", &last_modified),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/lot/all").header("if-modified-since", "Sun, 28 Feb 2021 09:00:00 GMT"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/lot/all").header("if-modified-since", "Mon, 01 Mar 2021 18:00:00 GMT"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(&config, request("GET", "/api/v2/lot/all").header("if-none-match", "\"stale\"")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/lot/all?amount=1").header("if-none-match", &etag),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    // a download changes the listed lot's counters, and so the tag
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/lot/1/download").header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&config, request("GET", "/api/v2/lot/all").header("if-none-match", &etag)).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn locked_lots_cannot_be_downloaded() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/lot/4/download").header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+}
+
+#[tokio::test]
+async fn admin_routes_need_an_admin() {
+    let (config, _files) = test_config();
+    let users = |auth: Option<(&str, &str)>| {
+        let request = request("GET", "/api/v2/user/all");
+        match auth {
+            Some((username, password)) => request.header("authorization", basic(username, password)),
+            None => request,
+        }
+    };
+
+    let (status, _) = send(&config, users(None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, _) = send(&config, users(Some(("admin", "wrong")))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let (status, body) = send(&config, users(Some(("player", "player")))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["code"], "forbidden");
+
+    let (status, body) = send(&config, users(Some(("admin", "admin")))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["total"], 4);
+
+    let (status, _) = send(
+        &config,
+        request("PUT", "/api/v2/user/3/status?active=false")
+            .header("authorization", basic("author", "author")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let (status, _) = send(
+        &config,
+        request("PUT", "/api/v2/user/3/status?active=false")
+            .header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let audit = config.storage.audit_entries(AuditTarget::User(3)).await.unwrap();
+    assert_eq!(audit[0].action, "user_deactivate");
+    assert_eq!(audit[0].details, "T -> F");
+
+    // deactivated users can no longer log in
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/user").header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn banned_addresses_are_forbidden() {
+    let (config, _files) = test_config();
+
+    let (status, _) = send(
+        &config,
+        request("GET", "/api/v2/user")
+            .remote_addr(BANNED_CLIENT.parse().unwrap())
+            .header("authorization", basic("player", "player")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn banning_a_user_bans_their_addresses() {
+    let (config, _files) = test_config();
+    let player = || request("GET", "/api/v2/user").remote_addr("198.51.100.9:50000".parse().unwrap());
+
+    let (status, _) = send(&config, player().header("authorization", basic("player", "player"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // the player's legacy registration address isn't one, so only their last address is banned
+    let (status, _) = send(
+        &config,
+        request("POST", "/api/v2/user/3/ban").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/ipban").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let patterns = body.as_array().unwrap().iter().map(|ban| ban["pattern"].clone()).collect::<Vec<_>>();
+    assert_eq!(patterns, ["203.0.113.0/24", "198.51.100.9"]);
+
+    let (status, _) = send(&config, player().header("authorization", basic("author", "author"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    let audit = config.storage.audit_entries(AuditTarget::User(3)).await.unwrap();
+    assert_eq!(audit.len(), 1);
+    assert_eq!((audit[0].usrid, audit[0].action.as_str()), (1, "user_ban"));
+    assert_eq!(audit[0].details, "banned ips: 198.51.100.9");
+}
+
+#[tokio::test]
+async fn errors_have_status_codes_and_bodies() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/no-such-route")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "not_found");
+    assert!(body["request_id"].is_string());
+
+    let (status, body) = send(&config, request("DELETE", "/api/v2/category/all")).await;
+    assert_eq!(status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(body["code"], "method_not_allowed");
+
+    let (status, body) = send(&config, request("GET", "/api/v2/lot/1/comment")).await;
+    assert_eq!(status, StatusCode::NOT_IMPLEMENTED);
+    assert_eq!(body["code"], "not_implemented");
+
+    let (status, body) = send(
+        &config,
+        request("GET", "/api/v2/user/all?rows_count=0").header("authorization", basic("admin", "admin")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "invalid_params");
+    assert_eq!(body["details"]["fields"][0]["field"], "rows_count");
+
+    let (status, body) = send(&config, request("GET", "/api/v2/user/activate?activation_key=%21%21")).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "malformed_request");
+}
+
+#[tokio::test]
+async fn categories_come_from_storage() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/category/all")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["group"][0]["name"], "Waterfront Set");
+    assert_eq!(body["lex_type"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn unversioned_paths_are_still_served() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/version")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["version"], ApiVersion::LEGACY.as_str());
+}
+
+#[tokio::test]
+async fn openapi_document_describes_the_responses() {
+    let (config, _files) = test_config();
+
+    let (status, body) = send(&config, request("GET", "/api/v2/openapi.json")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["components"]["schemas"]["LotView"].is_object());
+    assert!(body["components"]["schemas"]["UserProfile"].is_object());
+}